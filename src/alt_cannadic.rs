@@ -1,52 +1,89 @@
 
 // Ported from convert_alt_cannadic_to_mozcdic.rb file.
 
-use std::io::{BufWriter, Write};
+use std::io::Read;
 use std::fs::File;
+use std::path::PathBuf;
 
 use encoding_rs::*;
-use rayon::prelude::*;
 
-use super::mozc::get_id;
+use super::archive::*;
+use super::conjugation::{adjective_conjugation, Inflector};
+use super::entry::*;
+use super::error::{Result, SkippedLines, WithPath};
+use super::license::License;
+use super::pos::{pos_table, PosMap, PosTable};
+use super::fetch::Fetcher;
+use super::source::Source;
+use super::stats::SourceStats;
 use super::util::*;
 
 
-#[derive(Clone)]
-struct Entry {
-    key: String,
-    yomi: String,
-    hyouki: String,
-    cost: String,
-}
+const NAME: &str = "alt-cannadic";
+
+/// Parts of speech of alt-cannadic and their Mozc parts of speech.
+/// Others are 名詞,一般.
+const POS_RULES: &[(&str, &str)] = &[
+    ("#JN", "名詞,固有名詞,人名,一般"),
+    ("#JNS", "名詞,固有名詞,人名,姓"),
+    ("#JNM", "名詞,固有名詞,人名,名"),
+    ("#KK", "名詞,固有名詞,組織"),
+    ("#CN", "名詞,固有名詞,地域,一般"),
+    ("#CNS", "名詞,固有名詞,地域,一般"),
+    ("#T30", "名詞,サ変接続"),
+];
 
-fn convert_alt_cannadic_to_mozcdic(filename: &str, dicname: &str) -> std::io::Result<()> {
-    // Mozcの品詞IDを取得
-    // 「名詞,固有名詞,人名,一般,*,*」は優先度が低いので使わない。
-    // 「名詞,固有名詞,一般,*,*,*」は後でフィルタリングする。
-    let id = get_id(r"(\d*) 名詞,一般,\*,\*,\*,\*,\*")?;
+/// Returns mapping from parts of speech of alt-cannadic to ids in the table.
+pub fn pos_map(table: &PosTable) -> Result<PosMap> {
+    PosMap::new(table, POS_RULES, "名詞,一般")
+}
 
-    let buf = read_file_vec(filename)?;
+/// Verb classes of alt-cannadic and their IPADIC conjugation types.
+/// Words of these classes are written as stems without endings.
+const CONJUGATION_RULES: &[(&str, &str)] = &[
+    ("#K5", "五段・カ行イ音便"),
+    ("#K5r", "五段・カ行促音便"),
+    ("#G5", "五段・ガ行"),
+    ("#S5", "五段・サ行"),
+    ("#T5", "五段・タ行"),
+    ("#N5", "五段・ナ行"),
+    ("#B5", "五段・バ行"),
+    ("#M5", "五段・マ行"),
+    ("#R5", "五段・ラ行"),
+    ("#W5", "五段・ワ行促音便"),
+    ("#KS", "一段"),
+];
+
+/// Parses EUC-JP encoded alt-cannadic dictionary read from the reader.
+/// Entries are tagged with Mozc ids mapped from their parts of speech,
+/// Verbs and adjectives are expanded into conjugated forms by `inflector`.
+/// Malformed lines and skipped records are counted in `skipped`.
+pub fn parse_alt_cannadic<R: Read>(mut reader: R, pos: &PosMap, inflector: &Inflector, skipped: &mut SkippedLines) -> Result<Vec<DictEntry>> {
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
     let mut l2 = Vec::new();
     let (cow, _encoding_used, _had_errors) = EUC_JP.decode(&buf);
-    for line in cow.lines() {
+    for (line_no, line) in cow.lines().enumerate() {
         // あきびん #T35*202 空き瓶 空瓶 #T35*151 空きビン 空ビン #T35*150 空きびん
 
-        let mut s = line.trim_end().split(" ");
-        
-        let mut yomi = String::from(s.next().unwrap());
+        let mut s = line.trim_end().split(' ');
+
+        // split は必ず1つ以上の要素を返す
+        let mut yomi = String::from(s.next().unwrap_or_default());
         yomi = yomi.replace("う゛", "ゔ");
 
         // 読みがひらがな以外を含む場合はスキップ
         if yomi.chars()
-            .any(|c| !(('ぁ' <= c && c <= 'ゔ') || c == 'ー')) {
+            .any(|c| !(('ぁ'..='ゔ').contains(&c) || c == 'ー')) {
+            skipped.rule("reading is not hiragana", line_no + 1, line);
             continue;
         }
 
         let mut hinshi = "";
 
-        while let Some(entry) = s.next() {
+        for entry in s {
             // cannadicの品詞を取得
-            if entry.starts_with("#") {
+            if entry.starts_with('#') {
                 hinshi = entry;
                 continue;
             }
@@ -55,70 +92,140 @@ fn convert_alt_cannadic_to_mozcdic(filename: &str, dicname: &str) -> std::io::Re
 
             // cost を作成
             // alt-cannadicのコストは大きいほど優先度が高い。
-            let cost = if let Some((_, base_cost)) = hinshi.split_once('*') {
-                7000 - i32::from_str_radix(base_cost, 10).unwrap()
+            let (code, cost) = if let Some((code, base_cost)) = hinshi.split_once('*') {
+                if let Ok(base_cost) = base_cost.parse::<i32>() {
+                    (code, 7000 - base_cost)
+                } else {
+                    skipped.skip(line_no + 1, line, "invalid cost");
+                    break;
+                }
             } else {
                 continue;
             };
 
+            // 動詞と形容詞は語幹から活用形を作る
+            // かわい #KY*100 可愛
+            let conjugation = if code == "#KY" {
+                Some(adjective_conjugation(&format!("{}い", yomi)))
+            } else {
+                CONJUGATION_RULES.iter().find(|(class, _)| *class == code).map(|(_, conjugation)| *conjugation)
+            };
+            if let Some(conjugation) = conjugation {
+                let template = DictEntry::new(String::new(), 0, cost, String::new(), NAME).with_record(line_no + 1);
+                if let Some(mut entries) = inflector.inflect_stem(conjugation, &yomi, hyouki, &template) {
+                    l2.append(&mut entries);
+                }
+                continue;
+            }
+
             // 収録する品詞を選択
             if let Some("#T3" | "#T0" | "#JN" | "#KK" | "#CN") = hinshi.get(0..3) {
-                l2.push(Entry {
-                    key: format!("{}\t{}\t{}\n", &yomi, hyouki, cost),
-                    yomi: String::from(&yomi),
-                    hyouki: String::from(hyouki),
-                    cost: cost.to_string(),
-                });
+                let id = pos.id(&[code]);
+                l2.push(DictEntry::new(yomi.clone(), id, cost, String::from(hyouki), NAME).with_record(line_no + 1));
+            } else {
+                skipped.rule("part of speech is not used", line_no + 1, line);
             }
         }
     }
 
-    let mut lines = l2;
-    lines.par_sort_unstable_by(|a, b| a.key.cmp(&b.key));
-
-    let f = File::create(dicname)?;
-    let mut writer = BufWriter::new(f);
-    let count = lines.len();
-    for i in 0..count {
-        let s1 = &lines[i];
-        if i > 0 {
-            let s2 = &lines[i - 1];
-            // 「読み+表記」が重複するエントリはスキップ
-            if s1.yomi == s2.yomi && s1.hyouki == s2.hyouki {
-                continue;
-            }
-        }
+    Ok(l2)
+}
 
-        let v = format!("{}\t{}\t{}\t{}\t{}\n", &s1.yomi, id, id, &s1.cost, &s1.hyouki);
-        writer.write(v.as_bytes())?;
-    }
+/// Converts gcanna.ctd and g_fname.ctd files of alt-cannadic.
+#[derive(Debug, Default)]
+pub struct AltCannadic;
 
-    Ok(())
+impl AltCannadic {
+    const DATE: &'static str = "110208";
+    const CANNA_FILE1: &'static str = "gcanna.ctd";
+    const CANNA_FILE2: &'static str = "g_fname.ctd";
 }
 
-pub fn run_convert_alt_cannadic_to_mozcdic() -> std::io::Result<()> {
-    const DATE: &str = "110208";
-    const CANNA_FILE1: &str = "gcanna.ctd";
-    const CANNA_FILE2: &str = "g_fname.ctd";
+impl Source for AltCannadic {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn license(&self) -> License {
+        License::Gpl2
+    }
+
+    fn fetch(&mut self, fetcher: &Fetcher) -> Result<()> {
+        let name = format!("alt-cannadic-{}", Self::DATE);
+        let tar_name = format!("{}.tar.bz2", &name);
+        let addr = format!("https://ja.osdn.net/dl/alt-cannadic/{}", &tar_name);
+        let path_file1 = format!("{}/{}", &name, Self::CANNA_FILE1);
+        let path_file2 = format!("{}/{}", &name, Self::CANNA_FILE2);
+
+        let tar_path = fetcher.fetch(&addr, &tar_name, true)?;
+        remove_dir_if_exists(&name)?;
+        untar_bz2(&tar_path, ".")?;
+        std::fs::rename(&path_file1, Self::CANNA_FILE1)?;
+        std::fs::rename(&path_file2, Self::CANNA_FILE2)?;
+        remove_dir_if_exists(&name)?;
+
+        Ok(())
+    }
+
+    fn inputs(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from(Self::CANNA_FILE1), PathBuf::from(Self::CANNA_FILE2)]
+    }
 
-    let name = format!("alt-cannadic-{}", DATE);
-    let tar_name = format!("{}.tar.bz2", &name);
-    let addr = format!("https://ja.osdn.net/dl/alt-cannadic/{}", &tar_name);
-    let path_file1 = format!("{}/{}", &name, CANNA_FILE1);
-    let path_file2 = format!("{}/{}", &name, CANNA_FILE2);
+    fn version(&self) -> u32 {
+        6
+    }
 
-    command_wait("wget", vec!["-nc", "-q", &addr])?;
-    command_wait("rm", vec!["-rf", &name])?;
-    command_wait("tar", vec!["xf", &tar_name])?;
-    command_wait("mv", vec![&path_file1, "."])?;
-    command_wait("mv", vec![&path_file2, "."])?;
+    fn parse(&self, stats: &mut SourceStats) -> Result<Vec<DictEntry>> {
+        // Mozcの品詞IDを取得
+        // 固有名詞は後でフィルタリングする。
+        let table = pos_table()?;
+        let pos = pos_map(&table)?;
+        let inflector = Inflector::new(&table);
+
+        let mut entries = Vec::new();
+        for file_name in [Self::CANNA_FILE1, Self::CANNA_FILE2] {
+            let mut skipped = stats.skipped_lines(NAME, file_name);
+            entries.append(&mut parse_alt_cannadic(File::open(file_name).with_path(file_name)?, &pos, &inflector, &mut skipped)
+                .with_path(file_name)?);
+            skipped.report();
+            stats.add_skipped(&skipped);
+        }
 
-    convert_alt_cannadic_to_mozcdic(CANNA_FILE1, "mozcdic-ut-alt-cannadic.txt")?;
-    convert_alt_cannadic_to_mozcdic(CANNA_FILE2, "mozcdic-ut-alt-cannadic-jinmei.txt")?;
+        Ok(entries)
+    }
 
-    command_wait("rm", vec!["-rf", &name])?;
-    command_wait("rm", vec!["-f", CANNA_FILE1])?;
-    command_wait("rm", vec!["-f", CANNA_FILE2])?;
+    fn convert(&self, mut entries: Vec<DictEntry>) -> Result<Vec<DictEntry>> {
+        // 「読み+表記」が重複するエントリはスキップ
+        sort_dedup_by_reading_surface(&mut entries);
+        Ok(entries)
+    }
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_alt_cannadic_maps_place_names() {
+        let lines = "1 名詞,一般,*,*,*,*,*\n\
+                     2 名詞,固有名詞,人名,一般,*,*,*\n\
+                     3 名詞,固有名詞,人名,姓,*,*,*\n\
+                     4 名詞,固有名詞,人名,名,*,*,*\n\
+                     5 名詞,固有名詞,組織,*,*,*,*\n\
+                     6 名詞,固有名詞,地域,一般,*,*,*\n\
+                     7 名詞,サ変接続,*,*,*,*,*\n";
+        let table = PosTable::read_from(lines.as_bytes(), "id.def").unwrap();
+        let pos = pos_map(&table).unwrap();
+
+        let text = "はこね #CN*200 箱根\n\
+                    しぶや #CNS*100 渋谷\n\
+                    あきびん #T35*202 空き瓶\n";
+        let (bytes, _, _) = EUC_JP.encode(text);
+        let mut skipped = SkippedLines::new(NAME, AltCannadic::CANNA_FILE1);
+        let entries = parse_alt_cannadic(&bytes[..], &pos, &Inflector::default(), &mut skipped).unwrap();
+
+        let words: Vec<(&str, &str, u16, i32)> = entries.iter()
+            .map(|e| (e.reading.as_str(), e.surface.as_str(), e.left_id, e.cost)).collect();
+        assert_eq!(words, [("はこね", "箱根", 6, 6800), ("しぶや", "渋谷", 6, 6900), ("あきびん", "空き瓶", 1, 6798)]);
+    }
 }
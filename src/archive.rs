@@ -0,0 +1,102 @@
+// Decompression and archiving done in process instead of external tools.
+
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::fs::File;
+use std::path::Path;
+
+use bzip2::{read::MultiBzDecoder, write::BzEncoder, Compression};
+use flate2::read::MultiGzDecoder;
+use xz2::read::XzDecoder;
+
+
+/// Returns reader which decompresses gzip file.
+pub fn open_gz<P: AsRef<Path>>(path: P) -> std::io::Result<MultiGzDecoder<BufReader<File>>> {
+    Ok(MultiGzDecoder::new(BufReader::new(File::open(path)?)))
+}
+
+/// Returns reader which decompresses xz file.
+pub fn open_xz<P: AsRef<Path>>(path: P) -> std::io::Result<XzDecoder<BufReader<File>>> {
+    Ok(XzDecoder::new_multi_decoder(BufReader::new(File::open(path)?)))
+}
+
+/// Returns reader which decompresses bzip2 file.
+pub fn open_bz2<P: AsRef<Path>>(path: P) -> std::io::Result<MultiBzDecoder<BufReader<File>>> {
+    Ok(MultiBzDecoder::new(BufReader::new(File::open(path)?)))
+}
+
+/// Writes content of the reader into the file.
+fn write_to_file<R: Read, P: AsRef<Path>>(mut reader: R, dest: P) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(dest)?);
+    std::io::copy(&mut reader, &mut writer)?;
+    writer.flush()
+}
+
+/// Decompresses gzip file into the dest file.
+pub fn gunzip<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dest: Q) -> std::io::Result<()> {
+    write_to_file(open_gz(src)?, dest)
+}
+
+/// Decompresses xz file into the dest file.
+pub fn unxz<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dest: Q) -> std::io::Result<()> {
+    write_to_file(open_xz(src)?, dest)
+}
+
+/// Extracts all files in the zip archive into the directory.
+pub fn unzip<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dir: Q) -> std::io::Result<()> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(File::open(src)?))
+        .map_err(std::io::Error::from)?;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(std::io::Error::from)?;
+        // 展開先の外を指すパスは無視する
+        let path = if let Some(path) = file.enclosed_name() {
+            dir.as_ref().join(path)
+        } else {
+            continue;
+        };
+        if file.is_dir() {
+            std::fs::create_dir_all(&path)?;
+        } else {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            write_to_file(&mut file, &path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Extracts all files in the tar.bz2 archive into the directory.
+pub fn untar_bz2<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dir: Q) -> std::io::Result<()> {
+    tar::Archive::new(open_bz2(src)?).unpack(dir)
+}
+
+/// Reads the first file in the tar.bz2 archive whose path is selected,
+/// returns the path and the content, or None if no file is selected.
+pub fn read_tar_bz2_file<P: AsRef<Path>, F: FnMut(&str) -> bool>(src: P, mut select: F) -> std::io::Result<Option<(String, Vec<u8>)>> {
+    let mut archive = tar::Archive::new(open_bz2(src)?);
+    for file in archive.entries()? {
+        let mut file = file?;
+        let path = file.path()?.to_string_lossy().into_owned();
+        if file.header().entry_type().is_file() && select(&path) {
+            let mut content = Vec::new();
+            file.read_to_end(&mut content)?;
+            return Ok(Some((path, content)));
+        }
+    }
+    Ok(None)
+}
+
+/// Creates tar.bz2 archive which contains the files and directories,
+/// paths are stored as specified.
+pub fn tar_bz2<P: AsRef<Path>>(dest: P, paths: &[&str]) -> std::io::Result<()> {
+    let encoder = BzEncoder::new(BufWriter::new(File::create(dest)?), Compression::best());
+    let mut builder = tar::Builder::new(encoder);
+    for path in paths {
+        if Path::new(path).is_dir() {
+            builder.append_dir_all(path, path)?;
+        } else {
+            builder.append_path(path)?;
+        }
+    }
+    builder.into_inner()?.finish()?.flush()
+}
@@ -0,0 +1,174 @@
+// Cache of entries converted by sources, reused while their inputs are the same.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use super::entry::{parse_entry_with_origins, write_entry_with_origins, DictEntry};
+use super::error::{Error, Result, WithPath};
+use super::pos::ID_DEF_NAME;
+use super::stats::SourceStats;
+use super::util::intern;
+
+
+/// Returns key of entries converted from the input files, which is SHA-256
+/// of the crate version, the source name, the converter version, `settings`
+/// and contents of the input files and id.def file in hex.
+pub fn cache_key(name: &str, version: u32, inputs: &[PathBuf], settings: &str) -> Result<String> {
+    let files = inputs.iter().map(PathBuf::as_path).chain(std::iter::once(Path::new(ID_DEF_NAME)));
+    key_of(name, version, files, settings)
+}
+
+fn key_of<'a, I: IntoIterator<Item = &'a Path>>(name: &str, version: u32, files: I, settings: &str) -> Result<String> {
+    let mut hasher = Sha256::new();
+    // 区切りが曖昧にならないように長さを先に入れる
+    for field in [env!("CARGO_PKG_VERSION"), name, &version.to_string(), settings] {
+        hasher.update((field.len() as u64).to_le_bytes());
+        hasher.update(field.as_bytes());
+    }
+    for path in files {
+        let mut f = File::open(path).with_path(path)?;
+        hasher.update(f.metadata().with_path(path)?.len().to_le_bytes());
+        std::io::copy(&mut f, &mut hasher).with_path(path)?;
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Counts of the source kept with cached entries.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct CachedStats {
+    parsed: usize,
+    malformed: usize,
+    skipped: BTreeMap<String, usize>,
+    converted: usize,
+}
+
+/// Directory which keeps entries of each source with their key.
+///
+/// For each source, NAME.key has the key, NAME.entries.txt has entries
+/// with their origins and NAME.stats.json has counts of the source.
+/// Only the last run of each source is kept.
+#[derive(Clone, Debug)]
+pub struct BuildCache {
+    dir: PathBuf,
+}
+
+impl BuildCache {
+    /// Creates cache in the directory, which is created when entries are stored.
+    pub fn new<P: AsRef<Path>>(dir: P) -> BuildCache {
+        BuildCache {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    fn path(&self, name: &str, suffix: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", name, suffix))
+    }
+
+    /// Returns cached entries of the source if they have the key, and adds
+    /// counts of the cached run into `stats`. Broken cache is ignored.
+    pub fn load(&self, name: &str, key: &str, stats: &mut SourceStats) -> Result<Option<Vec<DictEntry>>> {
+        match std::fs::read_to_string(self.path(name, "key")) {
+            Ok(cached) if cached.trim_end() == key => {},
+            _ => return Ok(None),
+        }
+
+        let stats_path = self.path(name, "stats.json");
+        let cached: CachedStats = match std::fs::read_to_string(&stats_path).map(|s| serde_json::from_str(&s)) {
+            Ok(Ok(cached)) => cached,
+            _ => {
+                println!("{} is broken, ignored.", stats_path.display());
+                return Ok(None);
+            }
+        };
+
+        let entries_path = self.path(name, "entries.txt");
+        let reader = BufReader::new(File::open(&entries_path).with_path(&entries_path)?);
+        let mut entries = Vec::with_capacity(cached.converted);
+        for line in reader.lines() {
+            match parse_entry_with_origins(&line.with_path(&entries_path)?) {
+                Some(entry) => entries.push(entry),
+                None => {
+                    println!("{} is broken, ignored.", entries_path.display());
+                    return Ok(None);
+                }
+            }
+        }
+        if entries.len() != cached.converted {
+            println!("{} is broken, ignored.", entries_path.display());
+            return Ok(None);
+        }
+
+        stats.parsed += cached.parsed;
+        stats.malformed += cached.malformed;
+        for (rule, count) in &cached.skipped {
+            *stats.skipped.entry(intern(rule)).or_insert(0) += count;
+        }
+        stats.converted += cached.converted;
+        Ok(Some(entries))
+    }
+
+    /// Stores entries of the source and counts of the run with the key.
+    /// The key is written last, so that an interrupted write is not used.
+    pub fn store(&self, name: &str, key: &str, entries: &[DictEntry], stats: &SourceStats) -> Result<()> {
+        std::fs::create_dir_all(&self.dir).with_path(&self.dir)?;
+        let key_path = self.path(name, "key");
+        if key_path.exists() {
+            std::fs::remove_file(&key_path).with_path(&key_path)?;
+        }
+
+        let entries_path = self.path(name, "entries.txt");
+        let mut writer = BufWriter::new(File::create(&entries_path).with_path(&entries_path)?);
+        for entry in entries {
+            write_entry_with_origins(&mut writer, entry).with_path(&entries_path)?;
+        }
+        writer.flush().with_path(&entries_path)?;
+
+        let stats_path = self.path(name, "stats.json");
+        let s = serde_json::to_string(stats).map_err(|e| Error::other(format!("{}: {}", stats_path.display(), e)))?;
+        std::fs::write(&stats_path, s).with_path(&stats_path)?;
+
+        std::fs::write(&key_path, format!("{}\n", key)).with_path(&key_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_inputs_miss_the_cache() {
+        let dir = std::env::temp_dir().join(format!("mozcdic-ut-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.txt");
+        std::fs::write(&input, "なかいまさひろ\t中居正広\n").unwrap();
+        let key = |version: u32, settings: &str| key_of("test", version, vec![input.as_path()], settings).unwrap();
+
+        let cache = BuildCache::new(dir.join("cache"));
+        let entries = vec![DictEntry::new(String::from("なかいまさひろ"), 1, 6000, String::from("中居正広"), "test")];
+        let mut stats = SourceStats { parsed: 2, converted: 1, ..SourceStats::default() };
+        stats.skipped.insert("no reading", 1);
+        let stored = key(1, "");
+        cache.store("test", &stored, &entries, &stats).unwrap();
+
+        // 同じ入力なら変換した結果と数を読み込む
+        let mut loaded = SourceStats::default();
+        assert_eq!(cache.load("test", &key(1, ""), &mut loaded).unwrap(), Some(entries));
+        assert_eq!((loaded.parsed, loaded.converted), (2, 1));
+        assert_eq!(loaded.skipped.get("no reading"), Some(&1));
+
+        // 変換器の版、設定、入力のどれかが変われば使わない
+        assert_ne!(key(2, ""), stored);
+        assert_eq!(cache.load("test", &key(2, ""), &mut SourceStats::default()).unwrap(), None);
+        assert_eq!(cache.load("test", &key(1, "max_hits = 10"), &mut SourceStats::default()).unwrap(), None);
+        std::fs::write(&input, "なかいまさひろ\t中居正廣\n").unwrap();
+        assert_eq!(cache.load("test", &key(1, ""), &mut SourceStats::default()).unwrap(), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,21 +1,30 @@
 
 // Ported from fix_ken_all.rb and generate_chimei_for_mozcdic.rb files.
 
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
 use std::fs::File;
+use std::path::PathBuf;
 
 use encoding_rs::*;
 use regex::*;
-use rayon::prelude::*;
 
-use super::mozc::get_id;
+use super::archive::*;
+use super::entry::{sort_dedup, DictEntry};
+use super::error::{Result, SkippedLines, WithPath};
+use super::license::License;
+use super::pos::pos_table;
+use super::fetch::Fetcher;
+use super::source::Source;
+use super::stats::SourceStats;
 use super::util::*;
 
 
-pub fn generate_chimei_for_mozcdic() -> std::io::Result<()> {
-    // Mozcの品詞IDを取得
-    let id = get_id(r"(\d*) 名詞,固有名詞,地域,一般,\*,\*,\*")?;
+const NAME: &str = "chimei";
 
+/// Generates place name entries from KEN_ALL.CSV fixed by `fix_ken_all`.
+/// Entries are tagged with the specified Mozc id, malformed lines and
+/// skipped records are counted in `skipped`.
+pub fn generate_chimei_for_mozcdic<R: BufRead>(mut reader: R, id: u16, skipped: &mut SkippedLines) -> Result<Vec<DictEntry>> {
     let re_num = Regex::new(r"\d+").unwrap();
     let number_to_reading = {
         // 半角数字をひらがなに変換する配列を作成
@@ -35,14 +44,19 @@ pub fn generate_chimei_for_mozcdic() -> std::io::Result<()> {
     };
 
     let mut l2 = Vec::new();
-    let d = File::open("KEN_ALL.CSV.fixed")?;
-    let mut reader = BufReader::new(d);
     let mut line = String::new();
-    while let Ok(1..) = reader.read_line(&mut line) {
-        let mut s = line.replace("\"", "")
-                        .split(",")
+    let mut line_no = 0;
+    while reader.read_line(&mut line)? > 0 {
+        line_no += 1;
+        let mut s = line.replace('"', "")
+                        .split(',')
                         .map(String::from)
                         .collect::<Vec<String>>();
+        if s.len() < 9 {
+            skipped.skip(line_no, &line, "too few fields");
+            line.clear();
+            continue;
+        }
 
         // 並びの例
         // "トヤマケン","タカオカシ","ミハラマチ","富山県","高岡市","美原町"
@@ -57,75 +71,68 @@ pub fn generate_chimei_for_mozcdic() -> std::io::Result<()> {
         // removed in half_to_hiragana_no_dot
 
         // 市を出力
-        let l = format!("{}\t{}\t{}\t9000\t{}\n", &s[4], id, id, &s[7]);
-        l2.push(l);
+        l2.push(DictEntry::new(s[4].clone(), id, 9000, s[7].clone(), NAME).with_record(line_no));
 
         // 町の読みが半角数字を含むか確認
         // 町の読みの半角数字が59以下の場合はひらがなに変換
         // さっぽろしひがしくきた51じょうひがし
-        match re_num.replace_all(&s[5], |caps: &Captures| {
-            let index = usize::from_str_radix(caps.get(0).unwrap().as_str(), 10).unwrap();
+        if let std::borrow::Cow::Owned(r) = re_num.replace_all(&s[5], |caps: &Captures| {
+            let index = caps.get(0).unwrap().as_str().parse::<usize>().unwrap();
             String::from(if index < 60 {
                 &number_to_reading[index]
             } else {
                 caps.get(0).unwrap().as_str()
             })
         }) {
-            std::borrow::Cow::Owned(r) => s[5] = r,
-            _ => {},
+            s[5] = r;
         }
 
         // 町の読みがひらがな以外を含む場合はスキップ
         // 「自由が丘(3～7丁目)」「OAPたわー」
         if s[5].chars()
-               .any(|c| !(('ぁ' <= c && c <= 'ゔ') || c == 'ー')) ||
+               .any(|c| !(('ぁ'..='ゔ').contains(&c) || c == 'ー')) ||
            // 町の表記が空の場合はスキップ
-           s[8] == "" {
+           s[8].is_empty() {
+            skipped.rule("town reading is not hiragana or surface is empty", line_no, &line);
             line.clear();
             continue;
         }
 
         // 町を出力
-        l2.push(format!("{}\t{}\t{}\t9000\t{}\n", &s[5], id, id, &s[8]));
+        l2.push(DictEntry::new(s[5].clone(), id, 9000, s[8].clone(), NAME).with_record(line_no));
 
         // 市+町を出力
-        l2.push(format!("{}{}\t{}\t{}\t9000\t{}{}\n", &s[4], &s[5], id, id, &s[7], &s[8]));
+        l2.push(DictEntry::new(format!("{}{}", &s[4], &s[5]), id, 9000, format!("{}{}", &s[7], &s[8]), NAME).with_record(line_no));
 
         line.clear();
     }
 
-    // 重複行を削除
-    l2.par_sort_unstable();
-    l2.dedup();
-
-    let f = File::create("mozcdic-ut-chimei.txt")?;
-    let mut writer = BufWriter::new(f);
-    for line in l2 {
-        writer.write(line.as_bytes())?;
-    }
-
-    Ok(())
+    Ok(l2)
 }
 
-fn fix_ken_all(filename: &str, dicname: &str) -> std::io::Result<()> {
-    let d = File::create(dicname)?;
-    let mut writer = BufWriter::new(d);
-
+/// Fixes Shift_JIS encoded KEN_ALL.CSV read from the reader, and writes it
+/// into the writer in UTF-8. Malformed lines are counted in `skipped`.
+pub fn fix_ken_all<R: Read, W: Write>(mut reader: R, mut writer: W, skipped: &mut SkippedLines) -> Result<()> {
     let re = Regex::new(r"[０-９ａ-ｚＡ-Ｚ（）　−]").unwrap();
     // 除外する文字列
     // (例) 「3701、3704、」「4710〜4741」「坪毛沢「2」」
     let ngs = ["○", "〔", "〜", "、", "「", "を除く", "以外", "その他",
               "地割", "不明", "以下に掲載がない場合"];
 
-    let buf = read_file_vec(filename)?;
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
     let (cow, _encoding_used, _had_errors) = SHIFT_JIS.decode(&buf);
-    for line in cow.lines() {
+    for (line_no, line) in cow.lines().enumerate() {
         // 並びの例
         // 46201,"89112","8911275","カゴシマケン","カゴシマシ", "カワカミチョウ(3649)",
         // "鹿児島県","鹿児島市","川上町（３６４９）"
 
-        let mut s = line.split(",").map(String::from).collect::<Vec<String>>();
-        match re.replace_all(&s[8], |caps: &Captures| {
+        let mut s = line.split(',').map(String::from).collect::<Vec<String>>();
+        if s.len() < 9 {
+            skipped.skip(line_no + 1, line, "too few fields");
+            continue;
+        }
+        if let std::borrow::Cow::Owned(r) = re.replace_all(&s[8], |caps: &Captures| {
             let mut rs = String::with_capacity(16);
             for c in caps.get(0).unwrap().as_str().chars() {
                 match c {
@@ -140,20 +147,19 @@ fn fix_ken_all(filename: &str, dicname: &str) -> std::io::Result<()> {
             }
             rs
         }) {
-            std::borrow::Cow::Owned(r) => s[8] = r,
-            _ => {},
+            s[8] = r;
         }
 
         // 町域表記の () 内に除外文字列があるかチェック
-        if let Some(index) = s[8].find("(") {
-            let t = unsafe { s[8].get_unchecked(index..s[8].len() - 2) };
+        if let Some(index) = s[8].find('(') {
+            let t = s[8].get(index..s[8].len().saturating_sub(2)).unwrap_or_default();
             for ng in ngs {
                 if t.find(ng).is_some() {
                     // 該当する場合は町域の読みと表記の「(」以降を削除
-                    if let Some(index5) = s[5].find("(") {
+                    if let Some(index5) = s[5].find('(') {
                         s[5] = String::from(unsafe { s[5].get_unchecked(..index5) });
                     }
-                    if let Some(index8) = s[8].find("(") {
+                    if let Some(index8) = s[8].find('(') {
                         s[8] = String::from(unsafe { s[8].get_unchecked(..index8) });
                     }
                     break;
@@ -175,28 +181,71 @@ fn fix_ken_all(filename: &str, dicname: &str) -> std::io::Result<()> {
         // (例) 「"ハラ(ゴクラクザカ)","原(極楽坂)"」を
         // 「"ハラゴクラクザカ","原(極楽坂)"」にする。
         // 表記の () はそのままにする。「原極楽坂」だと読みにくいので
-        s[5] = s[5].replace("(", "").replace(")", "");
+        s[5] = s[5].replace(['(', ')'], "");
 
         for (index, ss) in s.iter().enumerate() {
-            writer.write(ss.as_bytes())?;
+            writer.write_all(ss.as_bytes())?;
             if index < 14 {
-                writer.write(b",")?;
+                writer.write_all(b",")?;
             }
         }
-        writer.write(b"\n")?;
+        writer.write_all(b"\n")?;
     }
 
-    Ok(())
+    Ok(writer.flush()?)
 }
 
-pub fn run_fix_ken_all() -> std::io::Result<()> {
-    const KEN_NAME: &str = "KEN_ALL.CSV";
+/// Generates place names from zip code data of Japan Post.
+#[derive(Debug, Default)]
+pub struct Chimei;
 
-    command_wait("rm", vec!["-f", KEN_NAME])?;
-    command_wait("wget", vec!["-N", "-q", "https://www.post.japanpost.jp/zipcode/dl/kogaki/zip/ken_all.zip"])?;
-    command_wait("unzip", vec!["ken_all.zip"])?;
-    fix_ken_all("KEN_ALL.CSV", "KEN_ALL.CSV.fixed")?;
-    command_wait("rm", vec!["-f", KEN_NAME])?;
+impl Chimei {
+    const KEN_NAME: &'static str = "KEN_ALL.CSV";
+    const FIXED_NAME: &'static str = "KEN_ALL.CSV.fixed";
+}
+
+impl Source for Chimei {
+    fn name(&self) -> &'static str {
+        NAME
+    }
 
-    Ok(())
+    fn license(&self) -> License {
+        License::PublicDomain
+    }
+
+    fn fetch(&mut self, fetcher: &Fetcher) -> Result<()> {
+        remove_file_if_exists(Self::KEN_NAME)?;
+        let zip_path = fetcher.fetch("https://www.post.japanpost.jp/zipcode/dl/kogaki/zip/ken_all.zip", "ken_all.zip", false)?;
+        unzip(&zip_path, ".")?;
+        let mut skipped = SkippedLines::new(NAME, Self::KEN_NAME);
+        fix_ken_all(File::open(Self::KEN_NAME).with_path(Self::KEN_NAME)?,
+                    BufWriter::new(File::create(Self::FIXED_NAME).with_path(Self::FIXED_NAME)?), &mut skipped)?;
+        skipped.report();
+        remove_file_if_exists(Self::KEN_NAME)?;
+
+        Ok(())
+    }
+
+    fn inputs(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from(Self::FIXED_NAME)]
+    }
+
+    fn parse(&self, stats: &mut SourceStats) -> Result<Vec<DictEntry>> {
+        // Mozcの品詞IDを取得
+        let id = pos_table()?.id("名詞,固有名詞,地域,一般")?;
+
+        let mut skipped = stats.skipped_lines(NAME, Self::FIXED_NAME);
+        let entries = generate_chimei_for_mozcdic(BufReader::new(File::open(Self::FIXED_NAME).with_path(Self::FIXED_NAME)?), id, &mut skipped)
+            .with_path(Self::FIXED_NAME)?;
+        skipped.report();
+        stats.add_skipped(&skipped);
+        Ok(entries)
+    }
+
+    fn convert(&self, mut entries: Vec<DictEntry>) -> Result<Vec<DictEntry>> {
+        // 重複行を削除
+        sort_dedup(&mut entries);
+
+        Ok(entries)
+    }
 }
@@ -0,0 +1,268 @@
+// Declarative configuration of the workflow command.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::{Deserialize, Deserializer};
+
+use super::costs::CostSettings;
+use super::error::{Error, Result, WithPath};
+use super::extsort::SortSettings;
+use super::fetch::Fetcher;
+use super::filter::{FilterStage, UNSUITABLE_FILE_NAME};
+use super::license::{parse_licenses, License};
+use super::source::source_from_command;
+
+
+/// Configuration bundled in data/workflow.toml file, used if no file is specified.
+const DEFAULT_CONFIG: &str = include_str!("../data/workflow.toml");
+
+/// Configuration of the workflow command.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Settings of the whole build.
+    #[serde(default)]
+    pub build: BuildConfig,
+    /// Parameters of cost calculation.
+    #[serde(default)]
+    pub costs: CostSettings,
+    /// Memory budget of sorting entries in the merge and cost stages.
+    #[serde(default)]
+    pub sort: SortSettings,
+    /// Sources in the order of merging.
+    #[serde(default)]
+    pub sources: Vec<SourceConfig>,
+}
+
+/// Settings of the whole build.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BuildConfig {
+    /// Name of the dictionary file.
+    pub output: String,
+    /// Name of the archive which contains the dictionary, README and licenses.
+    pub archive: String,
+    /// Name of the sidecar file which maps each line of the dictionary to
+    /// its sources, not written if unspecified.
+    pub provenance: Option<String>,
+    /// Name of the file where statistics of the build are written in JSON,
+    /// not written if unspecified.
+    pub stats: Option<String>,
+    /// Directory where converted entries of each source are cached,
+    /// sources are always converted if unspecified.
+    pub cache: Option<String>,
+    /// File of unsuitable entries filter.
+    pub unsuitable_filter: String,
+    /// Filters applied to entries of all sources before calculating costs.
+    pub filters: Vec<FilterStage>,
+    /// Licenses of entries allowed in the dictionary, all licenses if empty.
+    #[serde(deserialize_with = "deserialize_licenses")]
+    pub licenses: Vec<License>,
+    /// Head of README written into the archive, attributions follow it.
+    pub readme: String,
+}
+
+impl Default for BuildConfig {
+    fn default() -> Self {
+        BuildConfig {
+            output: String::from("mozcdic-ut.txt"),
+            archive: String::from("mozcdic-ut.tar.bz2"),
+            provenance: None,
+            stats: None,
+            cache: None,
+            unsuitable_filter: String::from(UNSUITABLE_FILE_NAME),
+            filters: vec![FilterStage::Unnecessary],
+            licenses: Vec::new(),
+            readme: String::new(),
+        }
+    }
+}
+
+impl BuildConfig {
+    /// Returns true if entries of the license can be put into the dictionary.
+    /// Entries of unknown license are allowed only if licenses are not restricted.
+    pub fn allows(&self, license: Option<License>) -> bool {
+        self.licenses.is_empty() || license.is_some_and(|license| self.licenses.contains(&license))
+    }
+}
+
+/// Reads license names, which may contain `apache-2.0-compatible`.
+fn deserialize_licenses<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Vec<License>, D::Error> {
+    let names = Vec::<String>::deserialize(deserializer)?;
+    parse_licenses(&names.join(","))
+        .ok_or_else(|| serde::de::Error::custom(format!("unknown license in {:?}", names)))
+}
+
+/// Settings of a source.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourceConfig {
+    /// Command name of the source.
+    pub name: String,
+    /// Used when no source is specified on the command line.
+    #[serde(default = "enabled_default")]
+    pub enabled: bool,
+    /// License of entries, the license of the source is used if unspecified.
+    #[serde(default)]
+    pub license: Option<License>,
+    /// License text put into the archive.
+    #[serde(default)]
+    pub license_file: Option<String>,
+    /// Text written into README.
+    #[serde(default)]
+    pub attribution: String,
+    /// Filters applied to entries of the source.
+    #[serde(default)]
+    pub filters: Vec<FilterStage>,
+    /// Download urls specified by file names or default urls.
+    #[serde(default)]
+    pub urls: HashMap<String, String>,
+    /// Pre-downloaded files specified by file names.
+    #[serde(default)]
+    pub paths: HashMap<String, String>,
+}
+
+fn enabled_default() -> bool {
+    true
+}
+
+impl SourceConfig {
+    /// Returns true if the command name specifies this source, aliases are accepted.
+    pub fn matches(&self, command: &str) -> bool {
+        self.name == command || match (source_from_command(&self.name), source_from_command(command)) {
+            (Some(a), Some(b)) => a.name() == b.name(),
+            _ => false,
+        }
+    }
+
+    /// Returns license of entries of this source.
+    pub fn license(&self) -> License {
+        self.license.unwrap_or_else(|| source_from_command(&self.name).expect("unknown source").license())
+    }
+
+    /// Returns settings of this source which are a part of the cache key.
+    pub fn cache_settings(&self) -> String {
+        let mut urls = self.urls.iter().collect::<Vec<_>>();
+        urls.sort_unstable();
+        let mut paths = self.paths.iter().collect::<Vec<_>>();
+        paths.sort_unstable();
+        format!("license={} urls={:?} paths={:?}", self.license(), urls, paths)
+    }
+
+    /// Returns fetcher which uses urls and paths of this source.
+    pub fn fetcher(&self, fetcher: &Fetcher) -> Fetcher {
+        let mut fetcher = fetcher.clone();
+        for (key, url) in &self.urls {
+            fetcher.set_url(key, url);
+        }
+        for (name, path) in &self.paths {
+            fetcher.set_override(name, path);
+        }
+        fetcher
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::parse(DEFAULT_CONFIG).expect("bundled workflow.toml is broken")
+    }
+}
+
+impl Config {
+    /// Parses configuration written in TOML.
+    pub fn parse(s: &str) -> Result<Config> {
+        let config: Config = toml::from_str(s).map_err(|e| Error::other(format!("invalid configuration: {}", e)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reads configuration from the TOML file.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Config> {
+        let s = std::fs::read_to_string(&path).with_path(&path)?;
+        Config::parse(&s).map_err(|e| Error::other(format!("{}: {}", path.as_ref().display(), e)))
+    }
+
+    /// Checks that every source is known.
+    fn validate(&self) -> Result<()> {
+        for source in &self.sources {
+            if source_from_command(&source.name).is_none() {
+                return Err(Error::other(format!("unknown source in configuration: {}", source.name)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns sources specified by command names in the order of the configuration.
+    /// Enabled sources are returned if no name is specified.
+    pub fn selected_sources(&self, commands: &[&str]) -> Result<Vec<&SourceConfig>> {
+        if commands.is_empty() {
+            return Ok(self.sources.iter().filter(|source| source.enabled).collect());
+        }
+        for command in commands {
+            if !self.sources.iter().any(|source| source.matches(command)) {
+                return Err(Error::other(format!("unknown option: {}", command)));
+            }
+        }
+        Ok(self.sources.iter().filter(|source| commands.iter().any(|command| source.matches(command))).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_config_has_default_settings() {
+        let config = Config::default();
+        assert_eq!(config.costs, CostSettings::default());
+        assert_eq!(config.sort, SortSettings::default());
+        assert_eq!(config.build.filters, [FilterStage::Unnecessary]);
+        assert!(config.sources.iter().all(|source| source_from_command(&source.name).is_some()));
+    }
+
+    #[test]
+    fn parse_sources_with_defaults() {
+        let config = Config::parse(r#"
+            [build]
+            licenses = ["apache-2.0-compatible"]
+
+            [costs]
+            max_hits = 10
+
+            [[sources]]
+            name = "neologd"
+            filters = ["unsuitable"]
+
+            [[sources]]
+            name = "sudachidic"
+            enabled = false
+            license = "public-domain"
+        "#).unwrap();
+        assert_eq!(config.build.output, "mozcdic-ut.txt");
+        assert_eq!(config.build.licenses, [License::Apache2, License::Bsd3, License::PublicDomain]);
+        assert_eq!(config.costs, CostSettings { max_hits: 10, ..CostSettings::default() });
+
+        let neologd = &config.sources[0];
+        assert!(neologd.enabled);
+        assert_eq!(neologd.filters, [FilterStage::Unsuitable]);
+        assert_eq!(neologd.license(), License::Apache2);
+        assert_eq!(config.sources[1].license(), License::PublicDomain);
+
+        // 別名でも選べ、指定がなければ有効なものだけを使う
+        let names = |sources: Vec<&SourceConfig>| sources.iter().map(|source| source.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(config.selected_sources(&[]).unwrap()), ["neologd"]);
+        assert_eq!(names(config.selected_sources(&["sudachidict"]).unwrap()), ["sudachidic"]);
+        assert!(config.selected_sources(&["skkdic"]).is_err());
+    }
+
+    #[test]
+    fn reject_unknown_keys_and_sources() {
+        assert!(Config::parse("[build]\noutputs = \"mozcdic-ut.txt\"\n").is_err());
+        assert!(Config::parse("[costs]\nmax_hit = 10\n").is_err());
+        assert!(Config::parse("[[sources]]\nname = \"neologd\"\nfilter = [\"unsuitable\"]\n").is_err());
+        assert!(Config::parse("[[sources]]\nname = \"unidic\"\n").is_err());
+        assert!(Config::parse("[[sources]]\nname = \"neologd\"\nfilters = [\"unknown\"]\n").is_err());
+        assert!(Config::parse("[build]\nlicenses = [\"MIT\"]\n").is_err());
+    }
+}
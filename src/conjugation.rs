@@ -0,0 +1,270 @@
+// Conjugated forms of verbs and adjectives, which Mozc has as separate entries.
+
+use super::entry::DictEntry;
+use super::pos::PosTable;
+
+
+/// Conjugation type of IPADIC, which is also used by id.def of Mozc.
+#[derive(Debug)]
+pub struct Conjugation {
+    /// 活用型, such as `五段・カ行イ音便`.
+    pub name: &'static str,
+    /// Ending of the base form which follows the stem.
+    pub ending: &'static str,
+    /// 活用形 and the suffix which follows the stem.
+    /// Forms which have the same suffix are made as separate entries,
+    /// since Mozc connects them by the ids of their forms.
+    pub forms: &'static [(&'static str, &'static str)],
+}
+
+const ADJECTIVE_FORMS: &[(&str, &str)] = &[
+    ("基本形", "い"), ("文語基本形", ""), ("ガル接続", ""), ("未然ヌ接続", "から"), ("未然ウ接続", "かろ"),
+    ("連用タ接続", "かっ"), ("連用テ接続", "く"), ("体言接続", "き"), ("仮定形", "けれ"), ("命令ｅ", "かれ"),
+    ("仮定縮約１", "けりゃ"), ("仮定縮約２", "きゃ"),
+];
+
+/// Conjugation types which are expanded.
+pub const CONJUGATIONS: &[Conjugation] = &[
+    Conjugation {
+        name: "五段・カ行イ音便",
+        ending: "く",
+        forms: &[("基本形", "く"), ("未然形", "か"), ("未然ウ接続", "こ"), ("連用形", "き"), ("連用タ接続", "い"),
+                 ("仮定形", "け"), ("命令ｅ", "け"), ("仮定縮約１", "きゃ")],
+    },
+    Conjugation {
+        name: "五段・カ行促音便",
+        ending: "く",
+        forms: &[("基本形", "く"), ("未然形", "か"), ("未然ウ接続", "こ"), ("連用形", "き"), ("連用タ接続", "っ"),
+                 ("仮定形", "け"), ("命令ｅ", "け"), ("仮定縮約１", "きゃ")],
+    },
+    Conjugation {
+        name: "五段・ガ行",
+        ending: "ぐ",
+        forms: &[("基本形", "ぐ"), ("未然形", "が"), ("未然ウ接続", "ご"), ("連用形", "ぎ"), ("連用タ接続", "い"),
+                 ("仮定形", "げ"), ("命令ｅ", "げ"), ("仮定縮約１", "ぎゃ")],
+    },
+    Conjugation {
+        name: "五段・サ行",
+        ending: "す",
+        forms: &[("基本形", "す"), ("未然形", "さ"), ("未然ウ接続", "そ"), ("連用形", "し"), ("連用タ接続", "し"),
+                 ("仮定形", "せ"), ("命令ｅ", "せ"), ("仮定縮約１", "しゃ")],
+    },
+    Conjugation {
+        name: "五段・タ行",
+        ending: "つ",
+        forms: &[("基本形", "つ"), ("未然形", "た"), ("未然ウ接続", "と"), ("連用形", "ち"), ("連用タ接続", "っ"),
+                 ("仮定形", "て"), ("命令ｅ", "て"), ("仮定縮約１", "ちゃ")],
+    },
+    Conjugation {
+        name: "五段・ナ行",
+        ending: "ぬ",
+        forms: &[("基本形", "ぬ"), ("未然形", "な"), ("未然ウ接続", "の"), ("連用形", "に"), ("連用タ接続", "ん"),
+                 ("仮定形", "ね"), ("命令ｅ", "ね"), ("仮定縮約１", "にゃ")],
+    },
+    Conjugation {
+        name: "五段・バ行",
+        ending: "ぶ",
+        forms: &[("基本形", "ぶ"), ("未然形", "ば"), ("未然ウ接続", "ぼ"), ("連用形", "び"), ("連用タ接続", "ん"),
+                 ("仮定形", "べ"), ("命令ｅ", "べ"), ("仮定縮約１", "びゃ")],
+    },
+    Conjugation {
+        name: "五段・マ行",
+        ending: "む",
+        forms: &[("基本形", "む"), ("未然形", "ま"), ("未然ウ接続", "も"), ("連用形", "み"), ("連用タ接続", "ん"),
+                 ("仮定形", "め"), ("命令ｅ", "め"), ("仮定縮約１", "みゃ")],
+    },
+    Conjugation {
+        name: "五段・ラ行",
+        ending: "る",
+        forms: &[("基本形", "る"), ("未然形", "ら"), ("未然ウ接続", "ろ"), ("連用形", "り"), ("連用タ接続", "っ"),
+                 ("仮定形", "れ"), ("命令ｅ", "れ"), ("仮定縮約１", "りゃ"), ("体言接続特殊", "ん"), ("体言接続特殊２", "")],
+    },
+    Conjugation {
+        name: "五段・ワ行促音便",
+        ending: "う",
+        forms: &[("基本形", "う"), ("未然形", "わ"), ("未然ウ接続", "お"), ("連用形", "い"), ("連用タ接続", "っ"),
+                 ("仮定形", "え"), ("命令ｅ", "え"), ("仮定縮約１", "や")],
+    },
+    Conjugation {
+        name: "一段",
+        ending: "る",
+        forms: &[("基本形", "る"), ("未然形", ""), ("未然ウ接続", "よ"), ("連用形", ""), ("仮定形", "れ"),
+                 ("命令ｒｏ", "ろ"), ("命令ｙｏ", "よ"), ("仮定縮約１", "りゃ"), ("体言接続特殊", "ん")],
+    },
+    Conjugation {
+        name: "形容詞・アウオ段",
+        ending: "い",
+        forms: ADJECTIVE_FORMS,
+    },
+    Conjugation {
+        name: "形容詞・イ段",
+        ending: "い",
+        forms: ADJECTIVE_FORMS,
+    },
+];
+
+/// Returns the conjugation type of the name.
+pub fn conjugation(name: &str) -> Option<&'static Conjugation> {
+    CONJUGATIONS.iter().find(|conjugation| conjugation.name == name)
+}
+
+/// Returns conjugation type of the adjective, which depends on the vowel
+/// before the ending い of the reading.
+pub fn adjective_conjugation(reading: &str) -> &'static str {
+    let mut chars = reading.chars().rev();
+    chars.next();
+    // 美しい、大きい はイ段、高い、赤い はアウオ段
+    if chars.next().is_some_and(|c| "いきぎしじちぢにひびぴみりぃ".contains(c)) {
+        "形容詞・イ段"
+    } else {
+        "形容詞・アウオ段"
+    }
+}
+
+/// Makes entries of conjugated forms with ids of the forms in id.def.
+#[derive(Clone, Debug, Default)]
+pub struct Inflector {
+    forms: Vec<(&'static Conjugation, Vec<(&'static str, u16)>)>,
+}
+
+impl Inflector {
+    /// Looks up ids of the forms in the table, forms which are not
+    /// defined in the table are not made.
+    pub fn new(table: &PosTable) -> Inflector {
+        let forms = CONJUGATIONS.iter().map(|conjugation| {
+            let pos = if conjugation.name.starts_with("形容詞") { "形容詞" } else { "動詞" };
+            let ids = conjugation.forms.iter().filter_map(|(form, suffix)| {
+                let fields = [pos, "自立", "*", "*", conjugation.name, form];
+                table.iter().find(|p| p.is(&fields)).map(|p| (*suffix, p.id))
+            }).collect();
+            (conjugation, ids)
+        }).collect();
+        Inflector { forms }
+    }
+
+    /// Returns entries of conjugated forms of the entry in the base form.
+    /// Returns None if the conjugation type is unknown, or the reading or
+    /// the surface does not end with the ending of the type.
+    pub fn inflect(&self, conjugation: &str, base: &DictEntry) -> Option<Vec<DictEntry>> {
+        let ending = self::conjugation(conjugation)?.ending;
+        let reading = base.reading.strip_suffix(ending)?;
+        let surface = base.surface.strip_suffix(ending)?;
+        self.inflect_stem(conjugation, reading, surface, base)
+    }
+
+    /// Returns entries of conjugated forms made from the stem, other fields
+    /// are copied from `template`. Returns None if the conjugation type is unknown.
+    pub fn inflect_stem(&self, conjugation: &str, reading: &str, surface: &str, template: &DictEntry) -> Option<Vec<DictEntry>> {
+        let (_, ids) = self.forms.iter().find(|(c, _)| c.name == conjugation)?;
+        Some(ids.iter().map(|(suffix, id)| {
+            let mut entry = template.clone();
+            entry.reading = format!("{}{}", reading, suffix);
+            entry.surface = format!("{}{}", surface, suffix);
+            entry.left_id = *id;
+            entry.right_id = *id;
+            entry.inflected = true;
+            entry
+        }).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::sort_dedup_by_reading_surface;
+
+    /// Makes id.def of the forms, ids are given in the order.
+    fn table(forms: &[(&str, &str, &str)]) -> PosTable {
+        let lines: String = forms.iter().enumerate().map(|(i, (pos, conjugation, form))| {
+            format!("{} {},自立,*,*,{},{},*\n", i + 1, pos, conjugation, form)
+        }).collect();
+        PosTable::read_from(lines.as_bytes(), "id.def").unwrap()
+    }
+
+    fn words(entries: &[DictEntry]) -> Vec<(&str, &str, u16)> {
+        entries.iter().map(|e| (e.reading.as_str(), e.surface.as_str(), e.left_id)).collect()
+    }
+
+    #[test]
+    fn inflect_godan_ka_row() {
+        let table = table(&[
+            ("動詞", "五段・カ行イ音便", "基本形"),
+            ("動詞", "五段・カ行イ音便", "連用タ接続"),
+            ("動詞", "五段・カ行促音便", "基本形"),
+            ("動詞", "五段・カ行促音便", "連用タ接続"),
+        ]);
+        let inflector = Inflector::new(&table);
+
+        // 書く → 書い(た)
+        let base = DictEntry::new(String::from("かく"), 0, 6000, String::from("書く"), "test");
+        let entries = inflector.inflect("五段・カ行イ音便", &base).unwrap();
+        assert_eq!(words(&entries), [("かく", "書く", 1), ("かい", "書い", 2)]);
+        assert!(entries.iter().all(|e| e.right_id == e.left_id && e.cost == 6000));
+
+        // 行く → 行っ(た)
+        let base = DictEntry::new(String::from("いく"), 0, 6000, String::from("行く"), "test");
+        let entries = inflector.inflect("五段・カ行促音便", &base).unwrap();
+        assert_eq!(words(&entries), [("いく", "行く", 3), ("いっ", "行っ", 4)]);
+    }
+
+    #[test]
+    fn inflect_ichidan_and_adjective() {
+        let table = table(&[
+            ("動詞", "一段", "基本形"),
+            ("動詞", "一段", "未然形"),
+            ("形容詞", "形容詞・イ段", "基本形"),
+            ("形容詞", "形容詞・イ段", "連用テ接続"),
+        ]);
+        let inflector = Inflector::new(&table);
+
+        let base = DictEntry::new(String::from("たべる"), 0, 6000, String::from("食べる"), "test");
+        let entries = inflector.inflect("一段", &base).unwrap();
+        assert_eq!(words(&entries), [("たべる", "食べる", 1), ("たべ", "食べ", 2)]);
+
+        // 語幹から活用形を作る
+        let template = DictEntry::new(String::new(), 0, 6000, String::new(), "test");
+        let entries = inflector.inflect_stem("形容詞・イ段", "うつくし", "美し", &template).unwrap();
+        assert_eq!(words(&entries), [("うつくしい", "美しい", 3), ("うつくしく", "美しく", 4)]);
+    }
+
+    #[test]
+    fn inflect_rejects_unknown_type_and_ending() {
+        let inflector = Inflector::new(&table(&[("動詞", "五段・カ行イ音便", "基本形")]));
+        let base = DictEntry::new(String::from("かく"), 0, 6000, String::from("書く"), "test");
+        assert!(inflector.inflect("サ変・スル", &base).is_none());
+        assert!(inflector.inflect("五段・ガ行", &base).is_none());
+
+        // id.defにない型は活用形を作らない
+        let base = DictEntry::new(String::from("とる"), 0, 6000, String::from("取る"), "test");
+        assert_eq!(inflector.inflect("五段・ラ行", &base), Some(Vec::new()));
+    }
+
+    #[test]
+    fn forms_of_same_suffix_keep_their_ids() {
+        let table = table(&[
+            ("動詞", "一段", "基本形"),
+            ("動詞", "一段", "未然形"),
+            ("動詞", "一段", "連用形"),
+        ]);
+        let inflector = Inflector::new(&table);
+
+        // 食べ(ない) と 食べ(ます) は別のIDで残す
+        let base = DictEntry::new(String::from("たべる"), 0, 6000, String::from("食べる"), "test");
+        let mut entries = inflector.inflect("一段", &base).unwrap();
+        let mut noun = DictEntry::new(String::from("たべ"), 9, 6000, String::from("食べ"), "test");
+        entries.push(noun.clone());
+        noun.cost = 7000;
+        entries.push(noun);
+        sort_dedup_by_reading_surface(&mut entries);
+        assert_eq!(words(&entries), [("たべ", "食べ", 9), ("たべ", "食べ", 2), ("たべ", "食べ", 3), ("たべる", "食べる", 1)]);
+        assert!(entries.iter().all(|e| e.inflected == (e.left_id != 9)));
+    }
+
+    #[test]
+    fn adjective_conjugation_depends_on_vowel() {
+        assert_eq!(adjective_conjugation("うつくしい"), "形容詞・イ段");
+        assert_eq!(adjective_conjugation("おおきい"), "形容詞・イ段");
+        assert_eq!(adjective_conjugation("たかい"), "形容詞・アウオ段");
+        assert_eq!(adjective_conjugation("あかい"), "形容詞・アウオ段");
+    }
+}
@@ -1,128 +1,188 @@
-
 // Ported from calculate_costs.rb file.
 
-use std::io::{BufWriter, Write};
+use std::cmp::Ordering;
+use std::io::{BufRead, BufReader};
 use std::fs::File;
 
-use rayon::prelude::*;
-
-use super::util::*;
-
-
-#[derive(Clone)]
-struct Entry<'a> {
-    key: String,
-    original: &'a str,
-    reading: &'a str,
-    id1: &'a str,
-    id2: &'a str,
-    cost: &'a str,
-    writing: &'a str,
-    /// Cost in numeric value.
-    cost_num: i32,
-    wiki_title: bool,
-    remove_flag: bool,
+use serde::Deserialize;
+
+use super::entry::*;
+use super::error::{Result, WithPath};
+use super::extsort::{ExternalSorter, SortSettings, SortedEntries};
+use super::stats::{BuildStats, DropReason};
+
+
+/// Name of the file which keeps hit counts of jawiki titles.
+pub const JAWIKI_HITS_NAME: &str = "jawiki-latest-all-titles-in-ns0.hits";
+
+/// Parameters of cost calculation from hit counts of jawiki titles.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CostSettings {
+    /// Base cost of entries which do not hit any titles.
+    pub no_hit_base: i32,
+    /// Base cost of entries which hit a title once.
+    pub one_hit_base: i32,
+    /// Base cost of entries which hit titles twice or more.
+    pub hits_base: i32,
+    /// Cost subtracted for each hit.
+    pub hit_weight: i32,
+    /// Hit counts are capped at this value.
+    pub max_hits: i32,
+    /// Removes entries which consist of ASCII only and do not hit any titles.
+    pub remove_ascii_without_hits: bool,
 }
 
-pub fn calculate_costs(filename: &str, dicname: &str) -> std::io::Result<()> {
-    // jawikiの見出し語ヒット数を読み込む
-    // jawikititles	0	0	34	中居正広
-    let mut data = read_file("jawiki-latest-all-titles-in-ns0.hits")?;
-
-    // 追加辞書を加える
-    // なかいまさひろ	1916	1916	6477	中居正広
-    {
-        let add = read_file(filename)?;
-        if !data.ends_with('\n') {
-            data.push('\n');
+impl Default for CostSettings {
+    fn default() -> Self {
+        CostSettings {
+            no_hit_base: 8000,
+            one_hit_base: 7000,
+            hits_base: 6000,
+            hit_weight: 30,
+            max_hits: 30,
+            remove_ascii_without_hits: true,
         }
-        data.push_str(&add);
     }
+}
 
-    let mut entries: Vec<Entry> = data.trim_end().split("\n").map(|line| {
-        let mut ss = line.split('\t');
-        let mut entry = Entry {
-            key: String::new(),
-            original: line,
-            reading: ss.next().unwrap(),
-            id1: ss.next().unwrap(),
-            id2: ss.next().unwrap(),
-            cost: ss.next().unwrap(),
-            writing: ss.next().unwrap(),
-            cost_num: 0,
-            wiki_title: false,
-            remove_flag: false,
-        };
-        entry.key = format!("{}\t{}\t{}\t{}\t{}", entry.writing, entry.reading, entry.id1, entry.id2, entry.cost);
-        entry.cost_num = i32::from_str_radix(&entry.cost, 10).unwrap();
-        entry.wiki_title = entry.reading == "jawikititles";
-        entry
-    }).collect();
-
-    // jawikiヒット数の下に追加辞書が来るよう並べ替える
-    // 中居正広	jawikititles	0	0	34
-    // 中居正広	なかいまさひろ	1847	1847	5900
-    // 中居正広	なかいまさひろ	1917	1917	6477
-    entries.par_sort_unstable_by(|a, b| a.key.cmp(&b.key));
-
-    let mut jawiki = entries[0].clone();
-
-    for mut entry in entries.iter_mut() {
-        // jawikiの見出し語を取得
-        // 中居正広	jawikititles	0	0	34
-
-        if entry.wiki_title {
-            jawiki = entry.clone();
-
-            // jawikiの見出し語は後でまとめて削除
-            entry.remove_flag = true;
-
-            // jawikiのヒット数が大きいときは抑制
-            if jawiki.cost_num > 30 {
-                jawiki.cost_num = 30;
-            }
+/// Reads hit counts of jawiki titles written by `add_search_results_to_each_title`.
+/// The cost field of each entry keeps the hit count.
+pub fn read_jawiki_hits_from<R: BufRead>(reader: R) -> Result<Vec<DictEntry>> {
+    // jawikiの見出し語ヒット数を読み込む
+    // jawikititles	0	0	34	中居正広
+    read_entries_from(reader, "jawikititles")
+}
 
-            continue;
-        }
+/// Reads hit counts of jawiki titles from the file.
+pub fn read_jawiki_hits(filename: &str) -> Result<Vec<DictEntry>> {
+    read_jawiki_hits_from(BufReader::new(File::open(filename).with_path(filename)?)).with_path(filename)
+}
 
+/// Returns the new cost of the entry from the hit count of its surface in jawiki titles,
+/// None if the entry is removed. The penalty of the entry is added as it is.
+fn cost_for(entry: &DictEntry, hits: Option<i32>, settings: &CostSettings) -> Option<i32> {
+    let cost = match hits {
         // jawikiの見出し語にヒットしない英数字のみの表記は除外
-        if entry.writing != jawiki.writing {
-            if entry.writing.chars().count() == entry.writing.len() {
-                entry.remove_flag = true;
-                continue;
-            }
-
-            // jawikiの見出し語にヒットしない表記はコストのベースを8000にする
-            // コスト = 8000 + (元のコスト値/10)
-            entry.cost_num = 8000 + entry.cost_num / 10;
-            continue;
-        }
-
+        None if settings.remove_ascii_without_hits &&
+                entry.surface.chars().count() == entry.surface.len() => None,
+        // jawikiの見出し語にヒットしない表記はコストのベースを8000にする
+        // コスト = 8000 + (元のコスト値/10)
+        None => Some(settings.no_hit_base + entry.cost / 10),
         // jawikiの見出し語に1回ヒットする表記はコストのベースを7000にする
         // 中居正広	なかいまさひろ	1917	1917	6477
         // コスト値 = 7000 + (元のコスト値/10)
-        if jawiki.cost_num == 1 {
-            entry.cost_num = 7000 + entry.cost_num / 10;
-            continue;
-        }
-
+        Some(1) => Some(settings.one_hit_base + entry.cost / 10),
         // jawikiの見出し語に2回以上ヒットする表記はコストのベースを6000にする
         // コスト = 6000 + (元のコスト値/10) - (ヒット数*30)
-        entry.cost_num = 6000 + entry.cost_num / 10 - jawiki.cost_num * 30;
+        Some(hits) => Some(settings.hits_base + entry.cost / 10 - hits * settings.hit_weight),
+    };
+    // 別の読みなどのペナルティは元のコスト値を10で割らずに加える
+    cost.map(|cost| cost + entry.penalty)
+}
+
+/// Returns the formula which calculates the cost of the entry.
+fn explain_cost(entry: &DictEntry, hits: Option<i32>, cost: i32, settings: &CostSettings) -> String {
+    let formula = match hits {
+        None => format!("no jawiki title, cost {} = {} + {} / 10", cost, settings.no_hit_base, entry.cost),
+        Some(1) => format!("1 jawiki title, cost {} = {} + {} / 10", cost, settings.one_hit_base, entry.cost),
+        Some(hits) => format!("{} jawiki titles, cost {} = {} + {} / 10 - {} * {}",
+            hits, cost, settings.hits_base, entry.cost, hits, settings.hit_weight),
+    };
+    if entry.penalty == 0 {
+        formula
+    } else {
+        format!("{} + {} penalty", formula, entry.penalty)
     }
+}
 
-    // Mozc形式の並びに戻す
-    entries.par_sort_unstable_by(|a, b| a.original.cmp(&b.original));
+fn surface_order(a: &DictEntry, b: &DictEntry) -> Ordering {
+    a.surface.cmp(&b.surface)
+}
 
-    let f = File::create(dicname)?;
-    let mut writer = BufWriter::new(f);
+/// Recalculates costs of entries with hit counts of jawiki titles.
+/// Entries and titles are sorted by surfaces and joined, then entries are
+/// returned in the order of Mozc dictionary. The memory budget of `sort` is
+/// shared by the three sorters.
+/// Entries which consist of ASCII only and do not hit any titles are removed
+/// unless disabled in the settings, and counted in `stats`.
+pub fn calculate_costs_stream<I, T>(entries: I, titles: T, settings: &CostSettings, sort: &SortSettings, stats: &mut BuildStats) -> Result<SortedEntries>
+    where I: IntoIterator<Item = Result<DictEntry>>,
+          T: IntoIterator<Item = Result<DictEntry>> {
+    // 3つの並べ替えを同時に行うので予算を分ける
+    let sort = &sort.split(3);
+
+    // jawikiの見出し語とエントリを表記の順に並べ替えて突き合わせる
+    let mut sorter = ExternalSorter::new(sort, surface_order);
     for entry in entries {
-        if !entry.remove_flag {
-            let l = format!("{}\t{}\t{}\t{}\t{}\n",
-                entry.reading, entry.id1, entry.id2, entry.cost_num, entry.writing);
-            writer.write(l.as_bytes())?;
+        sorter.push(entry?)?;
+    }
+    let mut title_sorter = ExternalSorter::new(sort, surface_order);
+    for title in titles {
+        title_sorter.push(title?)?;
+    }
+    let mut titles = title_sorter.finish()?;
+    let mut title = titles.next().transpose()?;
+
+    // Mozc形式の並びにする
+    let mut output = ExternalSorter::new(sort, <DictEntry as Ord>::cmp);
+    for entry in sorter.finish()? {
+        let mut entry = entry?;
+        // jawikiの見出し語を取得
+        // 中居正広	jawikititles	0	0	34
+        while title.as_ref().is_some_and(|title| title.surface < entry.surface) {
+            title = titles.next().transpose()?;
+        }
+        let hits = title.as_ref()
+            .filter(|title| title.surface == entry.surface)
+            // jawikiのヒット数が大きいときは抑制
+            .map(|title| title.cost.min(settings.max_hits));
+
+        match cost_for(&entry, hits, settings) {
+            Some(cost) => {
+                stats.explain(&entry, &explain_cost(&entry, hits, cost, settings));
+                entry.cost = cost;
+                entry.penalty = 0;
+                output.push(entry)?;
+            }
+            None => stats.drop(&entry, DropReason::NoTitle, "surface consists of ASCII only and hits no jawiki title"),
         }
     }
 
-    Ok(())
+    output.finish()
+}
+
+/// Recalculates costs of entries in memory with hit counts of jawiki titles.
+/// Removed entries are counted in `stats`.
+pub fn calculate_costs(entries: Vec<DictEntry>, titles: Vec<DictEntry>, settings: &CostSettings, stats: &mut BuildStats) -> Result<Vec<DictEntry>> {
+    calculate_costs_stream(entries.into_iter().map(Ok), titles.into_iter().map(Ok), settings, &SortSettings::in_memory(), stats)?.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn penalty_is_added_after_recalculation() {
+        let settings = CostSettings::default();
+        let entry = DictEntry::new(String::from("にほん"), 1, 6000, String::from("日本"), "jawiki");
+        let alternate = DictEntry::new(String::from("にっぽん"), 1, 6000, String::from("日本"), "jawiki").with_penalty(1000);
+
+        assert_eq!(cost_for(&entry, Some(30), &settings), Some(6000 + 600 - 900));
+        assert_eq!(cost_for(&alternate, Some(30), &settings), Some(6000 + 600 - 900 + 1000));
+        assert_eq!(cost_for(&alternate, None, &settings), Some(8000 + 600 + 1000));
+        assert!(explain_cost(&alternate, None, 9600, &settings).ends_with("+ 1000 penalty"));
+    }
+
+    #[test]
+    fn penalty_survives_external_sort() {
+        let mut entry = DictEntry::new(String::from("にっぽん"), 1, 6000, String::from("日本"), "jawiki").with_penalty(1000);
+        entry.record = 3;
+        let mut line = Vec::new();
+        write_entry_with_origins(&mut line, &entry).unwrap();
+        let parsed = parse_entry_with_origins(std::str::from_utf8(&line).unwrap()).unwrap();
+        assert_eq!(parsed, entry);
+        assert_eq!(parsed.penalty, 1000);
+        assert_eq!(parsed.record, 3);
+    }
 }
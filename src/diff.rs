@@ -0,0 +1,298 @@
+// Comparison of two dictionaries built by the workflow.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use super::archive::read_tar_bz2_file;
+use super::entry::{entries_from, DictEntry};
+use super::error::{Error, Result, WithPath};
+use super::util::intern;
+
+
+/// Source of entries whose provenance is not known.
+pub const UNKNOWN_SOURCE: &str = "unknown";
+
+/// Settings of grouping and flagging changes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiffSettings {
+    /// Number of leading characters of readings which make a group.
+    pub prefix_len: usize,
+    /// Cost changes whose absolute value is this or larger are listed.
+    pub threshold: i32,
+    /// Lists every added, removed and cost changed entry.
+    pub list: bool,
+}
+
+impl Default for DiffSettings {
+    fn default() -> Self {
+        DiffSettings {
+            prefix_len: 1,
+            threshold: 1000,
+            list: false,
+        }
+    }
+}
+
+/// Returns true if the path in the archive is a dictionary file written by the workflow.
+fn is_dictionary_file(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.starts_with("mozcdic-ut") && name.ends_with(".txt")
+}
+
+/// Reads entries of the dictionary file in Mozc format, or of the dictionary
+/// file in the tar.bz2 archive. Entries are tagged with their line numbers.
+pub fn read_dictionary(path: &str) -> Result<Vec<DictEntry>> {
+    if path.ends_with(".tar.bz2") {
+        let (_, content) = read_tar_bz2_file(path, is_dictionary_file).with_path(path)?
+            .ok_or_else(|| Error::other(format!("{}: no dictionary file in the archive", path)))?;
+        entries_from(&content[..], UNKNOWN_SOURCE).collect::<Result<_>>().with_path(path)
+    } else {
+        entries_from(BufReader::new(File::open(path).with_path(path)?), UNKNOWN_SOURCE)
+            .collect::<Result<_>>().with_path(path)
+    }
+}
+
+/// Reads the provenance sidecar file and tags entries with the first source
+/// which contributed to their lines.
+pub fn read_provenance_sources<P: AsRef<Path>>(path: P, entries: &mut [DictEntry]) -> Result<()> {
+    let path = path.as_ref();
+    let mut sources = HashMap::new();
+    for line in BufReader::new(File::open(path).with_path(path)?).lines() {
+        let line = line.with_path(path)?;
+        // 3	sudachidict:20	neologd:10
+        let mut fields = line.split('\t');
+        if let (Some(no), Some(origin)) = (fields.next().and_then(|no| no.parse::<u32>().ok()), fields.next()) {
+            let source = origin.split_once(':').map_or(origin, |(source, _)| source);
+            sources.insert(no, intern(source));
+        }
+    }
+    for entry in entries {
+        if let Some(source) = sources.get(&entry.record) {
+            entry.source = source;
+        }
+    }
+    Ok(())
+}
+
+/// Change of an entry between two dictionaries.
+#[derive(Clone, Debug)]
+pub enum Change {
+    /// Entry which is only in the new dictionary.
+    Added(DictEntry),
+    /// Entry which is only in the old dictionary.
+    Removed(DictEntry),
+    /// Entry which has the same reading, ids and surface but a different cost.
+    Cost { old: DictEntry, new: DictEntry },
+}
+
+impl Change {
+    /// Returns the entry in the new dictionary, or in the old one if removed.
+    pub fn entry(&self) -> &DictEntry {
+        match self {
+            Change::Added(entry) | Change::Removed(entry) => entry,
+            Change::Cost { new, .. } => new,
+        }
+    }
+
+    /// Returns new cost minus old cost, 0 if added or removed.
+    pub fn cost_delta(&self) -> i32 {
+        match self {
+            Change::Cost { old, new } => new.cost - old.cost,
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Added(entry) => write!(f, "+ {}", entry),
+            Change::Removed(entry) => write!(f, "- {}", entry),
+            Change::Cost { old, new } => write!(f, "~ {}\t{}\t{}\t{} -> {}\t{}",
+                new.reading, new.left_id, new.right_id, old.cost, new.cost, new.surface),
+        }
+    }
+}
+
+/// Counts of changes in a group.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GroupCounts {
+    pub added: usize,
+    pub removed: usize,
+    /// Entries whose costs are raised, which means lower priority.
+    pub raised: usize,
+    /// Entries whose costs are lowered, which means higher priority.
+    pub lowered: usize,
+}
+
+impl GroupCounts {
+    fn add(&mut self, change: &Change) {
+        match change {
+            Change::Added(_) => self.added += 1,
+            Change::Removed(_) => self.removed += 1,
+            Change::Cost { .. } if change.cost_delta() > 0 => self.raised += 1,
+            Change::Cost { .. } => self.lowered += 1,
+        }
+    }
+}
+
+/// Differences between two dictionaries.
+#[derive(Clone, Debug, Default)]
+pub struct DictDiff {
+    /// Changes in the order of reading, ids and surface.
+    pub changes: Vec<Change>,
+    /// Number of entries which are the same in both dictionaries.
+    pub unchanged: usize,
+}
+
+fn diff_key(entry: &DictEntry) -> (&str, &str, u16, u16) {
+    (&entry.reading, &entry.surface, entry.left_id, entry.right_id)
+}
+
+/// Compares entries by reading, surface and ids, and finds cost changes.
+pub fn diff_entries(mut old: Vec<DictEntry>, mut new: Vec<DictEntry>) -> DictDiff {
+    old.sort_unstable_by(|a, b| (diff_key(a), a.cost).cmp(&(diff_key(b), b.cost)));
+    new.sort_unstable_by(|a, b| (diff_key(a), a.cost).cmp(&(diff_key(b), b.cost)));
+
+    let mut diff = DictDiff::default();
+    let mut old = old.into_iter().peekable();
+    let mut new = new.into_iter().peekable();
+    loop {
+        let order = match (old.peek(), new.peek()) {
+            (Some(a), Some(b)) => diff_key(a).cmp(&diff_key(b)),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => break,
+        };
+        match order {
+            std::cmp::Ordering::Less => diff.changes.push(Change::Removed(old.next().unwrap())),
+            std::cmp::Ordering::Greater => diff.changes.push(Change::Added(new.next().unwrap())),
+            std::cmp::Ordering::Equal => {
+                let (old, new) = (old.next().unwrap(), new.next().unwrap());
+                if old.cost == new.cost {
+                    diff.unchanged += 1;
+                } else {
+                    diff.changes.push(Change::Cost { old, new });
+                }
+            }
+        }
+    }
+    diff
+}
+
+impl DictDiff {
+    /// Counts changes grouped by the key.
+    pub fn group_by<K: Ord, F: Fn(&Change) -> K>(&self, key: F) -> BTreeMap<K, GroupCounts> {
+        let mut groups = BTreeMap::new();
+        for change in &self.changes {
+            groups.entry(key(change)).or_insert_with(GroupCounts::default).add(change);
+        }
+        groups
+    }
+
+    /// Returns report of the differences formatted with the settings.
+    pub fn report<'a>(&'a self, settings: &'a DiffSettings) -> DiffReport<'a> {
+        DiffReport { diff: self, settings }
+    }
+}
+
+/// Report of differences grouped by reading prefix, id and source.
+#[derive(Debug)]
+pub struct DiffReport<'a> {
+    diff: &'a DictDiff,
+    settings: &'a DiffSettings,
+}
+
+fn write_groups<K: fmt::Display>(f: &mut fmt::Formatter, title: &str, groups: &BTreeMap<K, GroupCounts>) -> fmt::Result {
+    writeln!(f, "\nby {}:", title)?;
+    writeln!(f, "  {:<14} {:>9} {:>9} {:>9} {:>9}", title, "added", "removed", "raised", "lowered")?;
+    for (key, counts) in groups {
+        writeln!(f, "  {:<14} {:>9} {:>9} {:>9} {:>9}", key.to_string(), counts.added, counts.removed, counts.raised, counts.lowered)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for DiffReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.diff.group_by(|_| ());
+        let total = total.get(&()).cloned().unwrap_or_default();
+        writeln!(f, "{} added, {} removed, {} raised, {} lowered, {} unchanged",
+            total.added, total.removed, total.raised, total.lowered, self.diff.unchanged)?;
+
+        let prefix_len = self.settings.prefix_len;
+        write_groups(f, "reading prefix", &self.diff.group_by(|change| {
+            change.entry().reading.chars().take(prefix_len).collect::<String>()
+        }))?;
+        write_groups(f, "id", &self.diff.group_by(|change| change.entry().left_id))?;
+        write_groups(f, "source", &self.diff.group_by(|change| change.entry().source))?;
+
+        let threshold = self.settings.threshold;
+        let large = self.diff.changes.iter().filter(|change| change.cost_delta().abs() >= threshold).collect::<Vec<_>>();
+        if !large.is_empty() {
+            writeln!(f, "\ncost changes of {} or more:", threshold)?;
+            for change in large {
+                writeln!(f, "  {} ({:+})", change, change.cost_delta())?;
+            }
+        }
+
+        if self.settings.list {
+            writeln!(f, "\nchanges:")?;
+            for change in &self.diff.changes {
+                writeln!(f, "  {}", change)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dictionary(text: &str) -> Vec<DictEntry> {
+        entries_from(text.as_bytes(), UNKNOWN_SOURCE).collect::<Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn classify_changes() {
+        let old = dictionary("\
+            あいこ\t1\t1\t7000\t愛子\n\
+            かとう\t1\t1\t6000\t加藤\n\
+            きしゃ\t1\t1\t6500\t記者\n\
+            さとう\t1\t1\t6000\t佐藤\n");
+        let new = dictionary("\
+            あいこ\t1\t1\t7000\t愛子\n\
+            かとう\t1\t1\t7500\t加藤\n\
+            きしゃ\t1\t1\t6000\t記者\n\
+            さとう\t2\t2\t6000\t佐藤\n\
+            すずき\t1\t1\t6000\t鈴木\n");
+        let diff = diff_entries(old, new);
+        assert_eq!(diff.unchanged, 1);
+
+        let changes = diff.changes.iter().map(|change| (change.to_string(), change.cost_delta())).collect::<Vec<_>>();
+        assert_eq!(changes, [
+            (String::from("~ かとう\t1\t1\t6000 -> 7500\t加藤"), 1500),
+            (String::from("~ きしゃ\t1\t1\t6500 -> 6000\t記者"), -500),
+            // IDが変われば別のエントリ
+            (String::from("- さとう\t1\t1\t6000\t佐藤"), 0),
+            (String::from("+ さとう\t2\t2\t6000\t佐藤"), 0),
+            (String::from("+ すずき\t1\t1\t6000\t鈴木"), 0),
+        ]);
+
+        let groups = diff.group_by(|change| change.entry().reading.chars().next().unwrap());
+        assert_eq!(groups[&'か'], GroupCounts { raised: 1, ..GroupCounts::default() });
+        assert_eq!(groups[&'き'], GroupCounts { lowered: 1, ..GroupCounts::default() });
+        assert_eq!(groups[&'さ'], GroupCounts { added: 1, removed: 1, ..GroupCounts::default() });
+        assert_eq!(groups[&'す'], GroupCounts { added: 1, ..GroupCounts::default() });
+        assert!(!groups.contains_key(&'あ'));
+
+        // しきい値以上のコスト変更だけを挙げる
+        let report = diff.report(&DiffSettings::default()).to_string();
+        assert!(report.starts_with("2 added, 1 removed, 1 raised, 1 lowered, 1 unchanged\n"));
+        assert!(report.contains("加藤 (+1500)"));
+        assert!(!report.contains("記者 (-500)"));
+    }
+}
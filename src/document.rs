@@ -5,17 +5,19 @@ use std::io::{BufWriter, Write};
 use std::fs::File;
 
 use regex::*;
+use sha2::{Digest, Sha256};
 
+use super::error::{Result, WithPath};
 use super::util::*;
 
 
-pub fn update_documents() -> std::io::Result<()> {
+pub fn update_documents() -> Result<()> {
     const README_NAME: &str = "../README.md";
 
     // Get date, mozcver, utdicdate, sha256sum
 
     // Get date
-    let date = command_wait_output("date", vec!["+\"%Y-%m-%d\""])?;
+    let date = today();
 
     // Get mozcver
     let mozcver = {
@@ -45,9 +47,11 @@ pub fn update_documents() -> std::io::Result<()> {
 
     // Get sha256sum of Mozc
     let sha256 = {
-        let arg = format!("mozc-{}.tar.bz2", mozcver);
-        let v = command_wait_output("sha256sum", vec![arg.as_str()])?;
-        String::from(v.split(" ").next().unwrap())
+        let name = format!("mozc-{}.tar.bz2", mozcver);
+        let mut f = File::open(&name).with_path(&name)?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut f, &mut hasher).with_path(&name)?;
+        hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect::<String>()
     };
     println!("mozc sha256sum = {}", sha256);
 
@@ -69,7 +73,7 @@ pub fn update_documents() -> std::io::Result<()> {
 
         let f = File::create(README_NAME)?;
         let mut writer = BufWriter::new(f);
-        writer.write(lines.as_bytes())?;
+        writer.write_all(lines.as_bytes())?;
     }
 
     // Update PKGBUILD
@@ -101,7 +105,7 @@ pub fn update_documents() -> std::io::Result<()> {
 
         let f = File::create(&newfile)?;
         let mut writer = BufWriter::new(f);
-        writer.write(lines.as_bytes())?;
+        writer.write_all(lines.as_bytes())?;
     }
 
     Ok(())
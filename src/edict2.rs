@@ -1,91 +1,217 @@
 
 // Ported from convert_edict2_to_mozcdic.rb file.
 
-use std::io::{BufWriter, Write};
+use std::io::Read;
 use std::fs::File;
+use std::path::PathBuf;
 
 use encoding_rs::*;
 use regex::*;
-use rayon::prelude::*;
 
-use super::mozc::get_id;
+use super::archive::*;
+use super::conjugation::{adjective_conjugation, Inflector};
+use super::entry::{sort_dedup, DictEntry};
+use super::error::{Result, SkippedLines, WithPath};
+use super::license::License;
+use super::pos::{pos_table, PosMap, PosTable};
+use super::fetch::Fetcher;
+use super::source::Source;
+use super::stats::SourceStats;
 use super::util::*;
 
 
-fn convert_edict2_to_mozcdic(filename: &str, dicname: &str) -> std::io::Result<()> {
-    // Mozcの品詞IDを取得
-    // 「名詞,固有名詞,人名,一般,*,*」は優先度が低いので使わない。
-    // 「名詞,固有名詞,一般,*,*,*」は後でフィルタリングする。
-    let id = get_id(r"(\d*) 名詞,一般,\*,\*,\*,\*,\*")?;
+const NAME: &str = "edict2";
+const FILE_NAME: &str = "edict2";
+
+/// Tags of nouns in edict2 and their Mozc parts of speech.
+/// Others are 名詞,一般.
+const POS_RULES: &[(&str, &str)] = &[
+    ("n-adv", "名詞,副詞可能"),
+    ("n-t", "名詞,副詞可能"),
+    ("n-suf", "名詞,接尾,一般"),
+    ("n-pref", "接頭詞,名詞接続"),
+    ("n-pr", "名詞,固有名詞,一般"),
+];
+
+/// Returns mapping from noun tags of edict2 to ids in the table.
+pub fn pos_map(table: &PosTable) -> Result<PosMap> {
+    PosMap::new(table, POS_RULES, "名詞,一般")
+}
+
+/// Tags of verbs and adjectives in edict2 and their IPADIC conjugation types.
+const CONJUGATION_RULES: &[(&str, &str)] = &[
+    ("v5k", "五段・カ行イ音便"),
+    ("v5k-s", "五段・カ行促音便"),
+    ("v5g", "五段・ガ行"),
+    ("v5s", "五段・サ行"),
+    ("v5t", "五段・タ行"),
+    ("v5n", "五段・ナ行"),
+    ("v5b", "五段・バ行"),
+    ("v5m", "五段・マ行"),
+    ("v5r", "五段・ラ行"),
+    ("v5u", "五段・ワ行促音便"),
+    ("v1", "一段"),
+];
+
+/// Parses EUC-JP encoded edict2 dictionary read from the reader.
+/// Nouns are tagged with Mozc ids mapped from their tags, and verbs and adjectives
+/// are expanded into conjugated forms by `inflector`. Skipped records are counted in `skipped`.
+pub fn parse_edict2<R: Read>(mut reader: R, pos: &PosMap, inflector: &Inflector, skipped: &mut SkippedLines) -> Result<Vec<DictEntry>> {
     let re = Regex::new(r"[ ・=]").unwrap();
 
-    let buf = read_file_vec(filename)?;
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
     let mut l2 = Vec::new();
     let (cow, _encoding_used, _had_errors) = EUC_JP.decode(&buf);
-    for line in cow.lines() {
+    for (line_no, line) in cow.lines().enumerate() {
         // 全角スペースで始まるエントリはスキップ
         if line.starts_with("　") {
+            skipped.rule("starts with ideographic space", line_no + 1, line);
             continue;
         }
 
-        // 名詞のみを収録
-        if let Some((s, _)) = line.split_once(" /(n") {
-            // 表記と読みに分ける。表記または読みが複数あるときはそれぞれ最初のものを採用する
-            // 脇見(P);わき見;傍視 [わきみ(P);ぼうし(傍視)] /
-            let (hyouki, yomi) = if let Some((prefix, suffix)) = s.split_once(" [") {
-                let hyouki = prefix.split(";").next().unwrap();
-                let yomi = suffix.split(";").next().unwrap();
-                (String::from(hyouki), yomi.replace("]", ""))
-            } else {
-                // カタカナ語には読みがないので表記から読みを作る
-                // ブラスバンド(P);ブラス・バンド /(n) brass band/
-                let hyouki = s.split(";").next().unwrap();
-                (String::from(hyouki), String::from(hyouki))
-            };
-
-            let hyouki = hyouki.split("(").next().unwrap();
-            let mut yomi = String::from(yomi.split("(").next().unwrap());
-            match re.replace_all(&yomi, |_caps: &Captures| {
-                String::new() // replace with empty string
-            }) {
-                std::borrow::Cow::Owned(r) => yomi = r,
-                _ => {},
-            };
-
-            // 読みのカタカナをひらがなに変換
-            yomi = to_hiragana_replace_ie(&yomi);
-
-            l2.push(format!("{}\t{}\t{}\t6000\t{}\n", &yomi, id, id, hyouki));
+        // 名詞と動詞、形容詞を収録
+        // 品詞は最初の括弧に「,」で区切って書かれる
+        // 書く [かく] /(v5k,vt) to write/
+        // 「n」の他に「n-adv」「n-t」「n-suf」「n-pref」「n-pr」も名詞として品詞IDを割り当てる
+        // 「n」があれば「名詞,一般」にする
+        // 無地 [むじ] /(adj-no,n) plain/
+        // 毎日 [まいにち] /(n-adv,n-t) every day/
+        let (s, tags) = line.split_once(" /(").unwrap_or((line, ""));
+        let tags = tags.split(')').next().unwrap_or_default();
+        let noun = tags.split(',').find(|tag| *tag == "n")
+            .or_else(|| tags.split(',').find(|tag| tag.starts_with("n-")));
+        let conjugation = tags.split(',').find_map(|tag| {
+            CONJUGATION_RULES.iter().find(|(edict, _)| *edict == tag).map(|(_, conjugation)| *conjugation)
+                .or(if tag == "adj-i" { Some("形容詞") } else { None })
+        });
+        if noun.is_none() && conjugation.is_none() {
+            skipped.rule("not a noun, verb or adjective", line_no + 1, line);
+            continue;
         }
-    }
 
-    let mut lines = l2;
+        // 表記と読みに分ける。表記または読みが複数あるときはそれぞれ最初のものを採用する
+        // 脇見(P);わき見;傍視 [わきみ(P);ぼうし(傍視)] /
+        let (hyouki, yomi) = if let Some((prefix, suffix)) = s.split_once(" [") {
+            let hyouki = prefix.split(';').next().unwrap();
+            let yomi = suffix.split(';').next().unwrap();
+            (String::from(hyouki), yomi.replace("]", ""))
+        } else {
+            // カタカナ語には読みがないので表記から読みを作る
+            // ブラスバンド(P);ブラス・バンド /(n) brass band/
+            let hyouki = s.split(';').next().unwrap();
+            (String::from(hyouki), String::from(hyouki))
+        };
+
+        let hyouki = hyouki.split('(').next().unwrap();
+        let mut yomi = String::from(yomi.split('(').next().unwrap());
+        if let std::borrow::Cow::Owned(r) = re.replace_all(&yomi, |_caps: &Captures| {
+            String::new() // replace with empty string
+        }) {
+            yomi = r;
+        }
 
-    // 重複行を削除
-    lines.par_sort_unstable();
-    lines.dedup();
+        // 読みのカタカナをひらがなに変換
+        yomi = to_hiragana_replace_ie(&yomi);
 
-    let f = File::create(dicname)?;
-    let mut writer = BufWriter::new(f);
-    for line in lines {
-        writer.write(line.as_bytes())?;
+        if let Some(tag) = noun {
+            l2.push(DictEntry::new(yomi, pos.id(&[tag]), 6000, String::from(hyouki), NAME).with_record(line_no + 1));
+            continue;
+        }
+        let entry = DictEntry::new(yomi, pos.id(&[]), 6000, String::from(hyouki), NAME).with_record(line_no + 1);
+
+        // 形容詞の活用型は読みから決める
+        let conjugation = match conjugation {
+            Some("形容詞") => adjective_conjugation(&entry.reading),
+            conjugation => conjugation.unwrap_or_default(),
+        };
+        match inflector.inflect(conjugation, &entry) {
+            Some(mut entries) => l2.append(&mut entries),
+            None => skipped.rule("conjugation is not supported", line_no + 1, line),
+        }
     }
 
-    Ok(())
+    Ok(l2)
 }
 
-pub fn run_convert_edict2_to_mozcdic() -> std::io::Result<()> {
-    const FILE_NAME: &str = "edict2";
-    const DIC_NAME: &str = "mozcdic-ut-edict2.txt";
+/// Converts edict2 dictionary.
+#[derive(Debug, Default)]
+pub struct Edict2;
+
+impl Source for Edict2 {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn license(&self) -> License {
+        License::CcBySa3
+    }
+
+    fn fetch(&mut self, fetcher: &Fetcher) -> Result<()> {
+        let gz_name = format!("{}.gz", FILE_NAME);
+        let addr = format!("http://ftp.edrdg.org/pub/Nihongo/{}", &gz_name);
 
-    let gz_name = format!("{}.gz", FILE_NAME);
-    let addr = format!("http://ftp.edrdg.org/pub/Nihongo/{}", &gz_name);
+        let gz_path = fetcher.fetch(&addr, &gz_name, false)?;
+        gunzip(&gz_path, FILE_NAME)?;
 
-    command_wait("rm", vec!["-f", FILE_NAME])?;
-    command_wait("wget", vec!["-N", "-q", &addr])?;
-    command_wait("gzip", vec!["-dk", &gz_name])?;
+        Ok(())
+    }
 
-    convert_edict2_to_mozcdic(FILE_NAME, DIC_NAME)?;
+    fn inputs(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from(FILE_NAME)]
+    }
 
-    Ok(())
+    fn version(&self) -> u32 {
+        5
+    }
+
+    fn parse(&self, stats: &mut SourceStats) -> Result<Vec<DictEntry>> {
+        // Mozcの品詞IDを取得
+        // 「名詞,固有名詞,人名,一般,*,*」は優先度が低いので使わない。
+        // 「名詞,固有名詞,一般,*,*,*」は後でフィルタリングする。
+        let table = pos_table()?;
+        let pos = pos_map(&table)?;
+        let inflector = Inflector::new(&table);
+
+        let mut skipped = stats.skipped_lines(NAME, FILE_NAME);
+        let entries = parse_edict2(File::open(FILE_NAME).with_path(FILE_NAME)?, &pos, &inflector, &mut skipped).with_path(FILE_NAME)?;
+        skipped.report();
+        stats.add_skipped(&skipped);
+        Ok(entries)
+    }
+
+    fn convert(&self, mut entries: Vec<DictEntry>) -> Result<Vec<DictEntry>> {
+        // 重複行を削除
+        sort_dedup(&mut entries);
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_edict2_maps_noun_tags() {
+        let lines = "1 名詞,一般,*,*,*,*,*\n\
+                     2 名詞,副詞可能,*,*,*,*,*\n\
+                     3 名詞,接尾,一般,*,*,*,*\n\
+                     4 接頭詞,名詞接続,*,*,*,*,*\n\
+                     5 名詞,固有名詞,一般,*,*,*,*\n";
+        let pos = pos_map(&PosTable::read_from(lines.as_bytes(), "id.def").unwrap()).unwrap();
+
+        let text = "無地 [むじ] /(adj-no,n) plain/\n\
+                    毎日 [まいにち] /(n-adv,n-t) every day/\n\
+                    過ぎ [すぎ] /(n-suf) past/\n\
+                    ブラスバンド /(n) brass band/\n\
+                    綺麗 [きれい] /(adj-na) pretty/\n";
+        let (bytes, _, _) = EUC_JP.encode(text);
+        let mut skipped = SkippedLines::new(NAME, FILE_NAME);
+        let entries = parse_edict2(&bytes[..], &pos, &Inflector::default(), &mut skipped).unwrap();
+
+        let words: Vec<(&str, &str, u16)> = entries.iter().map(|e| (e.reading.as_str(), e.surface.as_str(), e.left_id)).collect();
+        assert_eq!(words, [("むじ", "無地", 1), ("まいにち", "毎日", 2), ("すぎ", "過ぎ", 3), ("ぶらすばんど", "ブラスバンド", 1)]);
+        assert_eq!(skipped.rules().get("not a noun, verb or adjective"), Some(&1));
+    }
 }
@@ -0,0 +1,342 @@
+// Typed dictionary entry shared by converters, filters and cost calculation.
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::fs::File;
+
+use super::error::{Result, WithPath};
+use super::license::License;
+use super::util::intern;
+
+
+/// Source and record which an entry came from.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Origin {
+    /// Name of the source.
+    pub source: &'static str,
+    /// Line or record number in the file read by the source, 0 if unknown.
+    pub record: u32,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.record == 0 {
+            write!(f, "{}", self.source)
+        } else {
+            write!(f, "{}:{}", self.source, self.record)
+        }
+    }
+}
+
+/// Entry of Mozc dictionary.
+///
+/// In Mozc format, an entry is written as a line of
+/// `reading\tleft_id\tright_id\tcost\tsurface`.
+/// Comparison uses these fields only, provenance is ignored.
+#[derive(Clone, Debug)]
+pub struct DictEntry {
+    /// Reading in hiragana.
+    pub reading: String,
+    /// Left context id defined in id.def.
+    pub left_id: u16,
+    /// Right context id defined in id.def.
+    pub right_id: u16,
+    /// Cost, smaller value has higher priority.
+    pub cost: i32,
+    /// Surface form of the word.
+    pub surface: String,
+    /// Name of the source which the entry came from.
+    pub source: &'static str,
+    /// Line or record number in the file read by the source, 0 if unknown.
+    pub record: u32,
+    /// Origins of duplicated entries merged into this entry.
+    pub merged: Vec<Origin>,
+    /// License of the entry, None if unknown such as entries read from a file.
+    pub license: Option<License>,
+    /// Cost added after the cost is recalculated from hit counts of jawiki
+    /// titles, which ranks the entry below others of the same source, such
+    /// as alternate readings.
+    pub penalty: i32,
+    /// True if the entry is a conjugated form made by `conjugation::Inflector`,
+    /// whose ids tell the form apart from others of the same reading and surface.
+    pub inflected: bool,
+}
+
+impl DictEntry {
+    /// Creates entry which has the same left and right id.
+    pub fn new(reading: String, id: u16, cost: i32, surface: String, source: &'static str) -> DictEntry {
+        DictEntry {
+            reading,
+            left_id: id,
+            right_id: id,
+            cost,
+            surface,
+            source,
+            record: 0,
+            merged: Vec::new(),
+            license: None,
+            penalty: 0,
+            inflected: false,
+        }
+    }
+
+    /// Sets cost added after the cost is recalculated.
+    pub fn with_penalty(mut self, penalty: i32) -> DictEntry {
+        self.penalty = penalty;
+        self
+    }
+
+    /// Sets line or record number in the file read by the source.
+    pub fn with_record(mut self, record: usize) -> DictEntry {
+        self.record = u32::try_from(record).unwrap_or(0);
+        self
+    }
+
+    /// Returns the origin of this entry.
+    pub fn origin(&self) -> Origin {
+        Origin {
+            source: self.source,
+            record: self.record,
+        }
+    }
+
+    /// Returns the origin of this entry followed by origins of merged entries.
+    pub fn origins(&self) -> impl Iterator<Item = Origin> + '_ {
+        std::iter::once(self.origin()).chain(self.merged.iter().copied())
+    }
+
+    /// Merges origins of the duplicated entry which is removed.
+    pub fn merge_origins(&mut self, other: &mut DictEntry) {
+        self.merged.push(other.origin());
+        self.merged.append(&mut other.merged);
+    }
+
+    /// Returns ids which keep conjugated forms of the same reading and surface
+    /// apart in deduplication, None for other entries.
+    pub fn form_ids(&self) -> Option<(u16, u16)> {
+        if self.inflected {
+            Some((self.left_id, self.right_id))
+        } else {
+            None
+        }
+    }
+
+    fn key(&self) -> (&str, u16, u16, i32, &str) {
+        (&self.reading, self.left_id, self.right_id, self.cost, &self.surface)
+    }
+
+    /// Parses a line written in Mozc format.
+    /// Returns None if the line does not have five valid fields.
+    pub fn parse_line(line: &str, source: &'static str) -> Option<DictEntry> {
+        let mut ss = line.trim_end_matches(&['\r', '\n'][..]).split('\t');
+        if let (Some(reading), Some(left_id), Some(right_id), Some(cost), Some(surface)) =
+            (ss.next(), ss.next(), ss.next(), ss.next(), ss.next()) {
+            Some(DictEntry {
+                reading: String::from(reading),
+                left_id: left_id.parse().ok()?,
+                right_id: right_id.parse().ok()?,
+                cost: cost.parse().ok()?,
+                surface: String::from(surface),
+                source,
+                record: 0,
+                merged: Vec::new(),
+                license: None,
+                penalty: 0,
+                inflected: false,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl PartialEq for DictEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for DictEntry {}
+
+impl PartialOrd for DictEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DictEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl fmt::Display for DictEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}\t{}\t{}\t{}\t{}",
+            self.reading, self.left_id, self.right_id, self.cost, self.surface)
+    }
+}
+
+/// Iterator of entries read from a file written in Mozc format.
+/// Lines which can not be parsed are ignored.
+#[derive(Debug)]
+pub struct Entries<R> {
+    reader: R,
+    source: &'static str,
+    line: String,
+    line_no: usize,
+}
+
+impl<R: BufRead> Iterator for Entries<R> {
+    type Item = Result<DictEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => {},
+                Err(e) => return Some(Err(e.into())),
+            }
+            self.line_no += 1;
+            if let Some(entry) = DictEntry::parse_line(&self.line, self.source) {
+                return Some(Ok(entry.with_record(self.line_no)));
+            }
+        }
+    }
+}
+
+/// Returns iterator of entries written in Mozc format read from the reader.
+pub fn entries_from<R: BufRead>(reader: R, source: &'static str) -> Entries<R> {
+    Entries {
+        reader,
+        source,
+        line: String::new(),
+        line_no: 0,
+    }
+}
+
+/// Returns iterator of entries read from the file written in Mozc format.
+pub fn open_entries(filename: &str, source: &'static str) -> Result<Entries<BufReader<File>>> {
+    Ok(entries_from(BufReader::new(File::open(filename).with_path(filename)?), source))
+}
+
+/// Reads entries written in Mozc format from the reader.
+/// Lines which can not be parsed are ignored.
+pub fn read_entries_from<R: BufRead>(reader: R, source: &'static str) -> Result<Vec<DictEntry>> {
+    entries_from(reader, source).collect()
+}
+
+/// Writes entries into the writer in Mozc format.
+pub fn write_entries_to<W: Write>(mut writer: W, entries: &[DictEntry]) -> Result<()> {
+    for entry in entries {
+        writeln!(writer, "{}", entry)?;
+    }
+    Ok(writer.flush()?)
+}
+
+/// Reads entries from the file written in Mozc format.
+/// Lines which can not be parsed are ignored.
+pub fn read_entries(filename: &str, source: &'static str) -> Result<Vec<DictEntry>> {
+    read_entries_from(BufReader::new(File::open(filename).with_path(filename)?), source).with_path(filename)
+}
+
+/// Writes entries into the file in Mozc format.
+pub fn write_entries(filename: &str, entries: &[DictEntry]) -> Result<()> {
+    write_entries_to(BufWriter::new(File::create(filename).with_path(filename)?), entries).with_path(filename)
+}
+
+/// Writes origins of the entry written at the line of the dictionary
+/// as `line_number\tsource:record[\tsource:record...]`.
+pub fn write_provenance_line<W: Write>(writer: &mut W, line: usize, entry: &DictEntry) -> std::io::Result<()> {
+    write!(writer, "{}", line)?;
+    for origin in entry.origins() {
+        write!(writer, "\t{}", origin)?;
+    }
+    writer.write_all(b"\n")
+}
+
+/// Writes origins of entries into the writer, each line corresponds to the line
+/// of the dictionary written by `write_entries_to` with the same entries.
+pub fn write_provenance_to<W: Write>(mut writer: W, entries: &[DictEntry]) -> Result<()> {
+    for (index, entry) in entries.iter().enumerate() {
+        write_provenance_line(&mut writer, index + 1, entry)?;
+    }
+    Ok(writer.flush()?)
+}
+
+/// Writes origins of entries into the file.
+pub fn write_provenance(filename: &str, entries: &[DictEntry]) -> Result<()> {
+    write_provenance_to(BufWriter::new(File::create(filename).with_path(filename)?), entries).with_path(filename)
+}
+
+/// Writes the entry with its provenance, license and penalty as a line of tab
+/// separated fields, which is read by `parse_entry_with_origins`.
+pub fn write_entry_with_origins<W: Write>(writer: &mut W, entry: &DictEntry) -> std::io::Result<()> {
+    write!(writer, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", entry.reading, entry.left_id, entry.right_id, entry.cost,
+        entry.surface, entry.source, entry.record, entry.license.map_or("-", License::name), entry.penalty,
+        u8::from(entry.inflected))?;
+    for origin in &entry.merged {
+        write!(writer, "\t{}\t{}", origin.source, origin.record)?;
+    }
+    writer.write_all(b"\n")
+}
+
+/// Reads the entry written by `write_entry_with_origins`.
+pub fn parse_entry_with_origins(line: &str) -> Option<DictEntry> {
+    let mut ss = line.trim_end_matches('\n').split('\t');
+    let mut entry = DictEntry::parse_line(line, "")?;
+    // 5つのフィールドは parse_line で読んだので飛ばす
+    ss.nth(4)?;
+    entry.source = intern(ss.next()?);
+    entry.record = ss.next()?.parse().ok()?;
+    entry.license = match ss.next()? {
+        "-" => None,
+        name => Some(License::from_name(name)?),
+    };
+    entry.penalty = ss.next()?.parse().ok()?;
+    entry.inflected = ss.next()? == "1";
+    while let Some(source) = ss.next() {
+        entry.merged.push(Origin {
+            source: intern(source),
+            record: ss.next()?.parse().ok()?,
+        });
+    }
+    Some(entry)
+}
+
+/// Sorts entries and removes duplicated entries, origins of removed entries
+/// are merged into the kept entry.
+pub fn sort_dedup(entries: &mut Vec<DictEntry>) {
+    use rayon::prelude::*;
+
+    entries.par_sort_unstable();
+    entries.dedup_by(|b, a| {
+        let duplicated = a == b;
+        if duplicated {
+            a.merge_origins(b);
+        }
+        duplicated
+    });
+}
+
+/// Sorts entries by reading, surface, cost and penalty, then removes entries
+/// which have the same reading and surface. Conjugated forms are removed only
+/// if they also have the same ids, see `DictEntry::form_ids`. The entry which
+/// has the lowest cost is kept, and origins of removed entries are merged into it.
+pub fn sort_dedup_by_reading_surface(entries: &mut Vec<DictEntry>) {
+    use rayon::prelude::*;
+
+    entries.par_sort_unstable_by(|a, b| {
+        (&a.reading, &a.surface, a.form_ids(), a.cost, a.penalty).cmp(&(&b.reading, &b.surface, b.form_ids(), b.cost, b.penalty))
+    });
+    entries.dedup_by(|b, a| {
+        let duplicated = a.reading == b.reading && a.surface == b.surface && a.form_ids() == b.form_ids();
+        if duplicated {
+            a.merge_origins(b);
+        }
+        duplicated
+    });
+}
@@ -0,0 +1,190 @@
+// Error type shared by converters and the build.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::stats::Query;
+
+
+/// Number of malformed lines reported one by one for each file.
+const MAX_REPORTED_LINES: usize = 10;
+
+/// Error raised while building dictionaries.
+#[derive(Debug)]
+pub enum Error {
+    /// I/O error, with the path of the file if it is known.
+    Io {
+        path: Option<PathBuf>,
+        error: std::io::Error,
+    },
+    /// Malformed record found in the input file of the source.
+    Parse {
+        source: &'static str,
+        file: String,
+        line: usize,
+        record: String,
+        reason: String,
+    },
+    /// Error which occurred while running the source.
+    Source {
+        source: &'static str,
+        error: Box<Error>,
+    },
+    /// Other errors, such as a version which is not found in a web page.
+    Other(String),
+}
+
+/// Result type of this crate.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Creates error from the message.
+    pub fn other<S: Into<String>>(message: S) -> Error {
+        Error::Other(message.into())
+    }
+
+    /// Tags the error with the name of the source where it occurred.
+    /// Errors which already have the name are returned as they are.
+    pub fn in_source(self, source: &'static str) -> Error {
+        match self {
+            Error::Parse { .. } | Error::Source { .. } => self,
+            error => Error::Source { source, error: Box::new(error) },
+        }
+    }
+
+    /// Creates I/O error which occurred on the file.
+    pub fn io_at<P: AsRef<Path>>(path: P, error: std::io::Error) -> Error {
+        Error::Io {
+            path: Some(path.as_ref().to_path_buf()),
+            error,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io { path: Some(path), error } => write!(f, "{}: {}", path.display(), error),
+            Error::Io { path: None, error } => write!(f, "{}", error),
+            Error::Parse { source, file, line, record, reason } =>
+                write!(f, "{}: {}:{}: {}: {:?}", source, file, line, reason, record),
+            Error::Source { source, error } => write!(f, "{}: {}", source, error),
+            Error::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { error, .. } => Some(error),
+            Error::Source { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Error {
+        Error::Io { path: None, error }
+    }
+}
+
+/// Adds path of the file to I/O errors which do not have it.
+pub trait WithPath<T> {
+    /// Returns the error with the path if failed.
+    fn with_path<P: AsRef<Path>>(self, path: P) -> Result<T>;
+}
+
+impl<T> WithPath<T> for std::io::Result<T> {
+    fn with_path<P: AsRef<Path>>(self, path: P) -> Result<T> {
+        self.map_err(|e| Error::io_at(path, e))
+    }
+}
+
+impl<T> WithPath<T> for Result<T> {
+    fn with_path<P: AsRef<Path>>(self, path: P) -> Result<T> {
+        self.map_err(|e| match e {
+            Error::Io { path: None, error } => Error::io_at(path, error),
+            e => e,
+        })
+    }
+}
+
+/// Counts malformed lines which are skipped while parsing, and records
+/// which are skipped by rules of the converter.
+///
+/// The first few malformed lines are reported as warnings with their line numbers.
+#[derive(Debug)]
+pub struct SkippedLines {
+    source: &'static str,
+    file: String,
+    count: usize,
+    rules: BTreeMap<&'static str, usize>,
+    query: Option<Query>,
+}
+
+impl SkippedLines {
+    /// Creates counter for the file read by the source.
+    pub fn new(source: &'static str, file: &str) -> SkippedLines {
+        SkippedLines {
+            source,
+            file: String::from(file),
+            count: 0,
+            rules: BTreeMap::new(),
+            query: None,
+        }
+    }
+
+    /// Explains lines and records which match to the query.
+    pub fn with_query(mut self, query: Option<Query>) -> SkippedLines {
+        self.query = query;
+        self
+    }
+
+    fn explain(&self, line: usize, record: &str, reason: &str) {
+        if self.query.as_ref().is_some_and(|query| query.matches_record(record)) {
+            println!("explain: {}: {}:{}: skipped by {}: {}", self.source, self.file, line, reason, record.trim_end());
+        }
+    }
+
+    /// Counts the malformed line, line number starts from 1.
+    pub fn skip(&mut self, line: usize, record: &str, reason: &str) {
+        self.explain(line, record, reason);
+        if self.count < MAX_REPORTED_LINES {
+            eprintln!("warning: {}", Error::Parse {
+                source: self.source,
+                file: self.file.clone(),
+                line,
+                record: String::from(record.trim_end()),
+                reason: String::from(reason),
+            });
+        }
+        self.count += 1;
+    }
+
+    /// Counts the record which is skipped by the rule of the converter,
+    /// line number starts from 1.
+    pub fn rule(&mut self, rule: &'static str, line: usize, record: &str) {
+        self.explain(line, record, rule);
+        *self.rules.entry(rule).or_insert(0) += 1;
+    }
+
+    /// Returns number of skipped lines.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns number of records skipped by each rule.
+    pub fn rules(&self) -> &BTreeMap<&'static str, usize> {
+        &self.rules
+    }
+
+    /// Prints number of skipped lines if any.
+    pub fn report(&self) {
+        if self.count > 0 {
+            eprintln!("warning: {}: {} malformed lines are skipped in {}", self.source, self.count, self.file);
+        }
+    }
+}
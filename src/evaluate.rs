@@ -0,0 +1,328 @@
+// Evaluation of conversion with dictionaries and connection costs of Mozc.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use rayon::prelude::*;
+
+use super::error::{Error, Result, WithPath};
+use super::lookup::DictIndex;
+
+
+/// Connection costs extracted from the source of Mozc.
+pub const CONNECTION_FILE_NAME: &str = "connection_single_column.txt";
+
+/// Cost of a character which no entry covers, read as it is.
+pub const UNKNOWN_COST: i32 = 10000;
+
+/// Costs of connecting a word to the next word.
+#[derive(Clone, Debug, Default)]
+pub struct Connection {
+    size: usize,
+    costs: Vec<i16>,
+}
+
+impl Connection {
+    /// Reads connection_single_column.txt, whose first line is the number of ids
+    /// and the following lines are costs in the order of right id of the previous
+    /// word and left id of the next word.
+    pub fn read_from<R: BufRead>(reader: R) -> Result<Connection> {
+        let mut lines = reader.lines();
+        let size: usize = lines.next().transpose()?.and_then(|line| line.trim().parse().ok())
+            .ok_or_else(|| Error::other("connection: no number of ids"))?;
+        let mut costs = Vec::with_capacity(size * size);
+        for line in lines {
+            let line = line?;
+            costs.push(line.trim().parse().map_err(|_| Error::other(format!("connection: invalid cost: {:?}", line)))?);
+        }
+        if costs.len() != size * size {
+            return Err(Error::other(format!("connection: {} costs for {} ids", costs.len(), size)));
+        }
+        Ok(Connection { size, costs })
+    }
+
+    /// Reads connection costs from the file.
+    pub fn read(filename: &str) -> Result<Connection> {
+        Connection::read_from(BufReader::new(File::open(filename).with_path(filename)?)).with_path(filename)
+    }
+
+    /// Returns cost of connecting the word of the right id to the next word of the left id.
+    pub fn cost(&self, right_id: u16, left_id: u16) -> i32 {
+        let (right_id, left_id) = (right_id as usize, left_id as usize);
+        if right_id < self.size && left_id < self.size {
+            self.costs[right_id * self.size + left_id] as i32
+        } else {
+            i16::MAX as i32
+        }
+    }
+}
+
+/// Word in the lattice.
+#[derive(Debug)]
+struct Node<'a> {
+    start: usize,
+    left_id: u16,
+    right_id: u16,
+    cost: i32,
+    surface: &'a str,
+    /// Best paths to this node, pairs of the total cost and the previous
+    /// node with its rank, sorted by the cost.
+    paths: Vec<(i32, Option<(usize, usize)>)>,
+}
+
+/// Converts readings into surfaces with the best paths of the lattice.
+#[derive(Debug)]
+pub struct Converter<'a> {
+    index: &'a DictIndex,
+    connection: &'a Connection,
+    unknown_id: u16,
+    max_reading_len: usize,
+}
+
+impl<'a> Converter<'a> {
+    /// Creates converter with entries of the dictionaries, `unknown_id` is
+    /// used for characters which no entry covers.
+    pub fn new(index: &'a DictIndex, connection: &'a Connection, unknown_id: u16) -> Converter<'a> {
+        let max_reading_len = index.entries().iter().map(|entry| entry.reading.chars().count()).max().unwrap_or(0);
+        Converter { index, connection, unknown_id, max_reading_len }
+    }
+
+    /// Returns at most `n` surfaces of the reading in the order of their costs.
+    pub fn convert(&self, reading: &str, n: usize) -> Vec<(String, i32)> {
+        let n = n.max(1);
+        let bounds = reading.char_indices().map(|(i, _)| i).chain(std::iter::once(reading.len())).collect::<Vec<usize>>();
+        let len = bounds.len() - 1;
+
+        // ラティスを作る、辞書にない文字は読みのまま1文字の単語にする
+        let mut nodes = Vec::new();
+        let mut ends_at = vec![Vec::new(); len + 1];
+        for start in 0..len {
+            for end in start + 1..=len.min(start + self.max_reading_len.max(1)) {
+                let word = &reading[bounds[start]..bounds[end]];
+                let mut found = self.index.reading(word).iter().map(|entry| (entry.left_id, entry.right_id, entry.cost, entry.surface.as_str())).collect::<Vec<_>>();
+                if end == start + 1 {
+                    found.push((self.unknown_id, self.unknown_id, UNKNOWN_COST, word));
+                }
+                for (left_id, right_id, cost, surface) in found {
+                    ends_at[end].push(nodes.len());
+                    nodes.push(Node { start, left_id, right_id, cost, surface, paths: Vec::new() });
+                }
+            }
+        }
+
+        // 始まりの位置の順に、その位置で終わる単語からのN-bestを求める
+        let mut order = (0..nodes.len()).collect::<Vec<usize>>();
+        order.sort_by_key(|index| nodes[*index].start);
+        for index in order {
+            let (start, left_id, cost) = (nodes[index].start, nodes[index].left_id, nodes[index].cost);
+            let mut paths = if start == 0 {
+                // BOS/EOS の ID は 0
+                vec![(self.connection.cost(0, left_id) + cost, None)]
+            } else {
+                let mut paths = Vec::new();
+                for &prev in &ends_at[start] {
+                    let connection = self.connection.cost(nodes[prev].right_id, left_id);
+                    for (rank, (total, _)) in nodes[prev].paths.iter().enumerate() {
+                        paths.push((total + connection + cost, Some((prev, rank))));
+                    }
+                }
+                paths
+            };
+            paths.sort_by_key(|(total, _)| *total);
+            paths.truncate(n);
+            nodes[index].paths = paths;
+        }
+
+        let mut ends = Vec::new();
+        for &last in &ends_at[len] {
+            for (rank, (total, _)) in nodes[last].paths.iter().enumerate() {
+                ends.push((total + self.connection.cost(nodes[last].right_id, 0), last, rank));
+            }
+        }
+        ends.sort_by_key(|(total, _, _)| *total);
+
+        let mut results: Vec<(String, i32)> = Vec::new();
+        for (total, last, rank) in ends {
+            let mut words = Vec::new();
+            let mut current = Some((last, rank));
+            while let Some((index, rank)) = current {
+                words.push(nodes[index].surface);
+                current = nodes[index].paths[rank].1;
+            }
+            words.reverse();
+            let surface = words.concat();
+            // 区切りが違うだけの同じ表記は1つにする
+            if !results.iter().any(|(other, _)| other == &surface) {
+                results.push((surface, total));
+                if results.len() == n {
+                    break;
+                }
+            }
+        }
+        results
+    }
+}
+
+/// Sentence and its expected conversion.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestCase {
+    pub reading: String,
+    pub expected: String,
+}
+
+/// Reads test cases written as `reading\tsurface` in each line,
+/// empty lines and lines starting with `#` are ignored.
+pub fn read_test_cases_from<R: BufRead>(reader: R, file: &str) -> Result<Vec<TestCase>> {
+    let mut cases = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some((reading, expected)) = line.split_once('\t') {
+            cases.push(TestCase { reading: String::from(reading), expected: String::from(expected.trim_end()) });
+        } else {
+            return Err(Error::Parse {
+                source: "evaluate",
+                file: String::from(file),
+                line: index + 1,
+                record: line,
+                reason: String::from("no tab between reading and surface"),
+            });
+        }
+    }
+    Ok(cases)
+}
+
+/// Reads test cases from the file.
+pub fn read_test_cases(filename: &str) -> Result<Vec<TestCase>> {
+    read_test_cases_from(BufReader::new(File::open(filename).with_path(filename)?), filename).with_path(filename)
+}
+
+/// Accuracy of conversion over test cases.
+#[derive(Clone, Debug, Default)]
+pub struct Evaluation {
+    /// Number of candidates checked for top-N accuracy.
+    pub n: usize,
+    /// Number of test cases.
+    pub cases: usize,
+    /// Test cases whose best candidate is the expected surface.
+    pub top1: usize,
+    /// Test cases which have the expected surface in the candidates.
+    pub top_n: usize,
+    /// Test cases whose best candidate is not expected, with their candidates.
+    pub failures: Vec<(TestCase, Vec<String>)>,
+}
+
+/// Converts readings of test cases and counts expected results in top-1 and top-N candidates.
+pub fn evaluate(converter: &Converter, cases: &[TestCase], n: usize) -> Evaluation {
+    let results = cases.par_iter().map(|case| {
+        converter.convert(&case.reading, n).into_iter().map(|(surface, _)| surface).collect::<Vec<String>>()
+    }).collect::<Vec<_>>();
+
+    let mut evaluation = Evaluation { n, cases: cases.len(), ..Evaluation::default() };
+    for (case, candidates) in cases.iter().zip(results) {
+        let rank = candidates.iter().position(|surface| surface == &case.expected);
+        if rank == Some(0) {
+            evaluation.top1 += 1;
+        } else {
+            evaluation.failures.push((case.clone(), candidates));
+        }
+        if rank.is_some() {
+            evaluation.top_n += 1;
+        }
+    }
+    evaluation
+}
+
+fn percent(count: usize, total: usize) -> f64 {
+    if total == 0 { 0.0 } else { count as f64 * 100.0 / total as f64 }
+}
+
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "top-1 {}/{} ({:.2}%), top-{} {}/{} ({:.2}%)",
+            self.top1, self.cases, percent(self.top1, self.cases),
+            self.n, self.top_n, self.cases, percent(self.top_n, self.cases))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::DictEntry;
+
+    /// Connection of 4 ids, BOS/EOS 0, noun 1, unused 2 and unknown 3.
+    /// Nouns following nouns cost 500, and others cost nothing.
+    fn connection() -> Connection {
+        let mut text = String::from("4\n");
+        for right_id in 0..4 {
+            for left_id in 0..4 {
+                text.push_str(if right_id == 1 && left_id == 1 { "500\n" } else { "0\n" });
+            }
+        }
+        Connection::read_from(text.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn read_connection() {
+        let connection = Connection::read_from("2\n0\n1\n2\n3\n".as_bytes()).unwrap();
+        assert_eq!(connection.cost(1, 0), 2);
+        assert_eq!(connection.cost(0, 1), 1);
+        // 範囲外のIDはつながらない
+        assert_eq!(connection.cost(2, 0), i16::MAX as i32);
+
+        assert!(Connection::read_from("2\n0\n1\n2\n".as_bytes()).is_err());
+        assert!(Connection::read_from("2\n0\n1\nx\n3\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn convert_n_best_with_connection_costs() {
+        let entry = |reading: &str, cost: i32, surface: &str| DictEntry::new(String::from(reading), 1, cost, String::from(surface), "test");
+        let index = DictIndex::new(vec![
+            entry("きしゃ", 1000, "記者"),
+            entry("きしゃ", 1200, "汽車"),
+            entry("きしゃ", 3000, "きしゃ"),
+            entry("き", 350, "木"),
+            entry("き", 2200, "き"),
+            entry("しゃ", 400, "社"),
+            entry("しゃ", 500, "しゃ"),
+        ]);
+        let connection = connection();
+        let converter = Converter::new(&index, &connection, 3);
+
+        // 木社 は 木 と 社 のコストに名詞同士の接続コストを加える
+        assert_eq!(converter.convert("きしゃ", 4), [
+            (String::from("記者"), 1000),
+            (String::from("汽車"), 1200),
+            (String::from("木社"), 1250),
+            (String::from("木しゃ"), 1350),
+        ]);
+
+        // き+しゃ (3200) は きしゃ (3000) と同じ表記なので1つにする
+        let candidates = converter.convert("きしゃ", 10);
+        assert_eq!(candidates[4], (String::from("きしゃ"), 3000));
+        assert_eq!(candidates[5], (String::from("き社"), 3100));
+        assert_eq!(candidates.iter().filter(|(surface, _)| surface == "きしゃ").count(), 1);
+        assert_eq!(candidates.iter().filter(|(surface, _)| surface == "き社").count(), 1);
+
+        // 辞書にない文字は読みのまま
+        assert_eq!(converter.convert("ぬ", 1), [(String::from("ぬ"), UNKNOWN_COST)]);
+    }
+
+    #[test]
+    fn read_test_cases_skips_comments() {
+        let text = "# コメント\nきしゃ\t記者\n\nきしゃのきしゃ\t貴社の記者 \n";
+        let cases = read_test_cases_from(text.as_bytes(), "test.tsv").unwrap();
+        assert_eq!(cases, [
+            TestCase { reading: String::from("きしゃ"), expected: String::from("記者") },
+            TestCase { reading: String::from("きしゃのきしゃ"), expected: String::from("貴社の記者") },
+        ]);
+
+        match read_test_cases_from("きしゃ\t記者\nきしゃ\n".as_bytes(), "test.tsv") {
+            Err(Error::Parse { line, .. }) => assert_eq!(line, 2),
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,399 @@
+// External merge sort of dictionary entries with a memory budget.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use super::entry::{parse_entry_with_origins, write_entry_with_origins, DictEntry, Origin};
+use super::error::{Error, Result, WithPath};
+
+
+/// Settings of sorting entries which do not fit in memory.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SortSettings {
+    /// Memory used for entries buffered by sorters in MiB, shared by sorters
+    /// which run at the same time. Sorted chunks are written into files when
+    /// entries exceed the share of a sorter.
+    pub memory_budget: usize,
+    /// Directory where sorted chunks are written.
+    pub temp_dir: String,
+    /// Maximum number of chunk files merged at once, more chunks are merged
+    /// into fewer chunks beforehand.
+    pub fan_in: usize,
+}
+
+impl Default for SortSettings {
+    fn default() -> Self {
+        SortSettings {
+            memory_budget: 1024,
+            temp_dir: String::from("sort"),
+            fan_in: 64,
+        }
+    }
+}
+
+impl SortSettings {
+    /// Returns settings which never write chunks into files.
+    pub fn in_memory() -> SortSettings {
+        SortSettings {
+            memory_budget: usize::MAX >> 20,
+            temp_dir: String::from("."),
+            fan_in: 64,
+        }
+    }
+
+    /// Returns settings for each of `n` sorters which run at the same time,
+    /// which share the memory budget. Each sorter gets 1 MiB at least.
+    pub fn split(&self, n: usize) -> SortSettings {
+        SortSettings {
+            memory_budget: (self.memory_budget / n.max(1)).max(1),
+            ..self.clone()
+        }
+    }
+}
+
+/// Comparison function which decides order of entries.
+pub type Compare = fn(&DictEntry, &DictEntry) -> Ordering;
+
+/// Sequence number of chunk files, to make their names unique in the process.
+static CHUNK_NO: AtomicUsize = AtomicUsize::new(0);
+
+/// Estimates memory used by the entry.
+fn entry_size(entry: &DictEntry) -> usize {
+    std::mem::size_of::<DictEntry>() + entry.reading.capacity() + entry.surface.capacity() +
+        entry.merged.capacity() * std::mem::size_of::<Origin>()
+}
+
+/// Chunk files which are removed when dropped.
+#[derive(Debug, Default)]
+struct Chunks {
+    paths: Vec<PathBuf>,
+}
+
+impl Drop for Chunks {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Sorts entries which may not fit in memory.
+///
+/// Entries are buffered until they exceed the memory budget, then the buffer
+/// is sorted and written into a chunk file. `finish` merges the chunks, at
+/// most `fan_in` of them at once. A sorter without comparison function keeps
+/// the pushed order.
+#[derive(Debug)]
+pub struct ExternalSorter {
+    compare: Option<Compare>,
+    budget: usize,
+    fan_in: usize,
+    dir: PathBuf,
+    buffer: Vec<DictEntry>,
+    buffer_size: usize,
+    chunks: Chunks,
+}
+
+impl ExternalSorter {
+    /// Creates sorter which orders entries by the comparison function.
+    pub fn new(settings: &SortSettings, compare: Compare) -> ExternalSorter {
+        ExternalSorter::with_compare(settings, Some(compare))
+    }
+
+    /// Creates sorter which keeps entries in the pushed order.
+    pub fn spool(settings: &SortSettings) -> ExternalSorter {
+        ExternalSorter::with_compare(settings, None)
+    }
+
+    fn with_compare(settings: &SortSettings, compare: Option<Compare>) -> ExternalSorter {
+        ExternalSorter {
+            compare,
+            budget: settings.memory_budget.saturating_mul(1024 * 1024),
+            fan_in: settings.fan_in.max(2),
+            dir: PathBuf::from(&settings.temp_dir),
+            buffer: Vec::new(),
+            buffer_size: 0,
+            chunks: Chunks::default(),
+        }
+    }
+
+    /// Adds the entry, the buffer is written into a chunk if it exceeds the budget.
+    pub fn push(&mut self, entry: DictEntry) -> Result<()> {
+        self.buffer_size += entry_size(&entry);
+        self.buffer.push(entry);
+        if self.buffer_size >= self.budget {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Adds entries.
+    pub fn extend<I: IntoIterator<Item = DictEntry>>(&mut self, entries: I) -> Result<()> {
+        for entry in entries {
+            self.push(entry)?;
+        }
+        Ok(())
+    }
+
+    fn sort_buffer(&mut self) {
+        if let Some(compare) = self.compare {
+            self.buffer.par_sort_unstable_by(compare);
+        }
+    }
+
+    /// Creates a new chunk file and returns its writer.
+    fn create_chunk(&mut self) -> Result<(PathBuf, BufWriter<File>)> {
+        std::fs::create_dir_all(&self.dir).with_path(&self.dir)?;
+        let path = self.dir.join(format!("chunk-{}-{}.txt", std::process::id(), CHUNK_NO.fetch_add(1, AtomicOrdering::Relaxed)));
+        self.chunks.paths.push(path.clone());
+        let writer = BufWriter::new(File::create(&path).with_path(&path)?);
+        Ok((path, writer))
+    }
+
+    /// Sorts buffered entries and writes them into a new chunk file.
+    fn spill(&mut self) -> Result<()> {
+        self.sort_buffer();
+        let (path, mut writer) = self.create_chunk()?;
+        for entry in &self.buffer {
+            write_entry_with_origins(&mut writer, entry).with_path(&path)?;
+        }
+        writer.flush().with_path(&path)?;
+        self.buffer = Vec::new();
+        self.buffer_size = 0;
+        Ok(())
+    }
+
+    /// Merges every `fan_in` chunks into a chunk until the number of chunks
+    /// does not exceed `fan_in`. Merged chunks are removed.
+    fn reduce_chunks(&mut self) -> Result<()> {
+        while self.chunks.paths.len() > self.fan_in {
+            // 古いチャンクはまとめ終わった後に削除する
+            let chunks = std::mem::take(&mut self.chunks);
+            for group in chunks.paths.chunks(self.fan_in) {
+                let mut merger = Merger::open(group, self.compare, Chunks::default())?;
+                let (path, mut writer) = self.create_chunk()?;
+                while let Some(entry) = merger.next_entry()? {
+                    write_entry_with_origins(&mut writer, &entry).with_path(&path)?;
+                }
+                writer.flush().with_path(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns all entries in the order, chunks are removed after read.
+    pub fn finish(mut self) -> Result<SortedEntries> {
+        if self.chunks.paths.is_empty() {
+            self.sort_buffer();
+            return Ok(SortedEntries::Memory(std::mem::take(&mut self.buffer).into_iter()));
+        }
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        self.reduce_chunks()?;
+
+        let chunks = std::mem::take(&mut self.chunks);
+        let paths = chunks.paths.clone();
+        Ok(SortedEntries::Merge(Merger::open(&paths, self.compare, chunks)?))
+    }
+}
+
+struct ChunkReader {
+    reader: BufReader<File>,
+    path: PathBuf,
+    line: String,
+}
+
+impl ChunkReader {
+    fn next_entry(&mut self) -> Result<Option<DictEntry>> {
+        self.line.clear();
+        if self.reader.read_line(&mut self.line).with_path(&self.path)? == 0 {
+            return Ok(None);
+        }
+        parse_entry_with_origins(&self.line).map(Some)
+            .ok_or_else(|| Error::other(format!("{}: broken chunk: {:?}", self.path.display(), self.line)))
+    }
+}
+
+/// Head entry of a chunk, the heap pops the smallest entry first.
+/// Chunks without comparison function are read one by one.
+struct HeapItem {
+    entry: DictEntry,
+    index: usize,
+    compare: Option<Compare>,
+}
+
+impl HeapItem {
+    fn order(&self, other: &Self) -> Ordering {
+        self.compare.map_or(Ordering::Equal, |compare| compare(&self.entry, &other.entry))
+            .then(self.index.cmp(&other.index))
+    }
+}
+
+impl PartialEq for HeapItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.order(other) == Ordering::Equal
+    }
+}
+
+impl Eq for HeapItem {}
+
+impl PartialOrd for HeapItem {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HeapItem {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.order(self)
+    }
+}
+
+/// Merges sorted chunks.
+pub struct Merger {
+    compare: Option<Compare>,
+    readers: Vec<ChunkReader>,
+    heap: BinaryHeap<HeapItem>,
+    _chunks: Chunks,
+}
+
+impl Merger {
+    /// Opens the chunk files in the order, `chunks` are removed when the merger is dropped.
+    fn open(paths: &[PathBuf], compare: Option<Compare>, chunks: Chunks) -> Result<Merger> {
+        let mut readers = Vec::with_capacity(paths.len());
+        for path in paths {
+            readers.push(ChunkReader {
+                reader: BufReader::new(File::open(path).with_path(path)?),
+                path: path.clone(),
+                line: String::new(),
+            });
+        }
+        let mut merger = Merger {
+            compare,
+            readers,
+            heap: BinaryHeap::new(),
+            _chunks: chunks,
+        };
+        for index in 0..merger.readers.len() {
+            merger.fill(index)?;
+        }
+        Ok(merger)
+    }
+
+    fn fill(&mut self, index: usize) -> Result<()> {
+        if let Some(entry) = self.readers[index].next_entry()? {
+            self.heap.push(HeapItem { entry, index, compare: self.compare });
+        }
+        Ok(())
+    }
+
+    fn next_entry(&mut self) -> Result<Option<DictEntry>> {
+        if let Some(item) = self.heap.pop() {
+            self.fill(item.index)?;
+            Ok(Some(item.entry))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Entries returned by `ExternalSorter::finish` in the order.
+pub enum SortedEntries {
+    /// Entries which fit in memory.
+    Memory(std::vec::IntoIter<DictEntry>),
+    /// Entries merged from chunk files.
+    Merge(Merger),
+}
+
+impl Iterator for SortedEntries {
+    type Item = Result<DictEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SortedEntries::Memory(entries) => entries.next().map(Ok),
+            SortedEntries::Merge(merger) => merger.next_entry().transpose(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::remove_unnecessary_entries_stream;
+    use crate::stats::BuildStats;
+
+    /// Settings which write every entry into its own chunk and merge two chunks at once.
+    fn tiny_settings(name: &str) -> SortSettings {
+        let dir = std::env::temp_dir().join(format!("mozcdic-ut-extsort-{}-{}", name, std::process::id()));
+        SortSettings {
+            memory_budget: 0,
+            temp_dir: dir.to_string_lossy().into_owned(),
+            fan_in: 2,
+        }
+    }
+
+    fn entry(reading: &str, cost: i32, source: &'static str) -> DictEntry {
+        DictEntry::new(String::from(reading), 1, cost, String::from("表記"), source)
+    }
+
+    fn chunk_count(settings: &SortSettings) -> usize {
+        std::fs::read_dir(&settings.temp_dir).map_or(0, |dir| dir.count())
+    }
+
+    #[test]
+    fn merge_spilled_chunks_in_order() {
+        let settings = tiny_settings("order");
+        let mut sorter = ExternalSorter::new(&settings, <DictEntry as Ord>::cmp);
+        let costs = [5, 3, 9, 1, 7, 2, 8];
+        for &cost in &costs {
+            sorter.push(entry("あいう", cost, "test")).unwrap();
+        }
+        assert_eq!(chunk_count(&settings), costs.len());
+
+        let sorted = sorter.finish().unwrap().map(|entry| entry.map(|entry| entry.cost)).collect::<Result<Vec<i32>>>().unwrap();
+        assert_eq!(sorted, [1, 2, 3, 5, 7, 8, 9]);
+        assert_eq!(chunk_count(&settings), 0);
+
+        // 並べ替えない場合は追加した順を保つ
+        let mut spool = ExternalSorter::spool(&settings);
+        for &cost in &costs {
+            spool.push(entry("あいう", cost, "test")).unwrap();
+        }
+        let spooled = spool.finish().unwrap().map(|entry| entry.map(|entry| entry.cost)).collect::<Result<Vec<i32>>>().unwrap();
+        assert_eq!(spooled, costs);
+        std::fs::remove_dir_all(&settings.temp_dir).unwrap();
+    }
+
+    #[test]
+    fn dedup_across_chunks() {
+        let settings = tiny_settings("dedup");
+        // 同じ「読み+表記」のエントリが別のチャンクに分かれても1つにまとめる
+        let entries = vec![
+            entry("かきくけこ", 6000, "neologd"),
+            entry("あいうえお", 6500, "neologd"),
+            entry("かきくけこ", 5000, "sudachidict"),
+            entry("あいうえお", 6200, "skkdic"),
+            entry("かきくけこ", 5500, "edict2"),
+        ];
+        let mut stats = BuildStats::default();
+        let mut kept = Vec::new();
+        remove_unnecessary_entries_stream(entries.into_iter().map(Ok), std::iter::empty(), &settings, &mut stats, |entry| {
+            kept.push(entry);
+            Ok(())
+        }).unwrap();
+
+        let words: Vec<(&str, i32, usize)> = kept.iter().map(|e| (e.reading.as_str(), e.cost, e.merged.len())).collect();
+        assert_eq!(words, [("あいうえお", 6200, 1), ("かきくけこ", 5000, 2)]);
+        assert_eq!(chunk_count(&settings), 0);
+        std::fs::remove_dir_all(&settings.temp_dir).unwrap();
+    }
+}
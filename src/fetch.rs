@@ -0,0 +1,238 @@
+// Resolves source files, downloading them or looking up pre-downloaded files.
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Read, Write};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::error::{Error, Result, WithPath};
+
+
+/// Opens contents on the network.
+pub trait Downloader {
+    /// Returns reader of the content at the url.
+    fn open(&self, url: &str) -> std::io::Result<Box<dyn Read + Send>>;
+}
+
+/// Downloads contents over HTTP(S).
+#[derive(Clone, Debug, Default)]
+pub struct HttpDownloader;
+
+impl Downloader for HttpDownloader {
+    fn open(&self, url: &str) -> std::io::Result<Box<dyn Read + Send>> {
+        match ureq::get(url).call() {
+            Ok(response) => Ok(Box::new(response.into_reader())),
+            Err(e) => Err(std::io::Error::new(std::io::ErrorKind::Other,
+                format!("failed to download {}: {}", url, e))),
+        }
+    }
+}
+
+/// Provides files required by sources.
+///
+/// In online mode, files are downloaded into the working directory.
+/// In offline mode, files are looked up in the specified directory or
+/// in the path overridden for each file name, and missing files are errors.
+#[derive(Clone)]
+pub struct Fetcher {
+    /// Directory which keeps pre-downloaded files, offline mode if specified.
+    offline_dir: Option<PathBuf>,
+    /// Paths of files specified by their file names.
+    overrides: HashMap<String, PathBuf>,
+    /// Download urls specified by file names or default urls.
+    urls: HashMap<String, String>,
+    /// Used to download files in online mode.
+    downloader: Arc<dyn Downloader + Send + Sync>,
+}
+
+impl std::fmt::Debug for Fetcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Fetcher")
+         .field("offline_dir", &self.offline_dir)
+         .field("overrides", &self.overrides)
+         .field("urls", &self.urls)
+         .finish_non_exhaustive()
+    }
+}
+
+impl Default for Fetcher {
+    fn default() -> Self {
+        Fetcher::with_downloader(HttpDownloader)
+    }
+}
+
+impl Fetcher {
+    /// Creates fetcher which downloads files.
+    pub fn online() -> Fetcher {
+        Fetcher::default()
+    }
+
+    /// Creates fetcher which downloads files with the downloader,
+    /// such as the one backed by a local file server.
+    pub fn with_downloader<D: Downloader + Send + Sync + 'static>(downloader: D) -> Fetcher {
+        Fetcher {
+            offline_dir: None,
+            overrides: HashMap::new(),
+            urls: HashMap::new(),
+            downloader: Arc::new(downloader),
+        }
+    }
+
+    /// Creates fetcher which uses files in the directory without network access.
+    pub fn offline<P: AsRef<Path>>(dir: P) -> Fetcher {
+        Fetcher {
+            offline_dir: Some(dir.as_ref().to_path_buf()),
+            ..Fetcher::default()
+        }
+    }
+
+    /// Returns true if files are not downloaded.
+    pub fn is_offline(&self) -> bool {
+        self.offline_dir.is_some()
+    }
+
+    /// Uses the path for the file name instead of downloading or looking up
+    /// the offline directory.
+    pub fn set_override<P: AsRef<Path>>(&mut self, name: &str, path: P) {
+        self.overrides.insert(String::from(name), path.as_ref().to_path_buf());
+    }
+
+    /// Downloads the file from the url instead of the default one,
+    /// `key` is the file name or the default url.
+    pub fn set_url(&mut self, key: &str, url: &str) {
+        self.urls.insert(String::from(key), String::from(url));
+    }
+
+    /// Returns path to the file which is downloaded from the url as the name.
+    ///
+    /// If `keep` is true and the file already exists in the working directory,
+    /// it is used without downloading.
+    pub fn fetch(&self, url: &str, name: &str, keep: bool) -> Result<PathBuf> {
+        let url = self.urls.get(name)
+                      .or_else(|| self.urls.get(url))
+                      .map_or(url, String::as_str);
+
+        if let Some(path) = self.overrides.get(name) {
+            return if path.is_file() {
+                Ok(path.clone())
+            } else {
+                Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+                    format!("{} is not found, which is specified for {}", path.display(), name)).into())
+            };
+        }
+
+        if let Some(dir) = &self.offline_dir {
+            let path = dir.join(name);
+            if path.is_file() {
+                Ok(path)
+            } else {
+                Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+                    format!("{} is not found in offline mode, download it from {}", path.display(), url)).into())
+            }
+        } else {
+            let path = PathBuf::from(name);
+            if keep && path.is_file() {
+                println!("{} already exists.", name);
+            } else {
+                self.download(url, &path)?;
+            }
+            Ok(path)
+        }
+    }
+
+    /// Downloads the url into the file. Partially written file is removed on failure.
+    fn download(&self, url: &str, path: &Path) -> Result<()> {
+        let result = self.downloader.open(url).and_then(|mut reader| {
+            let mut writer = BufWriter::new(File::create(path)?);
+            std::io::copy(&mut reader, &mut writer)?;
+            writer.flush()
+        });
+        if result.is_err() {
+            let _ = std::fs::remove_file(path);
+        }
+        result.with_path(path)
+    }
+
+    /// Returns content of the url as text, such as a web page to find the latest version.
+    /// This is an error in offline mode.
+    pub fn fetch_text(&self, url: &str) -> Result<String> {
+        if self.is_offline() {
+            return Err(Error::other(format!("{} can not be opened in offline mode", url)));
+        }
+        let url = self.urls.get(url).map_or(url, String::as_str);
+        let mut s = String::new();
+        self.downloader.open(url)?.read_to_string(&mut s)?;
+        Ok(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serves files of a local directory as if they were on the network.
+    struct LocalServer {
+        root: PathBuf,
+    }
+
+    impl Downloader for LocalServer {
+        fn open(&self, url: &str) -> std::io::Result<Box<dyn Read + Send>> {
+            let name = url.rsplit('/').next().unwrap_or(url);
+            Ok(Box::new(File::open(self.root.join(name))?))
+        }
+    }
+
+    /// Makes an empty directory for the test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mozcdic-ut-fetch-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn fetch_downloads_from_server() {
+        let dir = test_dir("online");
+        std::fs::create_dir(dir.join("server")).unwrap();
+        std::fs::write(dir.join("server").join("edict2.gz"), "content").unwrap();
+        let fetcher = Fetcher::with_downloader(LocalServer { root: dir.join("server") });
+
+        let name = dir.join("edict2.gz");
+        let path = fetcher.fetch("http://example.com/edict2.gz", name.to_str().unwrap(), false).unwrap();
+        assert_eq!(path, name);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "content");
+        assert_eq!(fetcher.fetch_text("http://example.com/edict2.gz").unwrap(), "content");
+
+        // 失敗したダウンロードのファイルは残さない
+        let missing = dir.join("missing.gz");
+        assert!(fetcher.fetch("http://example.com/missing.gz", missing.to_str().unwrap(), false).is_err());
+        assert!(!missing.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fetch_uses_offline_directory() {
+        let dir = test_dir("offline");
+        std::fs::write(dir.join("edict2.gz"), "content").unwrap();
+        let mut fetcher = Fetcher::offline(&dir);
+        assert!(fetcher.is_offline());
+
+        let path = fetcher.fetch("http://example.com/edict2.gz", "edict2.gz", false).unwrap();
+        assert_eq!(path, dir.join("edict2.gz"));
+
+        // 見つからないファイルはダウンロード元とともにエラーになる
+        let e = fetcher.fetch("http://example.com/SKK-JISYO.L.gz", "SKK-JISYO.L.gz", false).unwrap_err();
+        assert!(e.to_string().contains("http://example.com/SKK-JISYO.L.gz"), "{}", e);
+        assert!(fetcher.fetch_text("http://example.com/").is_err());
+
+        // 上書きしたパスはディレクトリより優先する
+        let other = dir.join("other.gz");
+        std::fs::write(&other, "other").unwrap();
+        fetcher.set_override("SKK-JISYO.L.gz", &other);
+        assert_eq!(fetcher.fetch("http://example.com/SKK-JISYO.L.gz", "SKK-JISYO.L.gz", false).unwrap(), other);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
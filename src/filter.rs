@@ -1,13 +1,19 @@
 
 // Ported from filter_unsuitable_entries.rb and remove_unnecessary_entries.rb files.
 
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::cmp::Ordering;
+use std::io::{BufRead, BufReader};
 use std::fs::File;
 
 use regex::*;
-use rayon::prelude::*;
-
-use super::mozc::get_id;
+use serde::Deserialize;
+
+use super::entry::DictEntry;
+use super::error::{Error, Result, WithPath};
+use super::extsort::{ExternalSorter, SortSettings};
+use super::mozc::{open_mozc_entries, MOZC_NAME};
+use super::pos::pos_table;
+use super::stats::{BuildStats, DropReason};
 use super::util::*;
 
 
@@ -20,187 +26,208 @@ fn to_halfwidth_ascii(s: &str) -> String {
 
 fn collect_numbers(v: &str) -> Option<i32> {
     let mut s = String::new();
-    v.chars().for_each(|c| if '0' <= c && c <= '9' { s.push(c); });
-    if s.len() > 0 { i32::from_str_radix(&s, 10).ok() } else { None }
+    v.chars().for_each(|c| if c.is_ascii_digit() { s.push(c); });
+    if !s.is_empty() { s.parse().ok() } else { None }
 }
 
 
-pub fn remove_unnecessary_entries(filename: &str, dicname: &str) -> std::io::Result<()> {
-    // Remove some hangul here too.
-    let re = Regex::new("[ !?=:・。★☆\u{1100}-\u{11FF}\u{A960}-\u{A97F}\u{D7B0}-\u{D7FF}]").unwrap();
-
-    let mut l2 = Vec::new();
-
-    let f = File::open(filename)?;
-    let mut reader = BufReader::new(f);
-    let mut line = String::new();
-    while let Ok(1..) = reader.read_line(&mut line) {
-        let mut ss = line.split("\t");
-        if let (Some(yomi), Some(id), Some(_), Some(cost), Some(hyouki)) =
-            (ss.next(), ss.next(), ss.next(), ss.next(), ss.next()) {
-            let mut hyouki = String::from(hyouki.trim_end());
-            let mut yomi = String::from(yomi);
-
-            // 表記の全角英数を半角に変換
-            hyouki = to_halfwidth_ascii(&hyouki);
+/// Returns the rule which rejects the normalized entry, `hyouki2` is the surface
+/// without characters which are not read.
+fn unnecessary_rule(yomi: &str, hyouki: &str, hyouki2: &str) -> Option<&'static str> {
+    let (yomi_len, yomi_hira_len) = yomi.chars().fold((0, 0), |(count, hira_count), c| {
+        if ('ぁ'..='ゔ').contains(&c) { (count + 1, hira_count + 1) } else { (count + 1, hira_count) }
+    });
+    let hyouki2_len = hyouki2.chars().count();
+
+    // 読みが2文字以下の場合はスキップ
+    if yomi_len <= 2 {
+        Some("reading has 2 or less characters")
+    // hyouki2が1文字の場合はスキップ
+    } else if hyouki2_len <= 1 {
+        Some("surface has 1 or less readable character")
+    // hyoukiが26文字以上の場合はスキップ
+    } else if hyouki.chars().count() >= 26 {
+        Some("surface has 26 or more characters")
+    // 読みの文字数がhyouki2の4倍を超える場合はスキップ
+    // けやきざかふぉーてぃーしっくす（15文字） 欅坂46（4文字）
+    } else if yomi_len > hyouki2_len * 4 {
+        Some("reading is longer than 4 times of surface")
+    // hyouki2の文字数が読みの文字数より多い場合はスキップ
+    // 英数字表記が削除されるのを防ぐため、hyouki2の文字数は (bytesize / 3) とする。
+    // みすたーちるどれんりふれくしょん（16文字） Mr.Children REFLECTION（22bytes / 3）
+    // あいしす（16文字） アイシス（48bytes / 3）
+    } else if yomi_len < hyouki2.len() / 3 {
+        Some("surface is longer than reading")
+    // 読みがひらがな以外を含む場合はスキップ
+    } else if yomi_len != yomi_hira_len {
+        Some("reading is not hiragana")
+    // hyoukiがコードポイントを含む場合はスキップ
+    // デコードする場合
+    // hyouki = hyouki.gsub(/\\u([\da-fA-F]{4})/){[$1.hex].pack("U")}
+    } else if hyouki.contains("\\u") {
+        Some("surface contains escaped code point")
+    // hyouki2の数字が101以上の場合はスキップ（100円ショップを残す）
+    // 国道120号, 3月26日
+    } else if collect_numbers(hyouki2).unwrap_or(0) > 100 {
+        Some("surface has number larger than 100")
+    } else {
+        None
+    }
+}
 
-            // 表記の「~」を「〜」に置き換える
-            // jawiki-latest-all-titles の表記に合わせる。
-            hyouki = hyouki.replace("~", "〜");
+/// Normalizes surface and reading of the entry, returns None if the entry
+/// is rejected by rules, which is counted in `stats`.
+fn normalize_entry(mut entry: DictEntry, re: &Regex, stats: &mut BuildStats) -> Option<DictEntry> {
+    let mut hyouki = std::mem::take(&mut entry.surface);
+    let mut yomi = std::mem::take(&mut entry.reading);
 
-            // 表記の最初が空白の場合は取る
-            if hyouki.starts_with(" ") {
-                hyouki = String::from(hyouki.trim_start());
-            }
+    // 表記の全角英数を半角に変換
+    hyouki = to_halfwidth_ascii(&hyouki);
 
-            // 表記の全角カンマを半角に変換
-            hyouki = hyouki.replace("，", ", ");
+    // 表記の「~」を「〜」に置き換える
+    // jawiki-latest-all-titles の表記に合わせる。
+    hyouki = hyouki.replace('~', "〜");
 
-            // 表記の最後が空白の場合は取る（「, 」もここで処理）
-            if hyouki.ends_with(" ") {
-                hyouki = String::from(hyouki.trim_end());
-            }
+    // 表記の最初が空白の場合は取る
+    if hyouki.starts_with(' ') {
+        hyouki = String::from(hyouki.trim_start());
+    }
 
-            // 読みにならない文字を削除したhyouki2を作る
-            let hyouki2 = re.replace(&hyouki, "").to_owned();
+    // 表記の全角カンマを半角に変換
+    hyouki = hyouki.replace('，', ", ");
 
-            // hyouki2がひらがなとカタカナだけの場合は、読みをhyouki2から作る
-            // さいたまスーパーアリーナ
-            if yomi.chars()
-               .any(|c| !(('ぁ' <= c && c <= 'ゔ') || ('ァ' <= c && c <= 'ヴ') || c == 'ー')) {
-                yomi = to_hiragana_replace_ie(&hyouki2);
-            }
+    // 表記の最後が空白の場合は取る（「, 」もここで処理）
+    if hyouki.ends_with(' ') {
+        hyouki = String::from(hyouki.trim_end());
+    }
 
-            //let yomi_len = yomi.chars().count();
-            let (yomi_len, yomi_hira_len) = yomi.chars().fold((0, 0), |(count, hira_count), c| {
-                if 'ぁ' <= c && c <= 'ゔ' { (count + 1, hira_count + 1) } else { (count + 1, hira_count) }
-            });
-            let hyouki2_len = hyouki2.chars().count();
-
-            // 読みが2文字以下の場合はスキップ
-            if yomi_len <= 2 ||
-                // hyouki2が1文字の場合はスキップ
-                hyouki2_len <= 1 ||
-                // hyoukiが26文字以上の場合はスキップ
-                hyouki.chars().count() >= 26 ||
-                // 読みの文字数がhyouki2の4倍を超える場合はスキップ
-                // けやきざかふぉーてぃーしっくす（15文字） 欅坂46（4文字）
-                yomi_len > hyouki2_len * 4 ||
-                // hyouki2の文字数が読みの文字数より多い場合はスキップ
-                // 英数字表記が削除されるのを防ぐため、hyouki2の文字数は (bytesize / 3) とする。
-                // みすたーちるどれんりふれくしょん（16文字） Mr.Children REFLECTION（22bytes / 3）
-                // あいしす（16文字） アイシス（48bytes / 3）
-                yomi_len < hyouki2.len() / 3 ||
-                // 読みがひらがな以外を含む場合はスキップ
-                yomi_len != yomi_hira_len ||
-                // hyoukiがコードポイントを含む場合はスキップ
-                // デコードする場合
-                // hyouki = hyouki.gsub(/\\u([\da-fA-F]{4})/){[$1.hex].pack("U")}
-                hyouki.find("\\u").is_some() ||
-                // hyouki2の数字が101以上の場合はスキップ（100円ショップを残す）
-                // 国道120号, 3月26日
-                collect_numbers(&hyouki2).unwrap_or(0) > 100 {
-                line.clear();
-                continue;
-            }
+    // 読みにならない文字を削除したhyouki2を作る
+    let hyouki2 = re.replace_all(&hyouki, "");
 
-            //l2.push(format!("{}\t{}\t{}\t{}\t{}\n", &yomi, id, id, cost, &hyouki));
-            l2.push(format!("{}\t{}\t{}\t{}\t{}\n", &yomi, &hyouki, cost, id, id));
-        }
-        line.clear();
+    // hyouki2がひらがなとカタカナだけの場合は、読みをhyouki2から作る
+    // さいたまスーパーアリーナ
+    if yomi.chars()
+       .any(|c| !(('ぁ'..='ゔ').contains(&c) || ('ァ'..='ヴ').contains(&c) || c == 'ー')) {
+        yomi = to_hiragana_replace_ie(&hyouki2);
     }
 
-    let mut lines = l2;
-
-    // UT辞書の並びを変える
-    // (変更前) げんかん	1823	1823	5278	玄関
-    // (変更後) げんかん	玄関	5278	1823	1823
-    // done by chainging order in the function above
-
-    // Mozc辞書の並びを変えてマークをつける
-    // (変更前) げんかん	1823	1823	6278	玄関
-    // (変更後) げんかん	玄関	*6278	1823	1823
-    {
-        let mut line = String::new();
-        let f = File::open("mozcdic.txt")?;
-        let mut reader = BufReader::new(f);
-        while let Ok(1..) = reader.read_line(&mut line) {
-            let mut ss = line.trim_end().split('\t');
-            if let (Some(yomi), Some(id), Some(_), Some(cost), Some(hyouki)) =
-                (ss.next(), ss.next(), ss.next(), ss.next(), ss.next()) {
-                lines.push(format!("{}\t{}\t*{}\t{}\t{}\n", yomi, hyouki, cost, id, id));
-            }
-        }
+    let rule = unnecessary_rule(&yomi, &hyouki, &hyouki2);
+    entry.surface = hyouki;
+    entry.reading = yomi;
+    if let Some(rule) = rule {
+        stats.drop(&entry, DropReason::Unnecessary, rule);
+        return None;
     }
+    Some(entry)
+}
 
-    lines.par_sort_unstable();
-
-    // この時点での並び。Mozc辞書が先になる
+/// Order of UT and Mozc entries to find duplicates.
+pub fn unnecessary_order(a: &DictEntry, b: &DictEntry) -> Ordering {
+    // UT辞書とMozc辞書を「読み+表記」で並べる
+    // Mozc辞書のエントリを先にし、UT辞書のエントリはコスト順にする
+    // ペナルティはコストを10で割った後に加わるので、10倍してコストに加える
     // げんかん	玄関	*6278	1823	1823
     // げんかん	玄関	5278	1823	1823
+    (&a.reading, &a.surface, a.source != MOZC_NAME, a.cost + a.penalty * 10)
+        .cmp(&(&b.reading, &b.surface, b.source != MOZC_NAME, b.cost + b.penalty * 10))
+}
+
+/// Removes entries which are not suitable for the dictionary, and entries
+/// which duplicate to Mozc OSS dictionary or to other UT entries.
+/// Entries are sorted within the memory budget of `sort`, and kept entries
+/// are passed to `out` in `unnecessary_order`. Removed entries are counted in `stats`.
+pub fn remove_unnecessary_entries_stream<I, M, F>(entries: I, mozc: M, sort: &SortSettings, stats: &mut BuildStats, mut out: F) -> Result<()>
+    where I: IntoIterator<Item = Result<DictEntry>>,
+          M: IntoIterator<Item = Result<DictEntry>>,
+          F: FnMut(DictEntry) -> Result<()> {
+    // Remove some hangul here too.
+    let re = Regex::new("[ !?=:・。★☆\u{1100}-\u{11FF}\u{A960}-\u{A97F}\u{D7B0}-\u{D7FF}]").unwrap();
 
-    let f = File::create(dicname)?;
-    let mut writer = BufWriter::new(f);
+    let mut sorter = ExternalSorter::new(sort, unnecessary_order);
+    for entry in entries {
+        if let Some(entry) = normalize_entry(entry?, &re, stats) {
+            sorter.push(entry)?;
+        }
+    }
+    for entry in mozc {
+        sorter.push(entry?)?;
+    }
 
-    let count = lines.len();
-    for i in 0..count {
-        let s1 = &lines[i];
-        let mut ss1 = s1.split('\t');
-        let (s1_yomi, s1_hyouki, s1_cost) =
-            (ss1.next().unwrap(), ss1.next().unwrap(), ss1.next().unwrap());
+    // 同じ「読み+表記」の最初のエントリ
+    let mut run: Option<(String, String)> = None;
+    // 同じ「読み+表記」の中のMozc辞書のエントリのID
+    let mut mozc_ids: Vec<u16> = Vec::new();
+    // 同じ「読み+表記」の中で見つけたUT辞書のエントリの活用形のIDと、残したエントリの位置
+    // 活用形は同じ「読み+表記」でもIDが異なれば重複としない
+    let mut found: Vec<(_, Option<usize>)> = Vec::new();
+    // 同じ「読み+表記」の中で残したUT辞書のエントリ
+    let mut run_kept: Vec<DictEntry> = Vec::new();
+    for entry in sorter.finish()? {
+        let mut entry = entry?;
+        if !run.as_ref().is_some_and(|(reading, surface)| reading == &entry.reading && surface == &entry.surface) {
+            for kept in run_kept.drain(..) {
+                out(kept)?;
+            }
+            run = Some((entry.reading.clone(), entry.surface.clone()));
+            mozc_ids.clear();
+            found.clear();
+        }
 
         // Mozc辞書はスキップ
-        if s1_cost.starts_with('*') {
+        if entry.source == MOZC_NAME {
+            mozc_ids.push(entry.left_id);
             continue;
         }
 
-        let id = ss1.next().unwrap();
-
-        if i > 0 {
-            let s2 = &lines[i - 1];
-            let mut ss2 = s2.split('\t');
-            let (s2_yomi, s2_hyouki, s2_cost, s2_id) =
-                (ss2.next().unwrap(), ss2.next().unwrap(), ss2.next().unwrap(), ss2.next().unwrap());
-
-            // Mozc辞書と「読み+表記+ID」が重複するUT辞書はスキップ
-            if s2_cost.starts_with('*') &&
-                (s1_yomi == s2_yomi && s1_hyouki == s2_hyouki) &&
-                id == s2_id {
-                continue;
+        // UT辞書内で重複するエントリをコスト順にスキップ
+        let form_ids = entry.form_ids();
+        if let Some((_, kept)) = found.iter().find(|(ids, _)| *ids == form_ids) {
+            stats.drop(&entry, DropReason::UtDuplicate, "kept another entry of the same reading and surface");
+            // 削除したエントリの出典を残したエントリにまとめる
+            if let Some(kept) = kept.and_then(|kept| run_kept.get_mut(kept)) {
+                kept.merge_origins(&mut entry);
             }
+            continue;
+        }
 
-            // UT辞書内で重複するエントリをコスト順にスキップ
-            if !s2_cost.starts_with('*') &&
-                (s1_yomi == s2_yomi && s1_hyouki == s2_hyouki) {
-                continue;
-            }
+        // Mozc辞書と「読み+表記+ID」が重複するUT辞書はスキップ
+        if mozc_ids.contains(&entry.left_id) {
+            found.push((form_ids, None));
+            stats.drop(&entry, DropReason::MozcDuplicate, "Mozc has the same reading, surface and id");
+            continue;
         }
 
-        let v = format!("{}\t{}\t{}\t{}\t{}\n", s1_yomi, id, id, s1_cost, s1_hyouki);
-        writer.write(v.as_bytes())?;
+        found.push((form_ids, Some(run_kept.len())));
+        run_kept.push(entry);
+    }
+    for kept in run_kept {
+        out(kept)?;
     }
-
     Ok(())
 }
 
-pub fn filter_unsuitable_entries(filename: &str, dicname: &str) -> std::io::Result<()> {
-    // Mozc形式に変換した辞書を読み込む
-    // なかいまさひろ	1917	1917	6477	中居正広
-    let lines = read_file(filename)?;
-
-    // フィルタリング対象のIDを取得
-    // 品詞IDを取得
-    let id = get_id(r"(\d*) 名詞,固有名詞,一般,\*,\*,\*,\*")?;
-
-    // 単語フィルタを読み込む
-    let filter_data = read_file("../data/filter/unsuitable-entries.txt")?;
+/// Removes entries which are not suitable for the dictionary, and entries
+/// which duplicate to Mozc OSS dictionary or to other UT entries, sorting
+/// them within the memory budget of `sort`. Removed entries are counted in `stats`.
+pub fn remove_unnecessary_entries(entries: Vec<DictEntry>, mozc: Vec<DictEntry>, sort: &SortSettings, stats: &mut BuildStats) -> Result<Vec<DictEntry>> {
+    let mut kept = Vec::with_capacity(entries.len());
+    remove_unnecessary_entries_stream(entries.into_iter().map(Ok), mozc.into_iter().map(Ok), sort, stats, |entry| {
+        kept.push(entry);
+        Ok(())
+    })?;
+    Ok(kept)
+}
 
+/// Reads unsuitable entries filter, each line is a word or
+/// a regular expression enclosed by `/`.
+pub fn read_unsuitable_filter<R: BufRead>(reader: R) -> Result<RegexSet> {
     // エントリが正規表現になっているときは正規表現を作る
     // /\Aバカ/
     let mut exp = Vec::new();
-    for line in filter_data.lines() {
+    for line in reader.lines() {
+        let line = line?;
         let s = line.trim_end();
-        if s.starts_with("/") && s.ends_with("/") {
+        if s.starts_with('/') && s.ends_with('/') {
             if s.ends_with("\\Z/") {
                 let mut v = String::from(unsafe { s.get_unchecked(1..s.len() - 3) });
                 v.push_str("\\z");
@@ -212,25 +239,129 @@ pub fn filter_unsuitable_entries(filename: &str, dicname: &str) -> std::io::Resu
             exp.push(String::from(s));
         }
     }
-    let res = RegexSet::new(&exp).unwrap();
+    RegexSet::new(&exp).map_err(|e| Error::other(format!("invalid unsuitable filter: {}", e)))
+}
+
+/// Returns true if the entry has one of the specified ids and its surface matches to the filter.
+/// Unsuitable entries are counted in `stats`.
+fn is_unsuitable(entry: &DictEntry, ids: &[u16], filter: &RegexSet, stats: &mut BuildStats) -> bool {
+    if !ids.contains(&entry.left_id) || !filter.is_match(&entry.surface) {
+        return false;
+    }
+    let index = filter.matches(&entry.surface).iter().next().unwrap_or_default();
+    stats.drop(entry, DropReason::Unsuitable, &format!("matches {}", filter.patterns()[index]));
+    true
+}
 
-    let f = File::create(dicname)?;
-    let mut writer = BufWriter::new(f);
+/// Removes entries which have one of the specified ids and whose surface matches to the filter.
+/// Removed entries are counted in `stats`.
+pub fn filter_unsuitable_entries(entries: &mut Vec<DictEntry>, ids: &[u16], filter: &RegexSet, stats: &mut BuildStats) {
+    entries.retain(|entry| !is_unsuitable(entry, ids, filter, stats));
+}
 
-    for line in lines.lines() {
-        let mut s = line.split("\t");
-        if let (Some(_reading), Some(tid), Some(_), Some(_cost), Some(writing)) =
-            (s.next(), s.next(), s.next(), s.next(), s.next()) {
+/// Default file of unsuitable entries filter, relative to the build directory.
+pub const UNSUITABLE_FILE_NAME: &str = "../data/filter/unsuitable-entries.txt";
+
+/// Filter applied to entries in the build directory.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterStage {
+    /// Removes entries listed in the unsuitable entries filter.
+    Unsuitable,
+    /// Removes entries which Mozc already has.
+    Unnecessary,
+}
 
-            // フィルタリング対象のIDの場合は実行
-            if tid == &id && res.is_match(writing) {
-                continue;
+impl FilterStage {
+    /// Applies the filter to entries, `unsuitable_filter` is the file
+    /// of unsuitable entries filter. Kept entries are passed to `out`,
+    /// and removed entries are counted in `stats`.
+    pub fn apply_stream<I, F>(self, entries: I, unsuitable_filter: &str, sort: &SortSettings, stats: &mut BuildStats, mut out: F) -> Result<()>
+        where I: IntoIterator<Item = Result<DictEntry>>,
+              F: FnMut(DictEntry) -> Result<()> {
+        match self {
+            FilterStage::Unsuitable => {
+                let (ids, filter) = read_unsuitable_filter_in_build(unsuitable_filter)?;
+                for entry in entries {
+                    let entry = entry?;
+                    if !is_unsuitable(&entry, &ids, &filter, stats) {
+                        out(entry)?;
+                    }
+                }
+                Ok(())
             }
-
-            writer.write(line.as_bytes())?;
-            writer.write(b"\n")?;
+            FilterStage::Unnecessary => remove_unnecessary_entries_stream(entries, open_mozc_entries()?, sort, stats, out),
         }
     }
 
+    /// Applies the filter to entries in memory, Mozc entries are sorted with
+    /// them within the memory budget of `sort`.
+    pub fn apply(self, entries: Vec<DictEntry>, unsuitable_filter: &str, sort: &SortSettings, stats: &mut BuildStats) -> Result<Vec<DictEntry>> {
+        let mut kept = Vec::with_capacity(entries.len());
+        self.apply_stream(entries.into_iter().map(Ok), unsuitable_filter, sort, stats, |entry| {
+            kept.push(entry);
+            Ok(())
+        })?;
+        Ok(kept)
+    }
+}
+
+/// Returns ids of proper nouns except places which are checked by unsuitable
+/// entries filter, and the filter read from the file.
+fn read_unsuitable_filter_in_build(filter_file: &str) -> Result<(Vec<u16>, RegexSet)> {
+    // フィルタリング対象のIDを取得
+    // 地名は郵便番号ファイルから生成するので対象外
+    let ids = pos_table()?.find(|pos| pos.pos == "名詞" && pos.subcategory1 == "固有名詞" && pos.subcategory2 != "地域")
+        .map(|pos| pos.id).collect::<Vec<u16>>();
+
+    // 単語フィルタを読み込む
+    let f = File::open(filter_file).with_path(filter_file)?;
+    let filter = read_unsuitable_filter(BufReader::new(f)).with_path(filter_file)?;
+
+    Ok((ids, filter))
+}
+
+/// Removes entries which have unsuitable surface listed in the filter file,
+/// such as data/filter/unsuitable-entries.txt file.
+/// Only entries which have ids of proper nouns except places are checked.
+pub fn filter_unsuitable_entries_in_build(entries: &mut Vec<DictEntry>, filter_file: &str, stats: &mut BuildStats) -> Result<()> {
+    let (ids, filter) = read_unsuitable_filter_in_build(filter_file)?;
+
+    // フィルタリング対象のIDの場合は実行
+    filter_unsuitable_entries(entries, &ids, &filter, stats);
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(reading: &str, id: u16, cost: i32, surface: &str, source: &'static str, inflected: bool) -> DictEntry {
+        let mut entry = DictEntry::new(String::from(reading), id, cost, String::from(surface), source);
+        entry.inflected = inflected;
+        entry
+    }
+
+    #[test]
+    fn remove_duplicates_keeps_forms_of_different_ids() {
+        let entries = vec![
+            // 確かめ の未然形と連用形は別のエントリとして残す
+            entry("たしかめ", 2, 6000, "確かめ", "neologd", true),
+            entry("たしかめ", 3, 6000, "確かめ", "neologd", true),
+            entry("たしかめ", 3, 5000, "確かめ", "sudachidict", true),
+            // Mozcと同じIDの活用形は削除する
+            entry("たしかめ", 4, 6000, "確かめ", "neologd", true),
+            // 活用形でないエントリは「読み+表記」でまとめる
+            entry("げんかん", 1, 6278, "玄関", "neologd", false),
+            entry("げんかん", 5, 5278, "玄関", "sudachidict", false),
+        ];
+        let mozc = vec![entry("たしかめ", 4, 3000, "確かめ", MOZC_NAME, false)];
+        let mut stats = BuildStats::default();
+        let kept = remove_unnecessary_entries(entries, mozc, &SortSettings::in_memory(), &mut stats).unwrap();
+
+        let words: Vec<(&str, u16, &str)> = kept.iter().map(|e| (e.reading.as_str(), e.left_id, e.source)).collect();
+        assert_eq!(words, [("げんかん", 5, "sudachidict"), ("たしかめ", 3, "sudachidict"), ("たしかめ", 2, "neologd")]);
+        assert_eq!(kept[1].merged.len(), 1);
+    }
+}
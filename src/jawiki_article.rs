@@ -2,64 +2,217 @@
 // Ported from generate_jawiki_ut.rb and convert_jawiki_ut_to_mozcdic.rb files.
 // Content of convert_jawiki_ut_to_mozcdic.rb is merged into some functions.
 
-use std::io::{BufWriter, Read, Write};
+use std::collections::HashMap;
+use std::io::BufReader;
 use std::fs::File;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
+use bzip2::read::MultiBzDecoder;
 use regex::*;
-use rayon::{prelude::*, ThreadPoolBuilder};
-use bzip2_rs::{decoder::ParallelDecoderReader, RayonThreadPool};
-//use bzip2::read::MultiBzDecoder;
-
-use super::mozc::get_id;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use super::entry::*;
+use super::error::{Error, Result, SkippedLines, WithPath};
+use super::license::License;
+use super::mediawiki::{read_multistream_index, read_stream_pages, Page, NS_ARTICLE};
+use super::pos::pos_table;
+use super::fetch::Fetcher;
+use super::source::Source;
+use super::stats::SourceStats;
 use super::util::*;
 
 
-fn check_jawiki_ut_version() -> std::io::Result<(String, bool)> {
-    const INDEX_FILE_NAME: &str = "jawiki-index.html";
-    command_wait("wget", vec!["-q", "https://dumps.wikimedia.org/jawiki/latest/", "-O", INDEX_FILE_NAME])?;
+const NAME: &str = "jawiki";
+const DIC_NAME: &str = "mozcdic-ut-jawiki.txt";
+
+/// Cost of readings of the title.
+const READING_COST: i32 = 6000;
+/// Penalty added to each following reading, such as にっぽん of （にほん、にっぽん）,
+/// after the cost is recalculated.
+const SECONDARY_READING_PENALTY: i32 = 1000;
+/// Penalty added to redirect titles which are aliases of the target.
+const ALIAS_PENALTY: i32 = 1000;
+
 
-    let jawiki_index = read_file(INDEX_FILE_NAME)?;
+fn check_jawiki_ut_version(fetcher: &Fetcher) -> Result<(String, bool)> {
+    let jawiki_index = fetcher.fetch_text("https://dumps.wikimedia.org/jawiki/latest/")?;
     if let Some((_, date)) = jawiki_index.split_once("jawiki-latest-pages-articles-multistream.xml.bz2</a>") {
         if let Some((date, _)) = date.trim_start().split_once(" ") {
             let utdic = format!("jawiki-ut-{}.txt", date);
             if File::open(&utdic).is_ok() {
                 println!("{} already exists.", date);
-                return Ok((utdic, true));
+                Ok((utdic, true))
             } else {
                 remove_matched(".", r"jawiki-ut-.*\.txt")?;
-                return Ok((utdic, false));
+                Ok((utdic, false))
             }
         } else {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "date is strange"));
+            Err(Error::other("date is strange"))
         }
     } else {
-        return Err(std::io::Error::new(std::io::ErrorKind::Other, "index data is broken"));
+        Err(Error::other("index data is broken"))
     }
 }
 
 
-fn generate_jawiki_ut(article: &str, ids: &str, out: Arc<Mutex<Vec<String>>>, re_remove_chars: Regex, re_ref: Regex, re_chars: Regex) {
-    // タイトルから表記を作る
+/// Regular expressions used for each article.
+struct Patterns {
+    remove_chars: Regex,
+    reference: Regex,
+    chars: Regex,
+    lang: Regex,
+    furigana: Regex,
+    ruby: Regex,
+    ruby_text: Regex,
+    ruby_markup: Regex,
+    link: Regex,
+}
 
-    // タイトルを取得
-    let title = if let Some((title, _)) = article.split_once("</title>") {
-        if let Some((_, title)) = title.split_once("<title>") {
-            title
-        } else {
-            return;
+impl Patterns {
+    fn new() -> Patterns {
+        Patterns {
+            remove_chars: Regex::new(r"[!?=:・。]").unwrap(),
+            reference: Regex::new(r"<ref.*?</ref>").unwrap(),
+            chars: Regex::new(r##"[ '"「」『』]"##).unwrap(),
+            // {{Lang|ja|表記}}
+            lang: Regex::new(r"\{\{[Ll]ang\|[A-Za-z-]+\|([^|{}]*)\}\}").unwrap(),
+            // {{読み仮名|表記|よみ}}、{{読み仮名_ruby不使用|表記|よみ}}
+            furigana: Regex::new(r"\{\{読み仮名(?:_?ruby不使用)?\|((?:\[\[[^\]]*\]\]|[^|{}])*)\|([^|{}]*)").unwrap(),
+            // <ruby>表記<rp>(</rp><rt>よみ</rt><rp>)</rp></ruby>
+            ruby: Regex::new(r"<ruby>(.*?)</ruby>").unwrap(),
+            ruby_text: Regex::new(r"<rt>(.*?)</rt>").unwrap(),
+            ruby_markup: Regex::new(r"<rp>.*?</rp>|<rt>.*?</rt>|</?rb>").unwrap(),
+            // [[記事名|表示]]、[[記事名]]
+            link: Regex::new(r"\[\[(?:[^|\]]*\|)?([^\]]*)\]\]").unwrap(),
         }
-    } else {
-        return;
-    };
+    }
+}
 
-    // 記事を取得
-    let article = if let Some((_, article)) = article.split_once("xml:space=\"preserve\">") {
-        article
+/// Converts the reading to hiragana. Returns None if it is not a reading.
+fn reading_to_hiragana(yomi: &str) -> Option<String> {
+    // 読みが空か「ー」で始まる場合はスキップ
+    if yomi.is_empty() || yomi.starts_with('ー') {
+        return None;
+    }
+
+    // 読みのカタカナをひらがなに変換
+    let yomi = to_hiragana_replace_ie(yomi);
+
+    // 読みがひらがな以外を含む場合はスキップ
+    if yomi.chars()
+           .any(|c| !(('ぁ'..='ゔ').contains(&c) || c == 'ー')) {
+        return None;
+    }
+    Some(yomi)
+}
+
+/// Adds the reading unless it is already added.
+fn push_reading(readings: &mut Vec<String>, yomi: String) {
+    if !readings.contains(&yomi) {
+        readings.push(yomi);
+    }
+}
+
+/// Adds readings of the title given by furigana templates or ruby markup
+/// in the line, whose spaces and quotes are already removed.
+fn template_readings(line: &str, hyouki: &str, patterns: &Patterns, readings: &mut Vec<String>) {
+    // {{読み仮名|[[大倉忠義|大倉忠義]]|おおくらただよし}}
+    if line.contains("{{読み仮名") {
+        for caps in patterns.furigana.captures_iter(line) {
+            let surface = patterns.link.replace_all(&caps[1], "$1");
+            if surface == hyouki {
+                for yomi in caps[2].split('、').filter_map(reading_to_hiragana) {
+                    push_reading(readings, yomi);
+                }
+            }
+        }
+    }
+
+    // 連続した<ruby>の親文字をつなげて表記と比べる
+    // <ruby>皆藤<rt>かいとう</rt></ruby><ruby>愛子<rt>あいこ</rt></ruby>
+    if line.contains("<ruby>") {
+        let rubies: Vec<_> = patterns.ruby.captures_iter(line).map(|caps| {
+            let m = caps.get(0).unwrap();
+            let inner = &caps[1];
+            let base = patterns.ruby_markup.replace_all(inner, "").to_string();
+            let text: String = patterns.ruby_text.captures_iter(inner).map(|t| t[1].to_string()).collect();
+            (m.start(), m.end(), base, text)
+        }).collect();
+        for start in 0..rubies.len() {
+            let mut surface = String::new();
+            let mut text = String::new();
+            for (i, (_, _, base, rt)) in rubies.iter().enumerate().skip(start) {
+                if i > start && rubies[i - 1].1 != rubies[i].0 {
+                    break;
+                }
+                surface.push_str(base);
+                text.push_str(rt);
+                if surface == hyouki {
+                    if let Some(yomi) = reading_to_hiragana(&text) {
+                        push_reading(readings, yomi);
+                    }
+                }
+                if surface.len() >= hyouki.len() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Adds readings in the parentheses after the title, which may be separated
+/// by 「、」. The first part is a reading unless it is labeled, and other parts
+/// are readings only if they are labeled such as 「旧読み:」.
+fn parenthesis_readings(line: &str, hyouki: &str, readings: &mut Vec<String>) {
+    // 「表記(読み」を検索
+    let mut yomi = if let Some(index) = line.find(&format!("{}(", hyouki)) {
+        line.get(index + hyouki.len() + 1..).unwrap()
     } else {
         return;
     };
 
+    // 読みを「)」で切る
+    if let Some((pre, _)) = yomi.split_once(")") {
+        yomi = pre;
+    }
+
+    // 読みを「[[」で切る
+    // ないとうときひろ[[1963年]]
+    if let Some((pre, _)) = yomi.split_once("[[") {
+        yomi = pre;
+    }
+
+    // 読みを「、」で区切る
+    // にっぽん、旧読み:にほん
+    // 最初の部分と「読み:」の見出しがある部分だけを読みにする
+    // 「英:」「旧称:」などの見出しがある部分や見出しのない2番目以降の部分は読みではない
+    // にっぽん、英: Japan、旧称: やまと
+    for (i, yomi) in yomi.split("、").enumerate() {
+        let yomi = match yomi.rsplit_once(':') {
+            Some((label, yomi)) if label.ends_with("読み") || label.ends_with("よみ") => yomi,
+            Some(_) => continue,
+            None if i == 0 => yomi,
+            None => continue,
+        };
+
+        // 読みが全てカタカナの場合はスキップ
+        // ミュージシャン一覧(グループ)
+        if !yomi.chars()
+               .any(|c| !(('ァ'..='ヴ').contains(&c) || c == 'ー')) {
+            continue;
+        }
+
+        // 読みのカタカナをひらがなに変換
+        if let Some(yomi) = reading_to_hiragana(yomi) {
+            push_reading(readings, yomi);
+        }
+    }
+}
+
+/// Makes the surface from the title of the page.
+fn title_to_hyouki(title: &str) -> String {
     // タイトルの全角英数を半角に変換してUTF-8で出力
     // -m0 MIME の解読を一切しない
     // -Z1 全角空白を ASCII の空白に変換
@@ -72,48 +225,85 @@ fn generate_jawiki_ut(article: &str, ids: &str, out: Arc<Mutex<Vec<String>>>, re
     if let Some((prefix, _)) = hyouki.split_once(" (") {
         hyouki = String::from(prefix);
     }
+    hyouki
+}
 
+/// Returns the rule which skips the surface, or None if the surface is used.
+fn hyouki_rule(hyouki: &str, patterns: &Patterns) -> Option<&'static str> {
     // 26文字以上の場合はスキップ。候補ウィンドウが大きくなりすぎる
     if hyouki.chars().count() >= 26 ||
-        // 内部用のページをスキップ
+        // 曖昧さ回避のページをスキップ
         hyouki.find("(曖昧さ回避)").is_some() ||
-        hyouki.starts_with("Wikipedia:") ||
-        hyouki.starts_with("ファイル:") ||
-        hyouki.starts_with("Portal:") ||
-        hyouki.starts_with("Help:") ||
-        hyouki.starts_with("Template:") ||
-        hyouki.starts_with("Category:") ||
-        hyouki.starts_with("プロジェクト:") ||
         // スペースがある場合はスキップ
         // 記事のスペースを削除してから「表記(読み」を検索するので、残してもマッチしない。
         hyouki.find(" ").is_some() ||
         // 「、」がある場合はスキップ
         // 記事の「、」で読みを切るので、残してもマッチしない。
         hyouki.find("、").is_some() {
-        return;
+        return Some("title is not used");
     }
 
-    // 読みにならない文字を削除したhyouki2を作る
-    let hyouki2 = re_remove_chars.replace_all(&hyouki, |_: &Captures| String::new());
-
-    // hyouki2が1文字の場合はスキップ
+    // 読みにならない文字を削除したhyouki2が1文字の場合はスキップ
+    let hyouki2 = patterns.remove_chars.replace_all(hyouki, |_: &Captures| String::new());
     if hyouki2.chars().count() <= 1 {
-        return;
+        return Some("title is too short");
+    }
+    None
+}
+
+/// Returns true if the surface consists of hiragana and katakana only.
+fn is_kana(hyouki: &str) -> bool {
+    !hyouki.chars()
+           .any(|c| !(('ぁ'..='ゔ').contains(&c) || ('ァ'..='ヴ').contains(&c) || c == 'ー'))
+}
+
+/// Makes entries from the title of the page and the readings found in the
+/// lead of the article, the first reading gets the lowest cost.
+/// Returns no entries if the page is skipped, which is counted in `skipped`.
+fn generate_jawiki_ut(page: &Page, page_no: usize, id: u16, skipped: &Mutex<&mut SkippedLines>,
+                      patterns: &Patterns) -> Vec<DictEntry> {
+    // タイトルから表記を作る
+
+    // 記事以外の名前空間のページをスキップ
+    if page.ns != NS_ARTICLE {
+        skipped.lock().unwrap().rule("page is not an article", page_no, &page.title);
+        return Vec::new();
+    }
+
+    // タイトルを取得
+    let title = page.title.as_str();
+    if title.is_empty() {
+        skipped.lock().unwrap().rule("no title", page_no, &page.text);
+        return Vec::new();
+    }
+
+    // 記事を取得
+    let article = page.text.as_str();
+    if article.is_empty() {
+        skipped.lock().unwrap().rule("no text", page_no, title);
+        return Vec::new();
+    }
+
+    let hyouki = title_to_hyouki(title);
+    if let Some(rule) = hyouki_rule(&hyouki, patterns) {
+        skipped.lock().unwrap().rule(rule, page_no, &hyouki);
+        return Vec::new();
     }
 
+    // 読みにならない文字を削除したhyouki2を作る
+    let hyouki2 = patterns.remove_chars.replace_all(&hyouki, |_: &Captures| String::new());
+
     // hyouki2がひらがなとカタカナだけの場合は、読みをhyouki2から作る
     // さいたまスーパーアリーナ
-    if !hyouki2.chars()
-              .any(|c| !(('ぁ' <= c && c <= 'ゔ') || ('ァ' <= c && c <= 'ヴ') || c == 'ー')) {
+    if is_kana(&hyouki2) {
         let yomi = to_hiragana_replace_ie(&hyouki2);
 
-        let v = format!("{}{}{}\n", &yomi, ids, &hyouki);
-        out.lock().unwrap().push(v);
-        return;
+        return vec![DictEntry::new(yomi, id, READING_COST, hyouki, NAME).with_record(page_no)];
     }
 
     // 記事の量を減らす
-    let lines = if article.starts_with("{{") {
+    // 冒頭が読み仮名のテンプレートの場合は残す
+    let lines = if article.starts_with("{{") && !article.starts_with("{{読み仮名") {
         // 冒頭の連続したテンプレートを1つにまとめる
         let lines = article.replace("}}\n{{", "");
         // 冒頭のテンプレートを削除
@@ -131,6 +321,7 @@ fn generate_jawiki_ut(article: &str, ids: &str, out: Arc<Mutex<Vec<String>>>, re
     let mut n = 0;
 
     // 記事から読みを作る
+    let mut readings = Vec::new();
 
     for line in lines.split("\n") {
         n += 1;
@@ -148,218 +339,428 @@ fn generate_jawiki_ut(article: &str, ids: &str, out: Arc<Mutex<Vec<String>>>, re
         // 「<ref 」から「</ref>」までを削除
         // '''皆藤 愛子'''<ref>一部のプロフィールが</ref>(かいとう あいこ、[[1984年]]
         // '''大倉 忠義'''（おおくら ただよし<ref name="oricon"></ref>、[[1985年]]
-        if s.find("&lt;ref").is_some() {
-            s = re_ref.replace(&s, |_: &Captures| String::new()).to_string();
+        if s.find("<ref").is_some() {
+            s = patterns.reference.replace(&s, |_: &Captures| String::new()).to_string();
         }
 
         // スペースと '"「」『』 を削除
         // '''皆藤 愛子'''(かいとう あいこ、[[1984年]]
-        s = re_chars.replace_all(&s, |_: &Captures| String::new()).to_string();
+        s = patterns.chars.replace_all(&s, |_: &Captures| String::new()).to_string();
 
-        // 「表記(読み」を検索
-        let mut yomi = if let Some(index) = s.find(&format!("{}(", &hyouki)) {
-            s.get(index + hyouki.len() + 1..).unwrap()
-        } else {
-            continue;
-        };
-
-        // 読みを「)」で切る
-        if let Some((pre, _)) = yomi.split_once(")") {
-            yomi = pre;
-        }
-        if yomi.len() == 0 {
-            continue;
+        // {{Lang|ja|表記}} を表記に置き換える
+        if s.contains("{{") {
+            s = patterns.lang.replace_all(&s, "$1").to_string();
         }
 
-        // 読みを「[[」で切る
-        // ないとうときひろ[[1963年]]
-        if let Some((pre, _)) = yomi.split_once("[[") {
-            yomi = pre;
-        }
-        if yomi.len() == 0 {
-            continue;
-        }
+        // テンプレートと「表記(読み」から読みを取得
+        template_readings(&s, &hyouki, patterns, &mut readings);
+        parenthesis_readings(&s, &hyouki, &mut readings);
 
-        // 読みを「、」で切る
-        // かいとうあいこ、[[1984年]]
-        if let Some((pre, _)) = yomi.split_once("、") {
-            yomi = pre;
-        }
-        if yomi.len() == 0 {
-            continue;
+        // 読みが見つかった行で終える
+        if !readings.is_empty() {
+            break;
         }
+    }
+
+    if readings.is_empty() {
+        skipped.lock().unwrap().rule("no reading in article", page_no, &hyouki);
+    }
 
-        // HTMLの特殊文字を変換
-        hyouki = hyouki.replace("&amp;", "&");
-        hyouki = hyouki.replace("&quot;", "\"");
+    // 2番目以降の読みはコストの再計算後にペナルティを加える
+    readings.into_iter().enumerate().map(|(i, yomi)| {
+        DictEntry::new(yomi, id, READING_COST, hyouki.clone(), NAME).with_record(page_no)
+            .with_penalty(SECONDARY_READING_PENALTY * i as i32)
+    }).collect()
+}
 
-        // 読みが「ー」で始まる場合はスキップ
-        if let Some('ー') = yomi.chars().next() {
-            continue;
-        }
+/// Redirect page whose title may be an alias of the target article.
+#[derive(Debug)]
+struct Redirect {
+    page_no: usize,
+    title: String,
+    target: String,
+}
 
-        // 読みが全てカタカナの場合はスキップ
-        // ミュージシャン一覧(グループ)
-        if !yomi.chars()
-               .any(|c| !(('ァ' <= c && c <= 'ヴ') || c == 'ー')) {
-            continue;
-        }
+/// Makes entries from the article, or returns the redirect to make aliases
+/// after all articles are read.
+fn process_page(page: &Page, page_no: usize, id: u16, skipped: &Mutex<&mut SkippedLines>,
+                patterns: &Patterns) -> (Vec<DictEntry>, Option<Redirect>) {
+    match page.redirect_target() {
+        Some(target) if page.ns == NS_ARTICLE => (Vec::new(), Some(Redirect {
+            page_no,
+            title: page.title.clone(),
+            target: String::from(target),
+        })),
+        _ => (generate_jawiki_ut(page, page_no, id, skipped, patterns), None),
+    }
+}
 
-        // 読みのカタカナをひらがなに変換
-        let yomi = to_hiragana_replace_ie(yomi);
+/// Pairs of kanji in old or variant forms and their common forms, which are
+/// read the same in names, such as 髙 of 高.
+const KANJI_VARIANTS: &[(char, char)] = &[
+    ('髙', '高'), ('﨑', '崎'), ('嵜', '崎'), ('國', '国'), ('學', '学'), ('齋', '斎'),
+    ('齊', '斉'), ('邊', '辺'), ('邉', '辺'), ('澤', '沢'), ('濱', '浜'), ('濵', '浜'),
+    ('廣', '広'), ('櫻', '桜'), ('龍', '竜'), ('瀧', '滝'), ('德', '徳'), ('榮', '栄'),
+    ('眞', '真'), ('壽', '寿'), ('藏', '蔵'), ('實', '実'), ('會', '会'), ('舊', '旧'),
+    ('體', '体'), ('圓', '円'), ('萬', '万'), ('縣', '県'), ('驛', '駅'), ('鐵', '鉄'),
+    ('黑', '黒'), ('發', '発'), ('氣', '気'), ('關', '関'), ('淺', '浅'), ('惠', '恵'),
+    ('彌', '弥'), ('檜', '桧'), ('嶋', '島'), ('嶌', '島'), ('晉', '晋'), ('藝', '芸'),
+    ('樂', '楽'), ('團', '団'), ('區', '区'), ('臺', '台'), ('灣', '湾'), ('戰', '戦'),
+    ('對', '対'), ('與', '与'), ('聲', '声'), ('寶', '宝'), ('變', '変'), ('禮', '礼'),
+    ('靜', '静'), ('條', '条'), ('檢', '検'), ('鹽', '塩'), ('劍', '剣'), ('應', '応'),
+    ('廳', '庁'), ('豐', '豊'), ('兒', '児'), ('傳', '伝'), ('冨', '富'), ('𠮷', '吉'),
+];
+
+/// Returns the common form of the kanji, or the character itself.
+fn kanji_common_form(c: char) -> char {
+    KANJI_VARIANTS.iter().find(|(variant, _)| *variant == c).map_or(c, |(_, common)| *common)
+}
 
-        // 読みがひらがな以外を含む場合はスキップ
-        if yomi.chars()
-               .any(|c| !(('ぁ' <= c && c <= 'ゔ') || c == 'ー')) {
-            continue;
+/// Returns true if the alias is written differently from the target but
+/// read the same, such as 國學院 of 国学院 or ジャンポール of ジャン・ポール.
+/// Width of alphanumerics is already unified in both.
+fn is_spelling_variant(alias: &str, target: &str, patterns: &Patterns) -> bool {
+    // 記号を除き、旧字体と異体字を通用字体にすると同じ表記
+    // 髙橋 → 高橋
+    let common_form = |s: &str| -> String {
+        patterns.remove_chars.replace_all(s, "").chars().map(kanji_common_form).collect()
+    };
+    common_form(alias) == common_form(target)
+}
+
+/// Returns true if the alias is an abbreviation of the target, which starts
+/// with the first character of the target and skips some of the rest, such as
+/// きむたく of きむらたくや or 東大 of 東京大学. A prefix of the target, such as
+/// あいす of あいすくりーむ, is not an abbreviation.
+fn is_abbreviation(alias: &str, target: &str) -> bool {
+    let mut rest = target.chars();
+    alias.chars().next() == target.chars().next() && !target.starts_with(alias) &&
+        alias.chars().all(|c| rest.any(|t| t == c))
+}
+
+/// Makes entries of the redirect title with readings of the target article,
+/// if the title is a spelling variant of the target. If the title is written
+/// in kana and abbreviates a reading of the target, such as キムタク of
+/// 木村拓哉, the target gets the reading of the title. Abbreviations in kanji,
+/// such as 東大 of 東京大学, and other redirects are skipped, since their
+/// readings are not known.
+fn generate_alias(redirect: &Redirect, targets: &HashMap<&str, Vec<(&str, i32)>>, id: u16,
+                  skipped: &Mutex<&mut SkippedLines>, patterns: &Patterns) -> Vec<DictEntry> {
+    let hyouki = title_to_hyouki(&redirect.title);
+    if let Some(rule) = hyouki_rule(&hyouki, patterns) {
+        skipped.lock().unwrap().rule(rule, redirect.page_no, &hyouki);
+        return Vec::new();
+    }
+
+    // 転送先の記事の読みを取得
+    let target = title_to_hyouki(&redirect.target);
+    let readings = if let Some(readings) = targets.get(target.as_str()) {
+        readings
+    } else {
+        skipped.lock().unwrap().rule("redirect target has no reading", redirect.page_no, &hyouki);
+        return Vec::new();
+    };
+
+    // 全角と半角だけが異なる表記は転送先と同じエントリになる
+    if hyouki == target {
+        skipped.lock().unwrap().rule("redirect title is the same as the target", redirect.page_no, &hyouki);
+        return Vec::new();
+    }
+
+    let hyouki2 = patterns.remove_chars.replace_all(&hyouki, |_: &Captures| String::new());
+    if is_kana(&hyouki2) {
+        // 転送先の読みを略したかなだけを読みにして転送先の表記を出力する
+        // キムタク → 木村拓哉
+        // 関連語や上位語のかなは転送先の読みにならないのでスキップ
+        // ネコ → イエネコ、アイス → アイスクリーム
+        let yomi = to_hiragana_replace_ie(&hyouki2);
+        if readings.iter().any(|(reading, _)| *reading == yomi) {
+            skipped.lock().unwrap().rule("redirect title is a reading of the target", redirect.page_no, &hyouki);
+            return Vec::new();
+        }
+        if !readings.iter().any(|(reading, _)| is_abbreviation(&yomi, reading)) {
+            skipped.lock().unwrap().rule("redirect title in kana is not an abbreviation of the target", redirect.page_no, &hyouki);
+            return Vec::new();
         }
+        return vec![DictEntry::new(yomi, id, READING_COST, target, NAME).with_record(redirect.page_no)
+            .with_penalty(ALIAS_PENALTY)];
+    }
 
-        let v = format!("{}{}{}\n", &yomi, ids, &hyouki);
-        out.lock().unwrap().push(v);
-        return;
+    // 漢字の略称は読みが分からないのでスキップ
+    // 東大 → 東京大学
+    if is_abbreviation(&hyouki, &target) {
+        skipped.lock().unwrap().rule("redirect title is an abbreviation in kanji", redirect.page_no, &hyouki);
+        return Vec::new();
+    }
+
+    if !is_spelling_variant(&hyouki, &target, patterns) {
+        skipped.lock().unwrap().rule("redirect title is not a spelling variant", redirect.page_no, &hyouki);
+        return Vec::new();
     }
+    readings.iter().enumerate().map(|(i, (yomi, _))| {
+        DictEntry::new(String::from(*yomi), id, READING_COST, hyouki.clone(), NAME).with_record(redirect.page_no)
+            .with_penalty(ALIAS_PENALTY + SECONDARY_READING_PENALTY * i as i32)
+    }).collect()
 }
 
-const LATEST_FILE_NAME: &str = "jawiki-latest-pages-articles-multistream.xml.bz2";
+/// Makes entries of redirect titles which are aliases of articles of the entries.
+fn generate_aliases(entries: &[DictEntry], redirects: &[Redirect], id: u16,
+                    skipped: &Mutex<&mut SkippedLines>, patterns: &Patterns) -> Vec<DictEntry> {
+    // 転送先の表記ごとに読みをペナルティの順にまとめる
+    let mut targets: HashMap<&str, Vec<(&str, i32)>> = HashMap::new();
+    for entry in entries {
+        let readings = targets.entry(entry.surface.as_str()).or_default();
+        match readings.iter_mut().find(|(yomi, _)| *yomi == entry.reading) {
+            Some((_, penalty)) => *penalty = (*penalty).min(entry.penalty),
+            None => readings.push((entry.reading.as_str(), entry.penalty)),
+        }
+    }
+    for readings in targets.values_mut() {
+        readings.sort_by_key(|(_, penalty)| *penalty);
+    }
 
-fn run_thread_generate_jawiki_ut(utdic: &str, dicname: &str) -> std::io::Result<()> {
-    let re_remove_chars = Regex::new(r"[!?=:・。]").unwrap();
-    let re_ref = Regex::new(r"&lt;ref.*?&lt;/ref&gt;").unwrap();
-    let re_chars = Regex::new(r##"[ '"「」『』]"##).unwrap();
+    redirects.par_iter().flat_map_iter(|redirect| {
+        generate_alias(redirect, &targets, id, skipped, patterns)
+    }).collect()
+}
 
-    // Mozcの品詞IDを取得
-    //「名詞,固有名詞,人名,一般,*,*」は優先度が低いので使わない。
-    //「名詞,固有名詞,一般,*,*,*」は後でフィルタリングする。
-    let id = get_id(r"(\d*) 名詞,固有名詞,一般,\*,\*,\*,\*")?;
-    let ids = format!("\t{}\t{}\t6000\t", id, id);
+const LATEST_FILE_NAME: &str = "jawiki-latest-pages-articles-multistream.xml.bz2";
+const LATEST_INDEX_NAME: &str = "jawiki-latest-pages-articles-multistream-index.txt.bz2";
 
+/// Makes thread pool of physical cores.
+fn thread_pool() -> Result<ThreadPool> {
     // Parallel のプロセス数を (物理コア数) にする
     let core_num = get_core_count()?;
-    let pool = ThreadPoolBuilder::new().num_threads(core_num).build().unwrap();
-
-    let fr = File::open(LATEST_FILE_NAME)?;
-    // TODO, ParallelDecoderReader is twice faster but makes strange result.
-    let mut reader = ParallelDecoderReader::new(fr, RayonThreadPool, 1024 * 1024 * 16);
-    //let mut reader = MultiBzDecoder::new(fr);
-
-    const BLOCK_SIZE: usize = 900 * 1000;
-    const BUF_SIZE: usize = BLOCK_SIZE * 260;
-    // bzip2 can load 900K bytes per read from multistreams archive.
-
-    let read_out = BUF_SIZE;
-
-    let mut broken_bytes = Vec::with_capacity(16);
-    let mut remained = Vec::with_capacity(64 * 1024);
-    let mut buf = String::with_capacity(BUF_SIZE);
-
-    // We need over 1060000 entries.
-    let out = Arc::new(Mutex::new(Vec::with_capacity(1 * 1024 * 1024 + 16 * 1024)));
-
-    loop {
-        //println!("Reading...");
-        let len = {
-            // Copy remained data to start of the buffer if exists.
-            let remained_len = remained.len();
-            if remained_len > 0 {
-                unsafe { buf.as_mut_vec()[0..remained_len].copy_from_slice(&remained) };
-                remained.clear();
-            }
+    ThreadPoolBuilder::new().num_threads(core_num).build()
+        .map_err(|e| Error::other(format!("failed to create thread pool: {}", e)))
+}
 
-            // Read data.
-            let len = {
-                let v = &mut unsafe { buf.as_mut_vec() };
-                unsafe { v.set_len(BUF_SIZE) };
-                let mut total_len = 0;
-                //let it = std::time::Instant::now();
-                while total_len + remained_len <= read_out {
-                    let l = reader.read(&mut v.as_mut_slice()[total_len + remained_len..]).unwrap();
-                    total_len += l;
-                    if l == 0 {
-                        break;
-                    }
-                }
-                total_len
-            };
-
-            // Cut at char boundary to make string UTF-8 safe.
-            if len > 0 {
-                let index = (0..len + remained_len)
-                    .rev()
-                    .find(|&i| buf.is_char_boundary(i))
-                    .unwrap_or(BUF_SIZE);
-                if index != BUF_SIZE && !buf.as_bytes()[index].is_ascii() {
-                    broken_bytes.extend_from_slice(&buf.as_bytes()[index..len + remained_len]);
-                    unsafe { buf.as_mut_vec().truncate(index) };
-                } else {
-                    unsafe { buf.as_mut_vec().truncate(len + remained_len) };
-                }
-            } else {
-                unsafe { buf.as_mut_vec().truncate(remained_len) };
-            }
-            len
-        };
+/// Generates entries from jawiki pages-articles multistream dump with its
+/// index, by decoding independent bzip2 streams in parallel. Entries are
+/// tagged with the specified Mozc id and returned in the order of pages,
+/// followed by aliases from redirects. Skipped pages are counted in `skipped`.
+pub fn generate_jawiki_ut_multistream(dump: &Path, index: &Path, id: u16, skipped: &mut SkippedLines) -> Result<Vec<DictEntry>> {
+    let patterns = Patterns::new();
+    let pool = thread_pool()?;
+
+    let reader = BufReader::new(MultiBzDecoder::new(File::open(index).with_path(index)?));
+    let streams = read_multistream_index(reader).with_path(index)?;
+
+    // 記事の番号を出典のレコード番号にする
+    // 各ストリームの最初の記事の番号は索引の行数から決まる
+    let mut first_page_no = Vec::with_capacity(streams.len());
+    let mut page_no = 0;
+    for stream in &streams {
+        first_page_no.push(page_no);
+        page_no += stream.pages;
+    }
 
-        //println!("Writing...");
-
-        pool.scope(|scope| {
-            let mut it = buf.split("  </page>").peekable();
-            while let Some(s) = it.next() {
-                if it.peek().is_some() || len == 0 {
-                    let article = s.clone();
-                    let ids_ = ids.as_str().clone();
-                    let out_ = Arc::clone(&out);
-                    let re_remove_chars_ = re_remove_chars.clone();
-                    let re_ref_ = re_ref.clone();
-                    let re_chars_ = re_chars.clone();
-                    scope.spawn(move |_| {
-                        generate_jawiki_ut(article, ids_, out_,
-                            re_remove_chars_, re_ref_, re_chars_);
-                    });
-                } else if len != 0 {
-                    // 途中で切れた記事をキープ
-                    remained.extend_from_slice(s.as_bytes());
-                    if !broken_bytes.is_empty() {
-                        remained.extend_from_slice(&broken_bytes);
-                        broken_bytes.clear();
-                    }
-                    break;
-                }
-            }
-        });
-        if len == 0 {
-            break;
-        }
+    let skipped = Mutex::new(skipped);
+    let results = pool.install(|| {
+        streams.par_iter().zip(first_page_no).map(|(stream, first)| {
+            let pages = read_stream_pages(dump, stream)?;
+            Ok(pages.iter().enumerate().map(|(i, page)| {
+                process_page(page, first + i + 1, id, &skipped, &patterns)
+            }).collect::<Vec<_>>())
+        }).collect::<Result<Vec<Vec<_>>>>()
+    })?;
+
+    let (entries, redirects): (Vec<_>, Vec<_>) = results.into_iter().flatten().unzip();
+    let mut entries: Vec<DictEntry> = entries.into_iter().flatten().collect();
+    let redirects: Vec<Redirect> = redirects.into_iter().flatten().collect();
+
+    // 転送ページのタイトルを別名にする
+    let aliases = pool.install(|| generate_aliases(&entries, &redirects, id, &skipped, &patterns));
+    entries.extend(aliases);
+
+    Ok(entries)
+}
+
+/// Generates entries from articles of jawiki dump.
+#[derive(Debug, Default)]
+pub struct JawikiArticles {
+    utdic: Option<String>,
+    dump: PathBuf,
+    index: PathBuf,
+}
+
+impl Source for JawikiArticles {
+    fn name(&self) -> &'static str {
+        NAME
     }
 
-    // 重複行を削除
-    if let Ok(ref mut mutex) = out.lock() {
-        mutex.par_sort_unstable();
-        mutex.dedup();
+    fn license(&self) -> License {
+        License::CcBySa3
+    }
+
+    fn fetch(&mut self, fetcher: &Fetcher) -> Result<()> {
+        let addr = format!("https://dumps.wikimedia.org/jawiki/latest/{}", LATEST_FILE_NAME);
+        let index_addr = format!("https://dumps.wikimedia.org/jawiki/latest/{}", LATEST_INDEX_NAME);
+
+        // オフラインの場合は日付を確認できないので、変換し直すかはダンプの内容で決める
+        if fetcher.is_offline() {
+            self.utdic = None;
+            self.dump = fetcher.fetch(&addr, LATEST_FILE_NAME, false)?;
+            self.index = fetcher.fetch(&index_addr, LATEST_INDEX_NAME, false)?;
+            return Ok(());
+        }
 
-        let d = File::create(dicname)?;
-        let mut writer = BufWriter::new(d);
-        for line in mutex.iter() {
-            writer.write(line.as_bytes())?;
+        let (utdic, state) = check_jawiki_ut_version(fetcher)?;
+        if state {
+            self.dump = PathBuf::from(LATEST_FILE_NAME);
+            self.index = PathBuf::from(LATEST_INDEX_NAME);
+        } else {
+            self.dump = fetcher.fetch(&addr, LATEST_FILE_NAME, false)?;
+            self.index = fetcher.fetch(&index_addr, LATEST_INDEX_NAME, false)?;
         }
+        self.utdic = Some(utdic);
+
+        Ok(())
+    }
+
+    fn inputs(&self) -> Vec<PathBuf> {
+        vec![self.dump.clone(), self.index.clone()]
+    }
+
+    fn version(&self) -> u32 {
+        9
+    }
+
+    fn parse(&self, stats: &mut SourceStats) -> Result<Vec<DictEntry>> {
+        // Mozcの品詞IDを取得
+        //「名詞,固有名詞,人名,一般,*,*」は優先度が低いので使わない。
+        //「名詞,固有名詞,一般,*,*,*」は後でフィルタリングする。
+        let id = pos_table()?.id("名詞,固有名詞,一般")?;
+
+        let mut skipped = stats.skipped_lines(NAME, LATEST_FILE_NAME);
+        let entries = generate_jawiki_ut_multistream(&self.dump, &self.index, id, &mut skipped)?;
+        stats.add_skipped(&skipped);
+        Ok(entries)
     }
 
-    // create flag, zero contents
-    File::create(utdic)?;
+    fn convert(&self, mut entries: Vec<DictEntry>) -> Result<Vec<DictEntry>> {
+        // 重複行を削除
+        // 同じ表記の記事が複数ある場合は、ペナルティの低い読みを残す
+        sort_dedup_by_reading_surface(&mut entries);
+
+        write_entries(DIC_NAME, &entries)?;
 
-    return Ok(());
+        // create flag, zero contents
+        if let Some(utdic) = &self.utdic {
+            File::create(utdic)?;
+        }
+
+        Ok(entries)
+    }
 }
 
-pub fn run_generate_jawiki_ut() -> std::io::Result<()> {
-    let dicname = "mozcdic-ut-jawiki.txt";
-    let (utdic, state) = check_jawiki_ut_version()?;
-    if state {
-        return Ok(());
-    } else {
-        let addr = format!("https://dumps.wikimedia.org/jawiki/latest/{}", LATEST_FILE_NAME);
-        command_wait("wget", vec!["-N", "-q", &addr])?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns readings of the title found in the article.
+    fn readings(title: &str, text: &str) -> Vec<String> {
+        let page = Page { ns: NS_ARTICLE, title: String::from(title), redirect: None, text: String::from(text) };
+        let mut skipped = SkippedLines::new(NAME, LATEST_FILE_NAME);
+        let entries = generate_jawiki_ut(&page, 1, 1, &Mutex::new(&mut skipped), &Patterns::new());
+        entries.into_iter().map(|entry| entry.reading).collect()
+    }
+
+    #[test]
+    fn reading_in_parentheses() {
+        assert_eq!(readings("皆藤愛子", "'''皆藤 愛子'''<ref>一部のプロフィールが</ref>(かいとう あいこ、[[1984年]]"), ["かいとうあいこ"]);
+        assert_eq!(readings("大倉忠義", "'''大倉 忠義'''（おおくら ただよし<ref name=\"oricon\"></ref>、[[1985年]]"), ["おおくらただよし"]);
+        assert!(readings("一覧表", "一覧表(ミュージシャン)").is_empty());
+    }
 
-        run_thread_generate_jawiki_ut(&utdic, dicname)?;
+    #[test]
+    fn reading_in_parentheses_with_labels() {
+        assert_eq!(readings("日本", "'''日本'''(にっぽん、旧読み:にほん)"), ["にっぽん", "にほん"]);
+        assert_eq!(readings("日本", "'''日本'''(よみ:にっぽん)"), ["にっぽん"]);
+        // 読みでない見出しの部分や見出しのない2番目以降の部分は読みにしない
+        assert_eq!(readings("日本", "'''日本'''(にっぽん、英: Japan、旧称: やまと、ひのもと)"), ["にっぽん"]);
+        assert!(readings("日本", "'''日本'''(英: Japan、にっぽん)").is_empty());
     }
 
-    Ok(())
+    #[test]
+    fn reading_in_furigana_template() {
+        assert_eq!(readings("皆藤愛子", "'''{{読み仮名|皆藤 愛子|かいとう あいこ}}'''は、日本のアナウンサー。"), ["かいとうあいこ"]);
+        assert_eq!(readings("大倉忠義", "{{読み仮名_ruby不使用|'''[[大倉忠義|大倉 忠義]]'''|おおくら ただよし}}"), ["おおくらただよし"]);
+        assert_eq!(readings("東京", "'''{{読み仮名|東京|トウキョウ|lang=ja}}'''"), ["とうきょう"]);
+        assert!(readings("東京", "'''{{読み仮名|京都|きょうと}}'''").is_empty());
+    }
+
+    #[test]
+    fn reading_in_lang_template() {
+        assert_eq!(readings("秋葉原", "'''{{Lang|ja|秋葉原}}'''（あきはばら）は"), ["あきはばら"]);
+        // 読み仮名の中のLang
+        assert_eq!(readings("秋葉原", "'''{{読み仮名|{{lang|ja|秋葉原}}|あきはばら}}'''は"), ["あきはばら"]);
+    }
+
+    #[test]
+    fn reading_in_ruby() {
+        assert_eq!(readings("神保町", "'''<ruby>神保<rp>(</rp><rt>じんぼう</rt><rp>)</rp></ruby><ruby>町<rt>ちょう</rt></ruby>'''は"), ["じんぼうちょう"]);
+        assert_eq!(readings("御茶ノ水", "<ruby><rb>御茶ノ水</rb><rp>（</rp><rt>おちゃのみず</rt><rp>）</rp></ruby>"), ["おちゃのみず"]);
+        // 離れた<ruby>はつなげない
+        assert!(readings("神保町", "<ruby>神保<rt>じんぼう</rt></ruby>の<ruby>町<rt>ちょう</rt></ruby>").is_empty());
+    }
+
+    #[test]
+    fn spelling_variants() {
+        let patterns = Patterns::new();
+        assert!(is_spelling_variant("國學院大學", "国学院大学", &patterns));
+        assert!(is_spelling_variant("髙橋一生", "高橋一生", &patterns));
+        assert!(is_spelling_variant("渡邊", "渡邉", &patterns));
+        assert!(is_spelling_variant("ジャンポール", "ジャン・ポール", &patterns));
+        // 旧字体ではない漢字の違いは別の語
+        assert!(!is_spelling_variant("東京府", "東京都", &patterns));
+        assert!(!is_spelling_variant("大阪府", "大阪市", &patterns));
+    }
+
+    #[test]
+    fn abbreviations() {
+        assert!(is_abbreviation("きむたく", "きむらたくや"));
+        assert!(is_abbreviation("ぱそこん", "ぱーそなるこんぴゅーた"));
+        assert!(is_abbreviation("東大", "東京大学"));
+        // 先頭から続く部分や途中から始まる部分は略称としない
+        assert!(!is_abbreviation("あいす", "あいすくりーむ"));
+        assert!(!is_abbreviation("ねこ", "いえねこ"));
+        assert!(!is_abbreviation("たくや", "きむらたくや"));
+    }
+
+    #[test]
+    fn aliases_of_redirects() {
+        let entries = [
+            DictEntry::new(String::from("きむらたくや"), 1, READING_COST, String::from("木村拓哉"), NAME),
+            DictEntry::new(String::from("たかはしいっせい"), 1, READING_COST, String::from("高橋一生"), NAME),
+            DictEntry::new(String::from("とうきょうと"), 1, READING_COST, String::from("東京都"), NAME),
+            DictEntry::new(String::from("いえねこ"), 1, READING_COST, String::from("イエネコ"), NAME),
+            DictEntry::new(String::from("あいすくりーむ"), 1, READING_COST, String::from("アイスクリーム"), NAME),
+            DictEntry::new(String::from("とうきょうだいがく"), 1, READING_COST, String::from("東京大学"), NAME),
+        ];
+        let redirect = |title: &str, target: &str| Redirect { page_no: 1, title: String::from(title), target: String::from(target) };
+        let redirects = [
+            redirect("髙橋一生", "高橋一生"),
+            redirect("キムタク", "木村拓哉"),
+            redirect("トウキョウト", "東京都"),
+            redirect("東京府", "東京都"),
+            redirect("ねこ", "イエネコ"),
+            redirect("アイス", "アイスクリーム"),
+            redirect("東大", "東京大学"),
+        ];
+        let mut skipped = SkippedLines::new(NAME, LATEST_FILE_NAME);
+        let aliases = generate_aliases(&entries, &redirects, 1, &Mutex::new(&mut skipped), &Patterns::new());
+        let aliases: Vec<(&str, &str, i32)> = aliases.iter()
+            .map(|entry| (entry.reading.as_str(), entry.surface.as_str(), entry.penalty)).collect();
+        assert_eq!(aliases, [
+            ("たかはしいっせい", "髙橋一生", ALIAS_PENALTY),
+            ("きむたく", "木村拓哉", ALIAS_PENALTY),
+        ]);
+
+        let rules = skipped.rules();
+        assert_eq!(rules.get("redirect title is a reading of the target"), Some(&1));
+        assert_eq!(rules.get("redirect title in kana is not an abbreviation of the target"), Some(&2));
+        assert_eq!(rules.get("redirect title is an abbreviation in kanji"), Some(&1));
+        assert_eq!(rules.get("redirect title is not a spelling variant"), Some(&1));
+    }
 }
@@ -1,16 +1,22 @@
 
 // Ported from add_search_results_to_each_title.rb file.
 
-use std::io::{BufWriter, Write};
+use std::io::{BufWriter, Read, Write};
 use std::fs::File;
 
 use rayon::prelude::*;
 
-use super::util::*;
+use super::archive::*;
+use super::error::Result;
+use super::fetch::Fetcher;
 
 
-fn add_search_results_to_each_title(filename: &str, dicname: &str) -> std::io::Result<()> {
-    let titles = command_wait_output("gzip", vec!["-c", "-d", filename])?;
+/// Counts titles which start with each title, reading the title list of jawiki
+/// from the reader. Counts are written into the writer as
+/// `jawikititles\t0\t0\tCOUNT\tTITLE` lines.
+pub fn add_search_results_to_each_title<R: Read, W: Write>(mut reader: R, mut writer: W) -> Result<()> {
+    let mut titles = String::new();
+    reader.read_to_string(&mut titles)?;
 
     let mut l2 = Vec::new();
     for line in titles.split("\n") {
@@ -34,9 +40,6 @@ fn add_search_results_to_each_title(filename: &str, dicname: &str) -> std::io::R
     let mut titles = l2;
     titles.par_sort_unstable();
 
-    let f = File::create(dicname)?;
-    let mut writer = BufWriter::new(f);
-
     let len = titles.len();
     for i in 0..len {
         let current = &titles[i];
@@ -54,21 +57,21 @@ fn add_search_results_to_each_title(filename: &str, dicname: &str) -> std::io::R
         }
 
         let v = format!("jawikititles\t0\t0\t{}\t{}\n", count, current);
-        writer.write(v.as_bytes())?;
+        writer.write_all(v.as_bytes())?;
     }
 
-    Ok(())
+    Ok(writer.flush()?)
 }
 
-pub fn run_add_search_results_to_each_title() -> std::io::Result<()> {
+/// Gets title list of jawiki and writes hit counts of titles into
+/// jawiki-latest-all-titles-in-ns0.hits file.
+pub fn run_add_search_results_to_each_title(fetcher: &Fetcher) -> Result<()> {
     const FILE_NAME: &str = "jawiki-latest-all-titles-in-ns0.gz";
     const DIC_NAME: &str = "jawiki-latest-all-titles-in-ns0.hits";
 
-    command_wait("wget", vec!["-N", "-q", "https://dumps.wikimedia.your.org//jawiki/latest/jawiki-latest-all-titles-in-ns0.gz"])?;
-
-    add_search_results_to_each_title(FILE_NAME, DIC_NAME)?;
+    let path = fetcher.fetch("https://dumps.wikimedia.your.org//jawiki/latest/jawiki-latest-all-titles-in-ns0.gz", FILE_NAME, false)?;
 
-    //command_wait("rm", vec!["-f", "jawiki-latest-all-titles-in-ns0"])?;
+    add_search_results_to_each_title(open_gz(&path)?, BufWriter::new(File::create(DIC_NAME)?))?;
 
     Ok(())
 }
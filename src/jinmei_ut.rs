@@ -1,51 +1,95 @@
-
 // Ported from convert_jinmei_ut_to_mozcdic.rb file.
 
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{BufRead, BufReader};
 use std::fs::File;
+use std::path::PathBuf;
 
-use rayon::prelude::*;
 
-use super::mozc::get_id;
+use super::entry::{sort_dedup, DictEntry};
+use super::error::{Result, SkippedLines, WithPath};
+use super::license::License;
+use super::mozc::MOZCDIC_NAME;
+use super::person_name::{split_full_names, NameIds, NameParts};
+use super::pos::pos_table;
+use super::fetch::Fetcher;
+use super::source::Source;
+use super::stats::SourceStats;
 
 
-fn convert_jinmei_ut_to_mozcdic(filename: &str, dicname: &str) -> std::io::Result<()> {
-    // Mozcの品詞IDを取得
-    // 「名詞,固有名詞,人名,一般,*,*」は優先度が低いので使わない。
-    // 「名詞,固有名詞,一般,*,*,*」は後でフィルタリングする。
-    let id = get_id(r"(\d*) 名詞,一般,\*,\*,\*,\*,\*")?;
+const NAME: &str = "jinmei";
+const FILE_NAME: &str = "../data/jinmei-ut/jinmei-ut.txt";
 
+/// Parses jinmei-ut dictionary read from the reader.
+/// Entries are tagged with the specified Mozc id, malformed lines are counted in `skipped`.
+/// Full names can be split by `person_name::split_full_names` afterwards.
+pub fn parse_jinmei_ut<R: BufRead>(mut reader: R, id: u16, skipped: &mut SkippedLines) -> Result<Vec<DictEntry>> {
     let mut lines = Vec::new();
-    let f = File::open(filename)?;
-    let mut reader = BufReader::new(f);
     let mut line = String::new();
-    while let Ok(1..) = reader.read_line(&mut line) {
-        let yomi = line.get(0..line.find("\t").unwrap()).unwrap();
-        let hyouki = line.get(line.rfind("\t").unwrap() + 1..).unwrap().trim_end();
+    let mut line_no = 0;
+    while reader.read_line(&mut line)? > 0 {
+        line_no += 1;
+        if let (Some(first), Some(last)) = (line.find('\t'), line.rfind('\t')) {
+            let yomi = &line[..first];
+            let hyouki = line[last + 1..].trim_end();
 
-        lines.push(format!("{}\t{}\t{}\t6000\t{}\n", yomi, id, id, hyouki));
+            lines.push(DictEntry::new(String::from(yomi), id, 6000, String::from(hyouki), NAME).with_record(line_no));
+        } else {
+            skipped.skip(line_no, &line, "no tab separator");
+        }
 
         line.clear();
     }
 
-    // 重複行を削除
-    lines.par_sort_unstable();
-    lines.dedup();
+    Ok(lines)
+}
+
+/// Converts jinmei-ut dictionary bundled in data directory.
+#[derive(Debug, Default)]
+pub struct JinmeiUt;
 
-    let f = File::create(dicname)?;
-    let mut writer = BufWriter::new(f);
-    for line in lines {
-        writer.write(line.as_bytes())?;
+impl Source for JinmeiUt {
+    fn name(&self) -> &'static str {
+        NAME
     }
 
-    Ok(())
-}
+    fn license(&self) -> License {
+        License::Apache2
+    }
 
-pub fn run_convert_jinmei_ut_to_mozcdic() -> std::io::Result<()> {
-    const FILE_NAME: &str = "../data/jinmei-ut/jinmei-ut.txt";
-    const DIC_NAME: &str = "mozcdic-ut-jinmei.txt";
+    fn fetch(&mut self, _fetcher: &Fetcher) -> Result<()> {
+        Ok(())
+    }
 
-    convert_jinmei_ut_to_mozcdic(FILE_NAME, DIC_NAME)?;
+    fn inputs(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from(FILE_NAME), PathBuf::from(MOZCDIC_NAME)]
+    }
 
-    Ok(())
+    fn version(&self) -> u32 {
+        4
+    }
+
+    fn parse(&self, stats: &mut SourceStats) -> Result<Vec<DictEntry>> {
+        // Mozcの品詞IDを取得
+        // 「名詞,固有名詞,人名,一般,*,*」は優先度が低いので使わない。
+        // 「名詞,固有名詞,一般,*,*,*」は後でフィルタリングする。
+        // 芸名やグループ名も含むので、Mozcの姓か名で分けられる場合だけ姓と名を加える
+        let table = pos_table()?;
+        let id = table.id("名詞,一般")?;
+        let ids = NameIds::new(&table)?;
+
+        let mut skipped = stats.skipped_lines(NAME, FILE_NAME);
+        let entries = parse_jinmei_ut(BufReader::new(File::open(FILE_NAME).with_path(FILE_NAME)?), id, &mut skipped)
+            .with_path(FILE_NAME)?;
+        skipped.report();
+        stats.add_skipped(&skipped);
+
+        Ok(split_full_names(entries, id, &NameParts::read_mozc(&ids)?, &ids))
+    }
+
+    fn convert(&self, mut entries: Vec<DictEntry>) -> Result<Vec<DictEntry>> {
+        // 重複行を削除
+        sort_dedup(&mut entries);
+
+        Ok(entries)
+    }
 }
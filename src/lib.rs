@@ -0,0 +1,39 @@
+//! Converters which make dictionary entries for Mozc from various sources,
+//! ported from mozcdic_ut.
+//!
+//! Each converter implements `source::Source` and returns `entry::DictEntry`
+//! values, which are filtered by `filter` module and get their costs by
+//! `costs` module before written into the dictionary file.
+
+pub mod alt_cannadic;
+pub mod archive;
+pub mod cache;
+pub mod chimei;
+pub mod config;
+pub mod conjugation;
+pub mod costs;
+pub mod diff;
+pub mod document;
+pub mod edict2;
+pub mod entry;
+pub mod error;
+pub mod evaluate;
+pub mod extsort;
+pub mod fetch;
+pub mod filter;
+pub mod jawiki_article;
+pub mod jawiki_titles;
+pub mod jinmei_ut;
+pub mod license;
+pub mod lookup;
+pub mod mediawiki;
+pub mod mozc;
+pub mod neologd;
+pub mod person_name;
+pub mod pos;
+pub mod skkdic;
+pub mod source;
+pub mod stats;
+pub mod sudachidict;
+pub mod util;
+pub mod validate;
@@ -0,0 +1,69 @@
+// Licenses of dictionary entries.
+
+use std::fmt;
+
+use serde::Deserialize;
+
+
+/// License of dictionary entries.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum License {
+    #[serde(rename = "Apache-2.0")]
+    Apache2,
+    #[serde(rename = "BSD-3-Clause")]
+    Bsd3,
+    #[serde(rename = "CC-BY-SA-3.0")]
+    CcBySa3,
+    #[serde(rename = "GPL-2.0")]
+    Gpl2,
+    #[serde(rename = "public-domain")]
+    PublicDomain,
+}
+
+impl License {
+    /// All licenses in the order of their names.
+    pub const ALL: [License; 5] = [License::Apache2, License::Bsd3, License::CcBySa3, License::Gpl2, License::PublicDomain];
+
+    /// Returns SPDX like name of the license.
+    pub fn name(self) -> &'static str {
+        match self {
+            License::Apache2 => "Apache-2.0",
+            License::Bsd3 => "BSD-3-Clause",
+            License::CcBySa3 => "CC-BY-SA-3.0",
+            License::Gpl2 => "GPL-2.0",
+            License::PublicDomain => "public-domain",
+        }
+    }
+
+    /// Returns license which has the name.
+    pub fn from_name(name: &str) -> Option<License> {
+        License::ALL.iter().copied().find(|license| license.name() == name)
+    }
+
+    /// Returns licenses which can be redistributed under Apache License 2.0.
+    pub fn apache2_compatible() -> Vec<License> {
+        vec![License::Apache2, License::Bsd3, License::PublicDomain]
+    }
+}
+
+impl fmt::Display for License {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Parses comma separated license names, `apache-2.0-compatible` is
+/// expanded to licenses compatible with Apache License 2.0.
+pub fn parse_licenses(s: &str) -> Option<Vec<License>> {
+    let mut licenses = Vec::new();
+    for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        if name == "apache-2.0-compatible" {
+            licenses.extend(License::apache2_compatible());
+        } else {
+            licenses.push(License::from_name(name)?);
+        }
+    }
+    licenses.sort_unstable();
+    licenses.dedup();
+    Some(licenses)
+}
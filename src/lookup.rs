@@ -0,0 +1,83 @@
+// Index of a built dictionary to answer searches by reading and surface.
+
+use super::entry::DictEntry;
+
+
+/// Kind of search.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchKind {
+    /// Entries whose reading is the query.
+    Reading,
+    /// Entries whose reading starts with the query.
+    Prefix,
+    /// Entries whose surface is the query.
+    Surface,
+}
+
+/// Entries sorted by reading, with the order of entries by surface.
+#[derive(Clone, Debug, Default)]
+pub struct DictIndex {
+    entries: Vec<DictEntry>,
+    surfaces: Vec<usize>,
+}
+
+impl DictIndex {
+    /// Creates index of the entries.
+    pub fn new(mut entries: Vec<DictEntry>) -> DictIndex {
+        entries.sort_unstable_by(|a, b| (&a.reading, &a.surface, a.cost).cmp(&(&b.reading, &b.surface, b.cost)));
+        let mut surfaces = (0..entries.len()).collect::<Vec<usize>>();
+        surfaces.sort_by(|a, b| entries[*a].surface.cmp(&entries[*b].surface));
+        DictIndex { entries, surfaces }
+    }
+
+    /// Returns number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns entries sorted by reading, surface and cost.
+    pub fn entries(&self) -> &[DictEntry] {
+        &self.entries
+    }
+
+    /// Returns true if the index has no entry.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns entries which have the reading, in the order of surface and cost.
+    pub fn reading(&self, reading: &str) -> &[DictEntry] {
+        let start = self.entries.partition_point(|entry| entry.reading.as_str() < reading);
+        let end = start + self.entries[start..].partition_point(|entry| entry.reading == reading);
+        &self.entries[start..end]
+    }
+
+    /// Returns entries whose reading starts with the prefix.
+    pub fn prefix(&self, prefix: &str) -> &[DictEntry] {
+        let start = self.entries.partition_point(|entry| entry.reading.as_str() < prefix);
+        let end = start + self.entries[start..].partition_point(|entry| entry.reading.starts_with(prefix));
+        &self.entries[start..end]
+    }
+
+    /// Returns entries which have the surface.
+    pub fn surface<'a>(&'a self, surface: &str) -> impl Iterator<Item = &'a DictEntry> + 'a {
+        let start = self.surfaces.partition_point(|index| self.entries[*index].surface.as_str() < surface);
+        let end = start + self.surfaces[start..].partition_point(|index| self.entries[*index].surface == surface);
+        self.surfaces[start..end].iter().map(move |index| &self.entries[*index])
+    }
+
+    /// Returns entries found by the search.
+    pub fn search<'a>(&'a self, kind: SearchKind, query: &str) -> Vec<&'a DictEntry> {
+        match kind {
+            SearchKind::Reading => self.reading(query).iter().collect(),
+            SearchKind::Prefix => self.prefix(query).iter().collect(),
+            SearchKind::Surface => self.surface(query).collect(),
+        }
+    }
+
+    /// Returns entries which have the same reading and surface as the entry,
+    /// such as Mozc OSS entries shadowed by a UT entry.
+    pub fn same_word<'a>(&'a self, entry: &'a DictEntry) -> impl Iterator<Item = &'a DictEntry> + 'a {
+        self.reading(&entry.reading).iter().filter(move |other| other.surface == entry.surface)
+    }
+}
@@ -1,42 +1,45 @@
 
 
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::Write;
-
-mod alt_cannadic;
-mod chimei;
-mod costs;
-mod document;
-mod edict2;
-mod filter;
-mod jawiki_article;
-mod jawiki_titles;
-mod jinmei_ut;
-mod mozc;
-mod neologd;
-mod skkdic;
-mod sudachidict;
-mod util;
-
-use alt_cannadic::*;
-use chimei::*;
-use costs::*;
-use document::*;
-use edict2::*;
-use filter::*;
-use jawiki_article::*;
-use jawiki_titles::*;
-use jinmei_ut::*;
-use mozc::*;
-use neologd::*;
-use skkdic::*;
-use sudachidict::*;
-use util::*;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use mozcdic_ut_rs::archive::*;
+use mozcdic_ut_rs::cache::BuildCache;
+use mozcdic_ut_rs::config::{Config, SourceConfig};
+use mozcdic_ut_rs::costs::*;
+use mozcdic_ut_rs::diff::*;
+use mozcdic_ut_rs::document::*;
+use mozcdic_ut_rs::entry::*;
+use mozcdic_ut_rs::error::{Error, Result, WithPath};
+use mozcdic_ut_rs::evaluate::*;
+use mozcdic_ut_rs::extsort::*;
+use mozcdic_ut_rs::fetch::Fetcher;
+use mozcdic_ut_rs::filter::*;
+use mozcdic_ut_rs::jawiki_titles::*;
+use mozcdic_ut_rs::license::*;
+use mozcdic_ut_rs::lookup::*;
+use mozcdic_ut_rs::mozc::*;
+use mozcdic_ut_rs::pos::*;
+use mozcdic_ut_rs::source::*;
+use mozcdic_ut_rs::stats::*;
+use mozcdic_ut_rs::util::*;
+use mozcdic_ut_rs::validate::*;
 
 
 /// Shows help message.
 fn help() {
-    print!("usage: cargo run --release COMMAND [FILENAME]
+    print!("usage: cargo run --release [OPTIONS] COMMAND [FILENAME]
+
+OPTIONS:
+  --offline DIR    uses files downloaded into DIR instead of downloading,
+                   fails if any file required by the commands is missing
+  --path NAME=PATH uses PATH for the file NAME, can be specified several times
+  --config FILE    reads workflow configuration from FILE instead of
+                   data/workflow.toml bundled in the binary
+  --licenses LIST  builds workflow only from sources of the comma separated
+                   licenses, apache-2.0-compatible is accepted as a license
 
 COMMAND:
   altcannadic      converts alt-canna dictionary
@@ -58,56 +61,110 @@ COMMAND:
   unsuitable       removes unsuitable entries from dictionary
                    needs FILENAME
 
+  validate         checks lines of dictionary, such as fields, readings,
+                   ids, costs, surfaces and duplicates, needs FILENAME
+
   document         update document
 
+  workflow [SOURCE...]
+                   builds mozcdic-ut.txt and its archive from SOURCEs,
+                   or from sources enabled in the configuration
+  explain [--reading READING] [--surface SURFACE] [SOURCE...]
+                   builds like workflow without writing files, and prints
+                   why entries of READING and SURFACE are skipped, removed
+                   or get their costs
+
+  diff [--prefix N] [--threshold COST] [--list] [--old-provenance FILE]
+       [--new-provenance FILE] OLD NEW
+                   compares dictionaries or tar.bz2 archives OLD and NEW,
+                   and reports added and removed entries and cost changes
+                   grouped by the first N characters of readings (1),
+                   ids and sources read from provenance files, cost changes
+                   of COST (1000) or more are listed, --list lists all changes
+
+  lookup [--prefix | --surface] [--mozc] [--dictionary FILE] [QUERY...]
+                   searches entries of the built dictionary by reading,
+                   by prefix of reading, or by surface, and shows their
+                   part of speech, --mozc shows Mozc OSS entries of the same
+                   reading and surface, queries are read from stdin if none
+
+  evaluate [--top N] [--failures] TESTSET [DICTIONARY...]
+                   converts readings in TESTSET, lines of reading and
+                   expected surface separated by a tab, with Mozc OSS
+                   dictionary and each DICTIONARY, or the built dictionary,
+                   and reports top-1 and top-N (5) accuracy
+
   clean            removes build directory
   help             this message
 ");
 }
 
+/// Runs the source with the cache if it is configured.
+fn run_source_cached(source: &mut dyn Source, fetcher: &Fetcher, config: &Config, settings: &str, stats: &mut SourceStats)
+    -> Result<Vec<DictEntry>> {
+    match &config.build.cache {
+        Some(dir) => source.run_cached(fetcher, &BuildCache::new(dir), settings, stats),
+        None => source.run(fetcher, stats),
+    }
+}
+
+/// Runs source and writes its entries into mozcdic-ut-NAME.txt file.
+fn run_source(mut source: Box<dyn Source>, fetcher: &Fetcher, config: &Config) -> Result<()> {
+    let settings = config.sources.iter().find(|source_config| source_config.matches(source.name()))
+        .map(SourceConfig::cache_settings).unwrap_or_default();
+    let entries = run_source_cached(source.as_mut(), fetcher, config, &settings, &mut SourceStats::default())?;
+    write_entries(&format!("mozcdic-ut-{}.txt", source.name()), &entries)
+}
+
+/// Checks lines of the dictionary, fails if any violation is found.
+fn validate_dictionary(filename: &str) -> Result<()> {
+    let violations = validate(filename, pos_table()?.iter().map(|pos| pos.id).collect())?;
+    print!("{}", violations);
+    if violations.count() > 0 {
+        return Err(Error::other(format!("{}: {} violations are found", filename, violations.count())));
+    }
+    Ok(())
+}
+
 /// Execute command.
-fn command_runner(args: &Vec<&str>) {
+fn command_runner(args: &[&str], fetcher: &Fetcher, config: &Config) -> Result<()> {
     let mut i = 0;
     let count = args.len();
     while i < count {
         let argument = args[i];
-        let r = match argument {
-            "altcannadic" => run_convert_alt_cannadic_to_mozcdic(),
-            "chimei" => run_fix_ken_all()
-                .and_then(|_| generate_chimei_for_mozcdic()),
-            "clean" => command_wait("rm", vec!["-rf", "build"]),
+        match argument {
+            "clean" => remove_dir_if_exists("build").with_path("build")?,
             "costs" => {
                 // costs filename
                 if i + 1 < count {
                     let filename = &args[i + 1];
                     i += 1;
                     let dicname = format!("{}.costs", filename);
-                    calculate_costs(filename, &dicname)
+                    let entries = calculate_costs(read_entries(filename, "ut")?, read_jawiki_hits(JAWIKI_HITS_NAME)?, &config.costs, &mut BuildStats::default())?;
+                    write_entries(&dicname, &entries)?;
                 } else {
-                    println!("costs option requires file path");
-                    std::process::exit(-1);
+                    return Err(Error::other("costs option requires file path"));
                 }
             },
-            "document" => update_documents(),
-            "edict2" => run_convert_edict2_to_mozcdic(),
-            "jawikiarticles" => run_generate_jawiki_ut(),
-            "jawikititles" => run_add_search_results_to_each_title(),
-            "jinmeiut" => run_convert_jinmei_ut_to_mozcdic(),
-            "help" => continue,
-            "mozc" => get_the_latest_mozc(),
-            "neologd" => run_convert_neologd_to_mozcdic(),
-            "skkdic" => run_convert_skkdic_to_mozcdic(),
-            "sudachidict" => run_convert_sudachidict_to_mozcdic(),
+            "document" => update_documents()?,
+            "jawikititles" => run_add_search_results_to_each_title(fetcher)?,
+            "help" => {},
+            "mozc" => get_the_latest_mozc(fetcher)?,
             "unnecessary" => {
                 // unnecessary filename
                 if i + 1 < count {
                     let filename = &args[i + 1];
                     i += 1;
                     let dicname = format!("{}.need", filename);
-                    remove_unnecessary_entries(filename, &dicname)
+                    let mut entries = Vec::new();
+                    remove_unnecessary_entries_stream(open_entries(filename, "ut")?, open_mozc_entries()?, &config.sort,
+                        &mut BuildStats::default(), |entry| {
+                            entries.push(entry);
+                            Ok(())
+                        })?;
+                    write_entries(&dicname, &entries)?;
                 } else {
-                    println!("unnecessary option requires file path");
-                    std::process::exit(-1);
+                    return Err(Error::other("unnecessary option requires file path"));
                 }
             },
             "unsuitable" => {
@@ -115,242 +172,438 @@ fn command_runner(args: &Vec<&str>) {
                 if i + 1 < count {
                     let filename = &args[i + 1];
                     i += 1;
-                    filter_unsuitable_entries(filename, filename)
+                    let mut entries = read_entries(filename, "ut")?;
+                    filter_unsuitable_entries_in_build(&mut entries, &config.build.unsuitable_filter, &mut BuildStats::default())?;
+                    write_entries(filename, &entries)?;
+                } else {
+                    return Err(Error::other("unsuitable option requires file path"));
+                }
+            },
+            "validate" => {
+                // validate filename
+                if i + 1 < count {
+                    let filename = &args[i + 1];
+                    i += 1;
+                    validate_dictionary(filename)?;
                 } else {
-                    println!("unsuitable option requires file path");
-                    std::process::exit(-1);
+                    return Err(Error::other("validate option requires file path"));
                 }
             },
             _ => {
-                println!("unknown option: {}", &argument);
-                std::process::exit(-1);
+                if let Some(source) = source_from_command(argument) {
+                    run_source(source, fetcher, config)?;
+                } else {
+                    return Err(Error::other(format!("unknown option: {}", argument)));
+                }
             },
-        };
-        if let Err(e) = r {
-            println!("{}", e);
-            std::process::exit(-1);
         }
         i += 1;
     }
+    Ok(())
 }
 
-#[derive(Debug, Default)]
-struct Licenses {
-    apl2: bool,
-    ccbysa3: bool,
-    gpl2: bool,
-}
+/// Runs sources, filters and cost calculation in the build directory, and
+/// returns entries of the dictionary and sources which are used.
+/// Entries which exceed the memory budget are kept in files of the sort directory.
+fn build_entries<'a>(sources: Vec<&'a SourceConfig>, fetcher: &Fetcher, config: &Config, stats: &mut BuildStats)
+    -> Result<(SortedEntries, Vec<&'a SourceConfig>)> {
+    command_runner(&["mozc"], fetcher, config)?;
+    command_runner(&["jawikititles"], fetcher, config)?;
 
-/// Run to make dictionary.
-fn workflow(args: &Vec<&str>) {
-    let mut licenses = Licenses::default();
-    let mut readme = Vec::new();
-
-    command_wait("mkdir", vec!["build"]).unwrap();
-    std::env::set_current_dir("build").unwrap();
-
-    command_wait("rm", vec!["-f", "mozcdic-*"]).unwrap();
-    command_wait("rm", vec!["-f", "jawiki-ut-*"]).unwrap();
-
-    command_runner(&vec!["mozc"]);
-    command_runner(&vec!["jawikititles"]);
-
-    let status = {
-        let mut status: u8 = 0;
-        let mut i = 1; // skip workflow
-        let count = args.len();
-        while i < count {
-            let argument = args[i];
-            match argument {
-                "altcannadic" => {
-                    status |= 0x1;
-                    command_runner(&vec!["altcannadic"]);
-                    licenses.gpl2 = true;
-                    readme.push(String::from(
-                        "* Entries came from alt-cannadic are licensed under General Public License 2.0.
-  https://ja.osdn.net/projects/alt-cannadic/"));
-                }
-                "chimei" => {
-                    status |= 0x2;
-                    command_runner(&vec!["chimei"]);
-                    readme.push(String::from(
-                        "* Location data is came from zipcode data made by Japan post, licensed under public domain.
-  https://www.post.japanpost.jp/zipcode/dl/readme.html"));
-                }
-                "edict2" => {
-                    status |= 0x4;
-                    command_runner(&vec!["edict2"]);
-                    licenses.ccbysa3 = true;
-                    readme.push(String::from(
-                        "* Entries from edict2 are licensed under CC-BY-SA 3.0.
-  http://ftp.edrdg.org/pub/Nihongo/"));
-                }
-                "jawikiarticles" => {
-                    status |= 0x8;
-                    command_runner(&vec!["jawikiarticles"]);
-                    command_runner(&vec!["unsuitable", "mozcdic-ut-jawiki.txt"]);
-                    licenses.ccbysa3 = true;
-                    readme.push(String::from(
-                        "* Entries from ja.wikipedia are licensed under CC-BY-SA 3.0.
-  https://ja.wikipedia.org/wiki/Wikipedia:%E3%83%87%E3%83%BC%E3%82%BF%E3%83%99%E3%83%BC%E3%82%B9%E3%83%80%E3%82%A6%E3%83%B3%E3%83%AD%E3%83%BC%E3%83%89"));
-                }
-                "jinmeiut" => {
-                    status |= 0x10;
-                    command_runner(&vec!["jinmeiut"]);
-                    licenses.apl2 = true;
-                    readme.push(String::from(
-                        "* Entries from jinmeiut are licensed under Apache License 2.0.
-  http://linuxplayers.g1.xrea.com/mozc-ut.html"));
-                }
-                "neologd" => {
-                    status |= 0x20;
-                    command_runner(&vec!["neologd"]);
-                    command_runner(&vec!["unsuitable", "mozcdic-ut-neologd.txt"]);
-                    licenses.apl2 = true;
-                    readme.push(String::from(
-                        "* Entries from neologd are licensed under Apache License 2.0.
-  https://github.com/neologd/mecab-ipadic-neologd"));
-                }
-                "skkdic" => {
-                    status |= 0x40;
-                    command_runner(&vec!["skkdic"]);
-                    licenses.gpl2 = true;
-                    readme.push(String::from(
-                        "* Entries from skkdic are licensed under General Public License 2.0.
-  http://openlab.jp/skk"));
-                }
-                "sudachidic" => {
-                    status |= 0x80;
-                    command_runner(&vec!["sudachidic"]);
-                    command_runner(&vec!["unsuitable", "mozcdic-ut-sudachidict-core.txt"]);
-                    command_runner(&vec!["unsuitable", "mozcdic-ut-sudachidict-notcore.txt"]);
-                    licenses.apl2 = true;
-                    readme.push(String::from(
-                        "* Entries from SudachiDict are licensed under Apache License 2.0.
-  https://github.com/WorksApplications/SudachiDict"));
-                }
-                _ => {
-                    println!("unknown option: {}", argument);
-                    std::process::exit(-1);
-                }
-            }
-            i += 1;
+    // 出典の出力、フィルタ、フィルタの出力と全体の出力の4つが同時に使うので予算を分ける
+    let sort = config.sort.split(4);
+    let mut entries = ExternalSorter::spool(&sort);
+    let mut used = Vec::new();
+    for source_config in sources {
+        let license = source_config.license();
+        if !config.build.allows(Some(license)) {
+            println!("skip {}: {} is not allowed", source_config.name, license);
+            continue;
         }
-        status
-    };
-    println!("dictionary status: {}", status);
+        let mut source = source_from_command(&source_config.name).unwrap();
+        let name = source.name();
+        let mut e = run_source_cached(source.as_mut(), &source_config.fetcher(fetcher), config,
+            &source_config.cache_settings(), stats.source(name))?;
+        for entry in &mut e {
+            entry.license = Some(license);
+        }
+        let mut filtered = ExternalSorter::spool(&sort);
+        filtered.extend(e)?;
+        for filter in &source_config.filters {
+            let mut next = ExternalSorter::spool(&sort);
+            filter.apply_stream(filtered.finish()?, &config.build.unsuitable_filter, &sort, stats, |entry| next.push(entry))
+                .map_err(|e| e.in_source(name))?;
+            filtered = next;
+        }
+        for entry in filtered.finish()? {
+            entries.push(entry?)?;
+        }
+        used.push(source_config);
+    }
+    println!("dictionary sources: {}", used.iter().map(|source| source.name.as_str()).collect::<Vec<&str>>().join(" "));
 
-    let dicname = "mozcdic-ut.txt";
-    let dicname_pre = "mozcdic-ut-pre.txt";
-    {
-        let mut f = File::create(&dicname_pre).unwrap();
-        let mut append = |filename: &str| {
-            let mut fc = File::open(filename).unwrap();
-            std::io::copy(&mut fc, &mut f).expect(filename);
-        };
-
-        let mut i = 1;
-        let count = args.len();
-        while i < count {
-            let argument = args[i];
-            match argument {
-                "altcannadic" => {
-                    append("mozcdic-ut-alt-cannadic.txt");
-                    append("mozcdic-ut-alt-cannadic-jinmei.txt");
-                }
-                "chimei" => {
-                    append("mozcdic-ut-chimei.txt");
-                }
-                "edict2" => {
-                    append("mozcdic-ut-edict2.txt");
-                }
-                "jawikiarticles" => {
-                    append("mozcdic-ut-jawiki.txt");
-                }
-                "jinmeiut" => {
-                    append("mozcdic-ut-jinmei.txt");
-                }
-                "neologd" => {
-                    append("mozcdic-ut-neologd.txt");
-                }
-                "skkdic" => {
-                    append("mozcdic-ut-skkdic.txt");
-                }
-                "sudachidic" => {
-                    append("mozcdic-ut-sudachidict-core.txt");
-                    append("mozcdic-ut-sudachidict-notcore.txt");
-                }
-                _ => {
-                    println!("unknown option: {}", argument);
-                    std::process::exit(-1);
-                }
-            }
-            i += 1;
+    for filter in &config.build.filters {
+        let mut filtered = ExternalSorter::spool(&sort);
+        filter.apply_stream(entries.finish()?, &config.build.unsuitable_filter, &sort, stats, |entry| filtered.push(entry))?;
+        entries = filtered;
+    }
+    let titles = open_entries(JAWIKI_HITS_NAME, "jawikititles")?;
+    let entries = calculate_costs_stream(entries.finish()?, titles, &config.costs, &sort, stats)?;
+
+    Ok((entries, used))
+}
+
+/// Run to make dictionary with sources selected from the configuration.
+fn workflow(args: &[&str], fetcher: &Fetcher, config: &Config) -> Result<()> {
+    let sources = config.selected_sources(&args[1..])?; // skip workflow
+
+    std::fs::create_dir_all("build").with_path("build")?;
+    std::env::set_current_dir("build").with_path("build")?;
+
+    remove_matched(".", r"^mozcdic-")?;
+    remove_matched(".", r"^jawiki-ut-")?;
+
+    let mut stats = BuildStats::default();
+    let (entries, sources) = build_entries(sources, fetcher, config, &mut stats)?;
+
+    // 辞書と出典を1行ずつ書き出す
+    let dicname = config.build.output.as_str();
+    let mut writer = BufWriter::new(File::create(dicname).with_path(dicname)?);
+    let mut provenance = match &config.build.provenance {
+        Some(filename) => Some((filename, BufWriter::new(File::create(filename).with_path(filename)?))),
+        None => None,
+    };
+    let mut counts = BTreeMap::new();
+    for (index, entry) in entries.enumerate() {
+        let entry = entry?;
+        writeln!(writer, "{}", entry).with_path(dicname)?;
+        if let Some((filename, writer)) = &mut provenance {
+            write_provenance_line(writer, index + 1, &entry).with_path(filename)?;
         }
+        *counts.entry(entry.license).or_insert(0) += 1;
+        stats.add_output(&entry);
+    }
+    writer.flush().with_path(dicname)?;
+    if let Some((filename, mut writer)) = provenance {
+        writer.flush().with_path(filename)?;
+    }
+    stats.finish();
+
+    print!("{}", stats);
+    if let Some(filename) = &config.build.stats {
+        stats.write_json(filename)?;
     }
 
-    command_runner(&vec!["unnecessary", &dicname_pre]);
-    let dicname_need = format!("{}.need", &dicname_pre);
-    command_runner(&vec!["costs", &dicname_need]);
-    let dicname_costs = format!("{}.costs", &dicname_need);
-    command_wait("mv", vec![&dicname_costs, &dicname]).unwrap();
+    validate_dictionary(dicname)?;
 
     // Generates README.md file.
     {
-        let head = "mozcdic_ut dictionary generated by mozcdic_ut_rs which
-is ported from original mozcdic_ut to Rust.
+        let mut f = File::create("README.md").with_path("README.md")?;
+        f.write_all(config.build.readme.as_bytes())?;
+        for source in &sources {
+            f.write_all(source.attribution.as_bytes())?;
+            f.write_all(b"\n")?;
+        }
+        writeln!(f, "\nNumber of entries for each license.\n")?;
+        for (license, count) in &counts {
+            let name = license.map_or("unknown", License::name);
+            writeln!(f, "* {}: {}", name, count)?;
+            println!("{}: {} entries", name, count);
+        }
+    }
 
-Before building mozc, merge the dictionary into mozc oss dictionary as follows.
+    // Generates archive
+    let mut args = vec![String::from(dicname), String::from("README.md")];
+    for license_file in sources.iter().filter_map(|source| source.license_file.as_ref()) {
+        let name = Path::new(license_file).file_name().map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| Error::other(format!("invalid license file: {}", license_file)))?;
+        if !args.contains(&name) {
+            std::fs::copy(license_file, &name).with_path(license_file)?;
+            args.push(name);
+        }
+    }
 
-```
-cat mozcdic-ut-XX.txt >> mozc-master/src/data/dictionary_oss/dictionary00.txt
-```
+    let args = args.iter().map(String::as_str).collect::<Vec<&str>>();
+    tar_bz2(&config.build.archive, &args).with_path(&config.build.archive)
+}
 
-This dictionary contains entries from the following projects.\n";
-        let mut f = File::create("README.md").unwrap();
-        f.write_all(head.as_bytes()).unwrap();
-        for line in readme {
-            f.write_all(line.as_bytes()).unwrap();
-            f.write(b"\n").unwrap();
+/// Traces entries which have the reading and the surface through sources,
+/// filters and cost calculation, and prints every decision made for them.
+fn explain(args: &[&str], fetcher: &Fetcher, config: &Config) -> Result<()> {
+    let mut query = Query::default();
+    let mut commands = Vec::new();
+    let mut it = args.iter().skip(1); // skip explain
+    while let Some(&arg) = it.next() {
+        match arg {
+            "--reading" => query.reading = Some(String::from(*it.next().ok_or_else(|| Error::other("--reading option requires reading"))?)),
+            "--surface" => query.surface = Some(String::from(*it.next().ok_or_else(|| Error::other("--surface option requires surface"))?)),
+            _ => commands.push(arg),
         }
     }
+    if query.reading.is_none() && query.surface.is_none() {
+        return Err(Error::other("explain requires --reading or --surface"));
+    }
+    let sources = config.selected_sources(&commands)?;
 
-    // Generates archive
-    let archive_name = "mozcdic-ut.tar.bz2";
-    let mut args = vec!["-cjf", &archive_name, dicname, "README.md"];
+    std::fs::create_dir_all("build").with_path("build")?;
+    std::env::set_current_dir("build").with_path("build")?;
 
-    if licenses.apl2 {
-        std::fs::copy("../data/license/Apache-2.0.txt", "Apache-2.0.txt").unwrap();
-        args.push("Apache-2.0.txt");
+    let mut stats = BuildStats::with_query(query);
+    let (entries, _) = build_entries(sources, fetcher, config, &mut stats)?;
+    let mut found = false;
+    for entry in entries {
+        let entry = entry?;
+        if stats.explains(&entry) {
+            stats.explain(&entry, "written into the dictionary");
+            found = true;
+        }
+    }
+    if !found {
+        println!("explain: no entry is written into the dictionary");
     }
-    if licenses.ccbysa3 {
-        std::fs::copy("../data/license/CC-BY-SA-3.0.txt", "CC-BY-SA-3.0.txt").unwrap();
-        args.push("CC-BY-SA-3.0.txt");
+    Ok(())
+}
+
+/// Compares two dictionaries and prints the differences.
+fn diff(args: &[&str]) -> Result<()> {
+    let mut settings = DiffSettings::default();
+    let mut provenances = [None, None];
+    let mut files = Vec::new();
+    let mut it = args.iter().skip(1); // skip diff
+    while let Some(&arg) = it.next() {
+        match arg {
+            "--prefix" => settings.prefix_len = it.next().and_then(|n| n.parse().ok())
+                .ok_or_else(|| Error::other("--prefix option requires number of characters"))?,
+            "--threshold" => settings.threshold = it.next().and_then(|n| n.parse().ok())
+                .ok_or_else(|| Error::other("--threshold option requires cost"))?,
+            "--list" => settings.list = true,
+            "--old-provenance" => provenances[0] = Some(*it.next().ok_or_else(|| Error::other("--old-provenance option requires file path"))?),
+            "--new-provenance" => provenances[1] = Some(*it.next().ok_or_else(|| Error::other("--new-provenance option requires file path"))?),
+            _ => files.push(arg),
+        }
     }
-    if licenses.gpl2 {
-        std::fs::copy("../data/license/GPL-2.0.txt", "GPL-2.0.txt").unwrap();
-        args.push("GPL-2.0.txt");
+    if files.len() != 2 {
+        return Err(Error::other("diff requires OLD and NEW dictionaries"));
     }
 
-    command_wait("tar", args).unwrap();
+    let mut dictionaries = Vec::new();
+    for (file, provenance) in files.iter().zip(provenances) {
+        let mut entries = read_dictionary(file)?;
+        if let Some(provenance) = provenance {
+            read_provenance_sources(provenance, &mut entries)?;
+        }
+        dictionaries.push(entries);
+    }
+    let new = dictionaries.pop().unwrap();
+    let old = dictionaries.pop().unwrap();
+    let diff = diff_entries(old, new);
+    print!("{}", diff.report(&settings));
+    Ok(())
 }
 
-fn main() {
-    let args = std::env::args().collect::<Vec<String>>();
+/// Searches the built dictionary and prints entries found for each query.
+fn lookup(args: &[&str], config: &Config) -> Result<()> {
+    let mut kind = SearchKind::Reading;
+    let mut with_mozc = false;
+    let mut dictionary = None;
+    let mut queries = Vec::new();
+    let mut it = args.iter().skip(1); // skip lookup
+    while let Some(&arg) = it.next() {
+        match arg {
+            "--prefix" => kind = SearchKind::Prefix,
+            "--surface" => kind = SearchKind::Surface,
+            "--mozc" => with_mozc = true,
+            "--dictionary" => dictionary = Some(absolute_path(it.next().ok_or_else(|| Error::other("--dictionary option requires file path"))?)?),
+            _ => queries.push(String::from(arg)),
+        }
+    }
+
+    std::env::set_current_dir("build").with_path("build")?;
 
-    if args.len() > 1 {
-        let a = args
-            .iter()
-            .skip(1)
-            .map(|s| s.as_str())
-            .collect::<Vec<&str>>();
-
-        match args[1].as_str() {
-            "workflow" => workflow(&a),
-            "help" => help(),
-            _ => command_runner(&a),
+    let dicname = dictionary.map_or_else(|| config.build.output.clone(), |path| path.to_string_lossy().into_owned());
+    let index = DictIndex::new(read_entries(&dicname, "ut")?);
+    let mozc = if with_mozc { DictIndex::new(read_mozc_entries()?) } else { DictIndex::default() };
+    // id.def がない場合は品詞名を表示しない
+    let pos_names = pos_table().unwrap_or_default();
+    let pos_name = |id: u16| pos_names.get(id).map_or_else(String::new, Pos::to_string);
+    println!("{} entries in {}", index.len(), dicname);
+
+    let print_query = |query: &str| {
+        let entries = index.search(kind, query);
+        println!("{}: {} entries", query, entries.len());
+        for entry in entries {
+            println!("{}\t{}", entry, pos_name(entry.left_id));
+            for shadowed in mozc.same_word(entry) {
+                println!("    shadows mozc: {}\t{}", shadowed, pos_name(shadowed.left_id));
+            }
+        }
+    };
+    if queries.is_empty() {
+        for line in std::io::stdin().lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                print_query(line.trim());
+            }
         }
     } else {
-        help()
+        queries.iter().for_each(|query| print_query(query));
+    }
+    Ok(())
+}
+
+/// Evaluates conversion of the test set with Mozc OSS dictionary only,
+/// and with each dictionary added to it.
+fn evaluate_dictionaries(args: &[&str], fetcher: &Fetcher, config: &Config) -> Result<()> {
+    let mut n = 5;
+    let mut show_failures = false;
+    let mut files = Vec::new();
+    let mut it = args.iter().skip(1); // skip evaluate
+    while let Some(&arg) = it.next() {
+        match arg {
+            "--top" => n = it.next().and_then(|n| n.parse().ok()).ok_or_else(|| Error::other("--top option requires number"))?,
+            "--failures" => show_failures = true,
+            _ => files.push(absolute_path(arg)?.to_string_lossy().into_owned()),
+        }
+    }
+    if files.is_empty() {
+        return Err(Error::other("evaluate requires test set"));
+    }
+    let cases = read_test_cases(&files.remove(0))?;
+
+    std::fs::create_dir_all("build").with_path("build")?;
+    std::env::set_current_dir("build").with_path("build")?;
+    command_runner(&["mozc"], fetcher, config)?;
+    if files.is_empty() {
+        files.push(config.build.output.clone());
+    }
+
+    let connection = Connection::read(CONNECTION_FILE_NAME)?;
+    // 辞書にない文字は名詞として扱う
+    let unknown_id = pos_table()?.id("名詞,一般")?;
+    let mozc = read_mozc_entries()?;
+    let names = std::iter::once(String::from("mozc")).chain(files.iter().cloned());
+    for (index, name) in names.enumerate() {
+        let mut entries = mozc.clone();
+        if index > 0 {
+            entries.extend(read_entries(&name, "ut")?);
+        }
+        let index = DictIndex::new(entries);
+        let evaluation = evaluate(&Converter::new(&index, &connection, unknown_id), &cases, n);
+        println!("{}: {}", name, evaluation);
+        if show_failures {
+            for (case, candidates) in &evaluation.failures {
+                println!("  {}\t{}\t{}", case.reading, case.expected, candidates.join(" / "));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses options placed before the command, returns fetcher, configuration
+/// and the rest of arguments.
+fn parse_options(args: &[String]) -> Result<(Fetcher, Config, Vec<&str>)> {
+    let mut offline_dir = None;
+    let mut config_file = None;
+    let mut licenses = None;
+    let mut overrides = Vec::new();
+
+    let mut it = args.iter().skip(1).map(|s| s.as_str()).peekable();
+    while let Some(&option) = it.peek() {
+        match option {
+            "--offline" => {
+                it.next();
+                if let Some(dir) = it.next() {
+                    offline_dir = Some(dir);
+                } else {
+                    return Err(Error::other("--offline option requires directory path"));
+                }
+            }
+            "--config" => {
+                it.next();
+                if let Some(file) = it.next() {
+                    config_file = Some(file);
+                } else {
+                    return Err(Error::other("--config option requires file path"));
+                }
+            }
+            "--licenses" => {
+                it.next();
+                if let Some(list) = it.next() {
+                    licenses = Some(parse_licenses(list).ok_or_else(|| Error::other(format!("unknown license in {}", list)))?);
+                } else {
+                    return Err(Error::other("--licenses option requires license names"));
+                }
+            }
+            "--path" => {
+                it.next();
+                if let Some((name, path)) = it.next().and_then(|s| s.split_once('=')) {
+                    overrides.push((name, path));
+                } else {
+                    return Err(Error::other("--path option requires NAME=PATH"));
+                }
+            }
+            _ => break,
+        }
+    }
+
+    let mut fetcher = if let Some(dir) = offline_dir {
+        Fetcher::offline(absolute_path(dir)?)
+    } else {
+        Fetcher::online()
+    };
+    for (name, path) in overrides {
+        fetcher.set_override(name, absolute_path(path)?);
+    }
+
+    let mut config = if let Some(file) = config_file {
+        Config::read(file)?
+    } else {
+        Config::default()
+    };
+    if let Some(licenses) = licenses {
+        config.build.licenses = licenses;
+    }
+
+    Ok((fetcher, config, it.collect()))
+}
+
+/// Makes path absolute because workflow changes working directory.
+fn absolute_path(path: &str) -> Result<std::path::PathBuf> {
+    let path = std::path::PathBuf::from(path);
+    if path.is_absolute() {
+        Ok(path)
+    } else {
+        Ok(std::env::current_dir()?.join(path))
+    }
+}
+
+/// Parses arguments and runs the command.
+fn run(args: &[String]) -> Result<()> {
+    let (fetcher, config, a) = parse_options(args)?;
+
+    if !a.is_empty() {
+        match a[0] {
+            "workflow" => workflow(&a, &fetcher, &config),
+            "explain" => explain(&a, &fetcher, &config),
+            "diff" => diff(&a),
+            "lookup" => lookup(&a, &config),
+            "evaluate" => evaluate_dictionaries(&a, &fetcher, &config),
+            "help" => {
+                help();
+                Ok(())
+            }
+            _ => command_runner(&a, &fetcher, &config),
+        }
+    } else {
+        help();
+        Ok(())
+    }
+}
+
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    if let Err(e) = run(&args) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,306 @@
+// Streaming reader of MediaWiki XML dumps such as jawiki pages-articles.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use bzip2::read::BzDecoder;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use super::error::{Error, Result, WithPath};
+
+
+/// Namespace of articles.
+pub const NS_ARTICLE: i32 = 0;
+
+/// Page of the dump with decoded XML entities.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Page {
+    /// Namespace number, `NS_ARTICLE` for articles.
+    pub ns: i32,
+    /// Title including the namespace prefix, such as `Template:Infobox`.
+    pub title: String,
+    /// Title of the target page if the page is a redirect.
+    pub redirect: Option<String>,
+    /// Wikitext of the latest revision.
+    pub text: String,
+}
+
+impl Page {
+    /// Returns the title of the target page if the page is a redirect, from
+    /// the redirect element or text such as `#REDIRECT [[target]]` or `#転送 [[target]]`.
+    /// The section after `#` in the target is removed.
+    pub fn redirect_target(&self) -> Option<&str> {
+        let target = match &self.redirect {
+            Some(target) => target.as_str(),
+            None => {
+                let (magic, link) = self.text.trim_start().strip_prefix('#')?.split_once("[[")?;
+                let magic = magic.trim();
+                if !(magic.eq_ignore_ascii_case("REDIRECT") || magic == "転送") {
+                    return None;
+                }
+                let (link, _) = link.split_once("]]")?;
+                link.split('|').next().unwrap_or(link)
+            }
+        };
+        let target = target.split('#').next().unwrap_or(target).trim();
+        if target.is_empty() {
+            None
+        } else {
+            Some(target)
+        }
+    }
+}
+
+/// Element of a page whose text is collected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Ns,
+    Title,
+    Text,
+}
+
+/// Makes error of the XML at the byte position.
+fn xml_error<E: std::fmt::Display>(position: usize, e: E) -> Error {
+    Error::other(format!("broken XML at byte {}: {}", position, e))
+}
+
+/// Returns the title attribute of the redirect element.
+fn redirect_title(e: &BytesStart, position: usize) -> Result<Option<String>> {
+    for attribute in e.attributes() {
+        let attribute = attribute.map_err(|e| xml_error(position, e))?;
+        if attribute.key.as_ref() == b"title" {
+            return Ok(Some(attribute.unescape_value().map_err(|e| xml_error(position, e))?.into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// Reads pages one by one from XML of a MediaWiki dump.
+pub struct PageReader<R: BufRead> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+}
+
+impl<R: BufRead> PageReader<R> {
+    /// Creates reader of decompressed XML.
+    pub fn new(reader: R) -> PageReader<R> {
+        PageReader {
+            reader: Reader::from_reader(reader),
+            buf: Vec::with_capacity(64 * 1024),
+        }
+    }
+
+    /// Creates reader of a part of decompressed XML, such as a stream of
+    /// a multistream dump, whose end tags may not match to start tags.
+    pub fn fragment(reader: R) -> PageReader<R> {
+        let mut pages = PageReader::new(reader);
+        pages.reader.check_end_names(false);
+        pages
+    }
+
+    /// Returns the next page, or None at the end of the dump.
+    pub fn next_page(&mut self) -> Result<Option<Page>> {
+        let mut page: Option<Page> = None;
+        let mut field = None;
+        let mut value = String::new();
+        loop {
+            self.buf.clear();
+            let event = self.reader.read_event_into(&mut self.buf);
+            let position = self.reader.buffer_position();
+            let event = event.map_err(|e| xml_error(position, e))?;
+            match event {
+                Event::Start(e) => match e.name().as_ref() {
+                    b"page" => page = Some(Page::default()),
+                    b"ns" if page.is_some() => field = Some(Field::Ns),
+                    b"title" if page.is_some() => field = Some(Field::Title),
+                    b"text" if page.is_some() => field = Some(Field::Text),
+                    _ => {}
+                },
+                Event::Empty(e) if e.name().as_ref() == b"redirect" => {
+                    let title = redirect_title(&e, position)?;
+                    if let Some(page) = &mut page {
+                        page.redirect = title;
+                    }
+                }
+                Event::Text(e) if field.is_some() => {
+                    value.push_str(&e.unescape().map_err(|e| xml_error(position, e))?);
+                }
+                Event::CData(e) if field.is_some() => {
+                    value.push_str(std::str::from_utf8(&e).map_err(|e| xml_error(position, e))?);
+                }
+                Event::End(e) => {
+                    if e.name().as_ref() == b"page" {
+                        if page.is_some() {
+                            return Ok(page);
+                        }
+                        continue;
+                    }
+                    if let (Some(f), Some(page)) = (field.take(), &mut page) {
+                        let value = std::mem::take(&mut value);
+                        match f {
+                            Field::Ns => page.ns = value.trim().parse().map_err(|e| xml_error(position, format!("invalid ns {:?}: {}", value, e)))?,
+                            Field::Title => page.title = value,
+                            Field::Text => page.text = value,
+                        }
+                    }
+                }
+                Event::Eof => {
+                    return match page {
+                        Some(page) => Err(xml_error(position, format!("page {:?} is not closed", page.title))),
+                        None => Ok(None),
+                    };
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PageReader<R> {
+    type Item = Result<Page>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_page().transpose()
+    }
+}
+
+/// Independent bzip2 stream of a multistream dump.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DumpStream {
+    /// Byte offset of the stream in the dump.
+    pub offset: u64,
+    /// Compressed length of the stream, None for the last stream.
+    pub len: Option<u64>,
+    /// Number of pages in the stream.
+    pub pages: usize,
+}
+
+/// Reads decompressed multistream index, whose lines are `offset:page id:title`,
+/// and returns streams in the order of offsets.
+pub fn read_multistream_index<R: BufRead>(reader: R) -> Result<Vec<DumpStream>> {
+    let mut streams: Vec<DumpStream> = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let offset = line.split_once(':').and_then(|(offset, _)| offset.parse::<u64>().ok())
+            .ok_or_else(|| Error::other(format!("line {}: no offset in {:?}", index + 1, line)))?;
+        match streams.last_mut() {
+            Some(last) if last.offset == offset => last.pages += 1,
+            Some(last) if last.offset > offset => {
+                return Err(Error::other(format!("line {}: offset {} is smaller than {}", index + 1, offset, last.offset)));
+            }
+            _ => {
+                if let Some(last) = streams.last_mut() {
+                    last.len = Some(offset - last.offset);
+                }
+                streams.push(DumpStream { offset, len: None, pages: 1 });
+            }
+        }
+    }
+    Ok(streams)
+}
+
+/// Decompresses the stream of the multistream dump and returns its pages.
+pub fn read_stream_pages(dump: &Path, stream: &DumpStream) -> Result<Vec<Page>> {
+    let mut file = File::open(dump).with_path(dump)?;
+    file.seek(SeekFrom::Start(stream.offset)).with_path(dump)?;
+    let compressed: Box<dyn Read> = match stream.len {
+        Some(len) => Box::new(file.take(len)),
+        None => Box::new(file),
+    };
+    let pages = PageReader::fragment(BufReader::new(BzDecoder::new(compressed)));
+    pages.collect::<Result<Vec<Page>>>()
+        .map_err(|e| Error::other(format!("{}: stream at {}: {}", dump.display(), stream.offset, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUMP: &str = r#"<mediawiki xml:lang="ja">
+  <siteinfo><sitename>Wikipedia</sitename></siteinfo>
+  <page>
+    <title>AT&amp;T</title>
+    <ns>0</ns>
+    <redirect title="エイ・ティ・アンド・ティ" />
+    <revision><text xml:space="preserve">#REDIRECT [[エイ・ティ・アンド・ティ]]</text></revision>
+  </page>
+  <page>
+    <title>Template:テスト</title>
+    <ns>10</ns>
+    <revision><text xml:space="preserve">'''皆藤 愛子'''&lt;ref&gt;x&lt;/ref&gt;(かいとう あいこ</text></revision>
+  </page>
+</mediawiki>
+"#;
+
+    #[test]
+    fn read_pages_with_entities_and_redirects() {
+        let pages = PageReader::new(DUMP.as_bytes()).collect::<Result<Vec<Page>>>().unwrap();
+        assert_eq!(pages, [
+            Page {
+                ns: NS_ARTICLE,
+                title: String::from("AT&T"),
+                redirect: Some(String::from("エイ・ティ・アンド・ティ")),
+                text: String::from("#REDIRECT [[エイ・ティ・アンド・ティ]]"),
+            },
+            Page {
+                ns: 10,
+                title: String::from("Template:テスト"),
+                redirect: None,
+                text: String::from("'''皆藤 愛子'''<ref>x</ref>(かいとう あいこ"),
+            },
+        ]);
+        assert_eq!(pages[0].redirect_target(), Some("エイ・ティ・アンド・ティ"));
+        assert_eq!(pages[1].redirect_target(), None);
+    }
+
+    #[test]
+    fn redirect_target_from_text() {
+        let page = |text: &str| Page { text: String::from(text), ..Page::default() };
+        assert_eq!(page("#転送 [[日本#歴史|日本]]").redirect_target(), Some("日本"));
+        assert_eq!(page("#redirect[[東京都]]").redirect_target(), Some("東京都"));
+        assert_eq!(page("#1 [[東京都]]").redirect_target(), None);
+        assert_eq!(page("'''東京都'''").redirect_target(), None);
+    }
+
+    #[test]
+    fn unclosed_page_is_error() {
+        let mut pages = PageReader::new("<mediawiki><page><title>x</title>".as_bytes());
+        assert!(pages.next_page().is_err());
+    }
+
+    #[test]
+    fn read_streams_of_multistream_dump() {
+        use bzip2::write::BzEncoder;
+        use std::io::Write;
+
+        // ヘッダー、記事、フッターをそれぞれ独立したストリームにする
+        let (header, rest) = DUMP.split_at(DUMP.find("  <page>").unwrap());
+        let (page, footer) = rest.split_at(rest.find("</mediawiki>").unwrap());
+        let mut dump = Vec::new();
+        let mut offsets = Vec::new();
+        for part in [header, page, footer] {
+            offsets.push(dump.len() as u64);
+            let mut encoder = BzEncoder::new(Vec::new(), bzip2::Compression::default());
+            encoder.write_all(part.as_bytes()).unwrap();
+            dump.extend(encoder.finish().unwrap());
+        }
+        let path = std::env::temp_dir().join(format!("mozcdic-ut-multistream-{}.xml.bz2", std::process::id()));
+        std::fs::write(&path, &dump).unwrap();
+
+        let index = format!("{0}:1:AT&T\n{0}:2:Template:テスト\n{1}:3:x\n", offsets[1], offsets[2]);
+        let streams = read_multistream_index(index.as_bytes()).unwrap();
+        assert_eq!(streams, [
+            DumpStream { offset: offsets[1], len: Some(offsets[2] - offsets[1]), pages: 2 },
+            DumpStream { offset: offsets[2], len: None, pages: 1 },
+        ]);
+
+        let pages = read_stream_pages(&path, &streams[0]).unwrap();
+        assert_eq!(pages.iter().map(|page| page.title.as_str()).collect::<Vec<_>>(), ["AT&T", "Template:テスト"]);
+        assert!(read_stream_pages(&path, &streams[1]).unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(read_multistream_index("20:1:a\n10:2:b\n".as_bytes()).is_err());
+    }
+}
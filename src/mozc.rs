@@ -3,32 +3,40 @@
 
 use std::io::{BufRead, BufReader};
 use std::fs::File;
+use std::path::Path;
 
-use regex::*;
-
+use super::archive::*;
+use super::entry::*;
+use super::error::{Result, WithPath};
+use super::evaluate::CONNECTION_FILE_NAME;
+use super::fetch::Fetcher;
 use super::util::*;
 
 
-/// Get Mozc id matches to passed regexp,
-/// ex. r"(\d*) 名詞,固有名詞,地域,一般,\*,\*,\*".
-pub fn get_id(exp: &str) -> std::io::Result<String> {
-    let re = Regex::new(exp).unwrap();
+/// Name of the source which tags entries of Mozc OSS dictionary.
+pub const MOZC_NAME: &str = "mozc";
 
-    let s = read_file("id.def").expect("id.def file not found");
-    if let Some(caps) = re.captures(&s) {
-        Ok(String::from(caps.get(1).unwrap().as_str()))
-    } else {
-        return Err(std::io::Error::new(std::io::ErrorKind::Other, "id not found"));
-    }
+/// File of Mozc OSS dictionary concatenated from dictionary_oss directory.
+pub const MOZCDIC_NAME: &str = "mozcdic.txt";
+
+/// Reads entries of Mozc OSS dictionary from mozcdic.txt file.
+pub fn read_mozc_entries() -> Result<Vec<DictEntry>> {
+    read_entries(MOZCDIC_NAME, MOZC_NAME)
 }
 
-pub fn parse_mozc_version_template() -> std::io::Result<String> {
-    let f = File::open("mozc_version_template.bzl")?;
+/// Returns iterator of entries of Mozc OSS dictionary read from mozcdic.txt file.
+pub fn open_mozc_entries() -> Result<Entries<BufReader<File>>> {
+    open_entries(MOZCDIC_NAME, MOZC_NAME)
+}
+
+/// Reads version of Mozc from mozc_version_template.bzl file.
+pub fn parse_mozc_version_template(filename: &Path) -> Result<String> {
+    let f = File::open(filename).with_path(filename)?;
     let mut reader = BufReader::new(f);
 
     let mut version = String::with_capacity(32);
     let mut line = String::new();
-    while let Ok(1..) = reader.read_line(&mut line) {
+    while reader.read_line(&mut line)? > 0 {
         if let Some(index) = line.find("MAJOR = ") {
             version.push_str(unsafe { line.get_unchecked(index + 8..) }.trim_end());
             version.push('.');
@@ -51,30 +59,42 @@ pub fn parse_mozc_version_template() -> std::io::Result<String> {
     Ok(version)
 }
 
-pub fn get_mozc(version: &str) -> std::io::Result<()> {
+/// Gets source of Mozc and extracts id.def, connection costs and mozcdic.txt files from it.
+pub fn get_mozc(version: &str, fetcher: &Fetcher) -> Result<()> {
     let mozcdir = format!("mozc-{}.102", version);
 
     // Get the latest mozc
     let tarfile = format!("{}.tar.bz2", mozcdir);
-    if let Ok(_) = File::open(&tarfile) {
+    if File::open(&tarfile).is_ok() {
         println!("{} already exists.", tarfile);
+        // 接続コストを取り出す前に作られたアーカイブから取り出す
+        if File::open(CONNECTION_FILE_NAME).is_err() {
+            let path = format!("src/data/dictionary_oss/{}", CONNECTION_FILE_NAME);
+            if let Some((_, content)) = read_tar_bz2_file(&tarfile, |name| name.ends_with(&path)).with_path(&tarfile)? {
+                std::fs::write(CONNECTION_FILE_NAME, content).with_path(CONNECTION_FILE_NAME)?;
+            }
+        }
         return Ok(());
     }
 
-    let zipfile = format!("{}.zip", mozcdir);
-    if let Ok(_) = File::open(&zipfile) {
-        println!("{}.zip already exists.", zipfile);
+    // オフラインの場合はバージョンなしのファイル名を使う
+    let zipfile = if fetcher.is_offline() {
+        String::from("mozc-master.zip")
     } else {
-        command_wait("rm", vec!["-f", "mozc-*.zip"])?;
-        command_wait("wget", vec!["-N", "-q", "https://github.com/google/mozc/archive/refs/heads/master.zip", "-O", &zipfile])?;
+        format!("{}.zip", mozcdir)
+    };
+    if !fetcher.is_offline() && File::open(&zipfile).is_err() {
+        remove_matched(".", r"mozc-.*\.zip")?;
     }
+    let zippath = fetcher.fetch("https://github.com/google/mozc/archive/refs/heads/master.zip", &zipfile, true)?;
 
-    command_wait("rm", vec!["-rf", "mozc-master"])?;
-    command_wait("unzip", vec!["-qq", &zipfile])?;
-    command_wait("cp", vec!["mozc-master/src/data/dictionary_oss/id.def", "."])?;
+    remove_dir_if_exists("mozc-master")?;
+    unzip(&zippath, ".")?;
+    std::fs::copy("mozc-master/src/data/dictionary_oss/id.def", "id.def")?;
+    std::fs::copy(format!("mozc-master/src/data/dictionary_oss/{}", CONNECTION_FILE_NAME), CONNECTION_FILE_NAME)?;
 
     {
-        let mut f = File::create("mozcdic.txt")?;
+        let mut f = File::create(MOZCDIC_NAME)?;
         for i in 0..10 {
             let path = format!("mozc-master/src/data/dictionary_oss/dictionary{:02}.txt", i);
             if let Ok(mut fd) = File::open(path) {
@@ -84,20 +104,23 @@ pub fn get_mozc(version: &str) -> std::io::Result<()> {
     }
 
     println!("Compress {}...", mozcdir);
-    command_wait("rm", vec!["-f", "mozc-*.tar.bz2"])?;
-    command_wait("rm", vec!["-rf", "mozc-master/src/third_party/"])?;
-    command_wait("mv", vec!["mozc-master", &mozcdir])?;
-    command_wait("tar", vec!["-cjf", &tarfile, &mozcdir])?;
-    command_wait("rm", vec!["-rf", &mozcdir])?;
-
-    return Ok(());
+    remove_matched(".", r"^mozc-.*\.tar\.bz2$")?;
+    remove_dir_if_exists("mozc-master/src/third_party/")?;
+    remove_dir_if_exists(&mozcdir)?;
+    std::fs::rename("mozc-master", &mozcdir)?;
+    tar_bz2(&tarfile, &[&mozcdir])?;
+    remove_dir_if_exists(&mozcdir)?;
+
+    Ok(())
 }
 
-pub fn get_the_latest_mozc() -> std::io::Result<()> {
-    command_wait("wget", vec!["-N", "-q", "https://raw.githubusercontent.com/google/mozc/master/src/data/version/mozc_version_template.bzl"])?;
-    let version = parse_mozc_version_template()?;
-    get_mozc(&version)?;
+/// Gets the latest source of Mozc.
+pub fn get_the_latest_mozc(fetcher: &Fetcher) -> Result<()> {
+    let template = fetcher.fetch("https://raw.githubusercontent.com/google/mozc/master/src/data/version/mozc_version_template.bzl",
+        "mozc_version_template.bzl", false)?;
+    let version = parse_mozc_version_template(&template)?;
+    get_mozc(&version, fetcher)?;
 
-    return Ok(());
+    Ok(())
 }
 
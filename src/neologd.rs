@@ -1,33 +1,61 @@
 
 // Ported from convert_neologd_to_mozcdic.rb file.
 
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{BufRead, BufReader};
 use std::fs::File;
+use std::path::PathBuf;
 
 use regex::*;
-use rayon::prelude::*;
 
-use super::mozc::get_id;
+use super::archive::*;
+use super::conjugation::Inflector;
+use super::entry::*;
+use super::error::{Error, Result, SkippedLines, WithPath};
+use super::license::License;
+use super::mozc::MOZCDIC_NAME;
+use super::person_name::{split_full_names, NameIds, NameParts};
+use super::pos::{pos_table, PosMap, PosTable};
+use super::fetch::Fetcher;
+use super::source::Source;
+use super::stats::SourceStats;
 use super::util::*;
 
 
-#[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
-struct Entry {
-    yomi: String,
-    hyouki: String,
-    cost: i32,
+const NAME: &str = "neologd";
+
+/// Parts of speech of neologd, which follows IPADIC as Mozc does, and
+/// their Mozc parts of speech. Others are 名詞,固有名詞,一般.
+const POS_RULES: &[(&str, &str)] = &[
+    ("名詞,固有名詞,一般", "名詞,固有名詞,一般"),
+    ("名詞,固有名詞,人名,一般", "名詞,固有名詞,人名,一般"),
+    ("名詞,固有名詞,人名,姓", "名詞,固有名詞,人名,姓"),
+    ("名詞,固有名詞,人名,名", "名詞,固有名詞,人名,名"),
+    ("名詞,固有名詞,組織", "名詞,固有名詞,組織"),
+    ("名詞,一般", "名詞,一般"),
+    ("名詞,サ変接続", "名詞,サ変接続"),
+    ("名詞,形容動詞語幹", "名詞,形容動詞語幹"),
+    ("名詞,副詞可能", "名詞,副詞可能"),
+];
+
+/// Returns mapping from parts of speech of neologd to ids in the table.
+pub fn pos_map(table: &PosTable) -> Result<PosMap> {
+    PosMap::new(table, POS_RULES, "名詞,固有名詞,一般")
 }
 
-fn convert_neologd_to_mozcdic(filename: &str, dicname: &str) -> std::io::Result<()> {
+/// Parses mecab-user-dict-seed CSV read from the reader.
+/// Entries are tagged with Mozc ids mapped from their parts of speech,
+/// Verbs and adjectives are expanded into conjugated forms by `inflector`.
+/// Malformed lines and skipped records are counted in `skipped`.
+pub fn parse_neologd<R: BufRead>(mut reader: R, pos: &PosMap, inflector: &Inflector, skipped: &mut SkippedLines) -> Result<Vec<DictEntry>> {
     // mecab-user-dict-seedを読み込む
     // Over 2610000 entries before sorting.
     let mut l2 = Vec::with_capacity(1024 * 1024 * 2 + 1024 * 512);
 
-    let d = File::open(filename)?;
-    let mut reader = BufReader::new(d);
     let mut line = String::new();
+    let mut line_no = 0;
     // neologd のエントリから読みと表記を取得
-    while let Ok(1..) = reader.read_line(&mut line) {
+    while reader.read_line(&mut line)? > 0 {
+        line_no += 1;
         // 表層形,左文脈ID,右文脈ID,コスト,品詞1,品詞2,品詞3,品詞4,品詞5,品詞6,\
         // 原形,読み,発音
         // little glee monster,1289,1289,2098,名詞,固有名詞,人名,一般,*,*,\
@@ -39,26 +67,45 @@ fn convert_neologd_to_mozcdic(filename: &str, dicname: &str) -> std::io::Result<
         // 新型コロナウイルス,1288,1288,4404,名詞,固有名詞,一般,*,*,*,\
         // 新型コロナウイルス,シンガタコロナウイルス,シンガタコロナウイルス
 
-        let mut s = line.split(",");
-        // cost, kind1, kind3, kind4, genkei(hyouki), yomi
-        // 3,    4,     6,     7,     10,             11
-        s.next(); s.next(); s.next(); // 0-2
-        let cost = i32::from_str_radix(s.next().unwrap(), 10).unwrap(); // 3
-        let kind1 = s.next().unwrap(); // 4
-        s.next();
-        let kind3 = s.next().unwrap(); // 6
-        let kind4 = s.next().unwrap(); // 7
-        s.next(); s.next(); // 8-9
+        let s = line.split(',').collect::<Vec<&str>>();
+        // cost, kind1, kind3, katsuyougata, katsuyoukei, genkei(hyouki), yomi
+        // 3,    4,     6,     8,            9,           10,             11
+        if s.len() < 12 {
+            skipped.skip(line_no, &line, "too few fields");
+            line.clear();
+            continue;
+        }
+        let cost = if let Ok(cost) = s[3].parse::<i32>() {
+            cost
+        } else {
+            skipped.skip(line_no, &line, "invalid cost");
+            line.clear();
+            continue;
+        };
+        let kind1 = s[4];
+        let kind3 = s[6];
         // 「原形」を表記にする
-        let hyouki = String::from(s.next().unwrap()); // 10
+        let hyouki = String::from(s[10]);
         // 「読み」を取得
-        let mut yomi = String::from(s.next().unwrap()); // 11
+        let mut yomi = String::from(s[11]);
 
         // 読みのカタカナをひらがなに変換
         yomi = to_hiragana_replace_ie(&yomi);
 
         // 読みがひらがな以外を含む場合はスキップ
-        if yomi.chars().any(|c| !(('ぁ' <= c && c <= 'ゔ') || c == 'ー')) {
+        if yomi.chars().any(|c| !(('ぁ'..='ゔ').contains(&c) || c == 'ー')) {
+            skipped.rule("reading is not hiragana", line_no, &line);
+            line.clear();
+            continue;
+        }
+
+        // 動詞と形容詞は基本形から活用形を作る
+        if kind1 == "動詞" || kind1 == "形容詞" {
+            let base = DictEntry::new(yomi, 0, cost, hyouki, NAME).with_record(line_no);
+            match inflector.inflect(s[8], &base) {
+                Some(mut entries) if s[9] == "基本形" => l2.append(&mut entries),
+                _ => skipped.rule("conjugation is not supported", line_no, &line),
+            }
             line.clear();
             continue;
         }
@@ -66,103 +113,124 @@ fn convert_neologd_to_mozcdic(filename: &str, dicname: &str) -> std::io::Result<
         // 名詞以外の場合はスキップ
         if kind1 != "名詞" ||
            // 「地域」をスキップ。地名は郵便番号ファイルから生成する
-           kind3 == "地域" ||
-           // 「名」をスキップ
-           kind4 == "名" {
+           kind3 == "地域" {
+            skipped.rule("part of speech is not used", line_no, &line);
             line.clear();
             continue;
         }
 
+        // 品詞1から品詞4までをMozcの品詞IDに変換
+        let id = pos.id(&s[4..8]);
+
         // [読み, 表記, コスト] の順に並べる
-        l2.push(Entry {
-            yomi,
-            hyouki,
-            cost,
-        });
+        l2.push(DictEntry::new(yomi, id, cost, hyouki, NAME).with_record(line_no));
 
         line.clear();
     }
 
-    let mut lines = l2;
-    lines.par_sort_unstable();
-
-    // Mozcの品詞IDを取得
-    // 「名詞,固有名詞,人名,一般,*,*」は優先度が低いので使わない。
-    // 「名詞,固有名詞,一般,*,*,*」は後でフィルタリングする。
-    let id = get_id(r"(\d*) 名詞,固有名詞,一般,\*,\*,\*,\*")?;
-
-    // Mozc形式で書き出す
-    let f = File::create(dicname)?;
-    let mut writer = BufWriter::new(f);
-
-    let count = lines.len();
-    for i in 0..count {
-        let s1 = &lines[i];
-        if i > 0 {
-            let s2 = &lines[i - 1];
-            // [読み..表記] が重複する場合はスキップ
-            if s1.yomi == s2.yomi && s1.hyouki == s2.hyouki {
-                continue;
-            }
-        }
+    Ok(l2)
+}
 
-        let cost = {
-            //let mut cost = i32::from_str_radix(&s1.cost, 10).unwrap();
-            let mut cost = s1.cost;
+/// Removes duplicated entries and adjusts costs of parsed neologd entries.
+pub fn convert_neologd_to_mozcdic(mut entries: Vec<DictEntry>) -> Vec<DictEntry> {
+    // [読み..表記] が重複する場合はスキップ
+    sort_dedup_by_reading_surface(&mut entries);
 
-            // コストがマイナスの場合は8000にする
-            if cost < 0 {
-                cost = 8000;
-            }
+    for entry in entries.iter_mut() {
+        let mut cost = entry.cost;
 
-            // コストが10000を超える場合は10000にする
-            if cost > 10000 {
-                cost = 10000;
-            }
+        // コストがマイナスの場合は8000にする
+        if cost < 0 {
+            cost = 8000;
+        }
 
-            // コストを 6000 < cost < 7000 に調整する
-            6000 + (cost / 10)
-        };
+        // コストが10000を超える場合は10000にする
+        if cost > 10000 {
+            cost = 10000;
+        }
 
-        // [読み,id,id,コスト,表記] の順に並べる
-        let v = format!("{}\t{}\t{}\t{}\t{}\n", &s1.yomi, id, id, cost, &s1.hyouki);
-        writer.write(v.as_bytes())?;
+        // コストを 6000 < cost < 7000 に調整する
+        entry.cost = 6000 + (cost / 10);
     }
 
-    Ok(())
+    entries
 }
 
-pub fn run_convert_neologd_to_mozcdic() -> std::io::Result<()> {
-    const URL: &str = "https://github.com/neologd/mecab-ipadic-neologd/tree/master/seed";
-    const SEED_NAME: &str = "seed.html";
+/// Converts mecab-ipadic-neologd seed file.
+#[derive(Debug, Default)]
+pub struct Neologd {
+    file_name: String,
+}
 
-    let re = Regex::new(r"mecab-user-dict-seed.(\d*).csv.xz").unwrap();
+impl Source for Neologd {
+    fn name(&self) -> &'static str {
+        NAME
+    }
 
-    command_wait("wget", vec!["-q", URL, "-O", SEED_NAME])?;
+    fn license(&self) -> License {
+        License::Apache2
+    }
+
+    fn fetch(&mut self, fetcher: &Fetcher) -> Result<()> {
+        const URL: &str = "https://github.com/neologd/mecab-ipadic-neologd/tree/master/seed";
 
-    let neologdver = {
-        let s = read_file(SEED_NAME)?;
-        if let Some(captures) = re.captures(&s) {
-            String::from(captures.get(1).unwrap().as_str())
+        // オフラインの場合はバージョンなしのファイル名を使う
+        // mecab-user-dict-seed.csv.xz
+        let neologdver = if fetcher.is_offline() {
+            String::new()
         } else {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "neologd version not found"));
-        }
-    };
+            let re = Regex::new(r"mecab-user-dict-seed.(\d*).csv.xz").unwrap();
 
-    command_wait("rm", vec![SEED_NAME])?;
+            let s = fetcher.fetch_text(URL)?;
+            if let Some(captures) = re.captures(&s) {
+                format!(".{}", captures.get(1).unwrap().as_str())
+            } else {
+                return Err(Error::other("neologd version not found"));
+            }
+        };
+
+        let file_name = format!("mecab-user-dict-seed{}.csv", neologdver);
+        let archive_name = format!("{}.xz", &file_name);
+        let addr = format!("https://github.com/neologd/mecab-ipadic-neologd/raw/master/seed/{}", &archive_name);
+
+        let archive = fetcher.fetch(&addr, &archive_name, true)?;
+        if fetcher.is_offline() || File::open(&file_name).is_err() {
+            unxz(&archive, &file_name)?;
+        }
 
-    let file_name = format!("mecab-user-dict-seed.{}.csv", neologdver);
-    let archive_name = format!("{}.xz", &file_name);
-    let addr = format!("https://github.com/neologd/mecab-ipadic-neologd/raw/master/seed/{}", &archive_name);
+        self.file_name = file_name;
+        Ok(())
+    }
 
-    if !File::open(&archive_name).is_ok() {
-        command_wait("wget", vec!["-nc", "-q", &addr])?;
+    fn inputs(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from(&self.file_name), PathBuf::from(MOZCDIC_NAME)]
     }
-    if !File::open(&file_name).is_ok() {
-        command_wait("7z", vec!["x", "-bb0", "-aos", &archive_name])?;
+
+    fn version(&self) -> u32 {
+        7
     }
 
-    convert_neologd_to_mozcdic(&file_name, "mozcdic-ut-neologd.txt")?;
+    fn parse(&self, stats: &mut SourceStats) -> Result<Vec<DictEntry>> {
+        // Mozcの品詞IDを取得
+        // 固有名詞は後でフィルタリングする。
+        let table = pos_table()?;
+        let pos = pos_map(&table)?;
+        let inflector = Inflector::new(&table);
+
+        let mut skipped = stats.skipped_lines(NAME, &self.file_name);
+        let entries = parse_neologd(BufReader::new(File::open(&self.file_name).with_path(&self.file_name)?), &pos, &inflector, &mut skipped)
+            .with_path(&self.file_name)?;
+        skipped.report();
+        stats.add_skipped(&skipped);
+
+        // フルネームを姓と名に分ける
+        let ids = NameIds::new(&table)?;
+        let mut parts = NameParts::read_mozc(&ids)?;
+        parts.add_entries(&entries, &ids);
+        Ok(split_full_names(entries, ids.full, &parts, &ids))
+    }
 
-    Ok(())
+    fn convert(&self, entries: Vec<DictEntry>) -> Result<Vec<DictEntry>> {
+        Ok(convert_neologd_to_mozcdic(entries))
+    }
 }
@@ -0,0 +1,248 @@
+// Person names split into family and given names, which Mozc combines in conversion.
+
+use std::collections::HashMap;
+
+use super::entry::DictEntry;
+use super::error::Result;
+use super::mozc::open_mozc_entries;
+use super::pos::PosTable;
+
+
+/// Ids of person names in id.def.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NameIds {
+    /// 名詞,固有名詞,人名,一般, used for full names.
+    pub full: u16,
+    /// 名詞,固有名詞,人名,姓
+    pub family: u16,
+    /// 名詞,固有名詞,人名,名
+    pub given: u16,
+}
+
+impl NameIds {
+    /// Looks up ids of person names in the table.
+    pub fn new(table: &PosTable) -> Result<NameIds> {
+        Ok(NameIds {
+            full: table.id("名詞,固有名詞,人名,一般")?,
+            family: table.id("名詞,固有名詞,人名,姓")?,
+            given: table.id("名詞,固有名詞,人名,名")?,
+        })
+    }
+}
+
+/// Full name split into the family name and the given name, as pairs of
+/// reading and surface.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FullName<'a> {
+    pub family: (&'a str, &'a str),
+    pub given: (&'a str, &'a str),
+    /// True if the family name is known before the split.
+    pub family_known: bool,
+    /// True if the given name is known before the split.
+    pub given_known: bool,
+}
+
+/// Family and given names known as separate entries, used to split full names.
+#[derive(Clone, Debug, Default)]
+pub struct NameParts {
+    /// Readings of family names keyed by their surfaces.
+    family: HashMap<String, Vec<String>>,
+    /// Readings of given names keyed by their surfaces.
+    given: HashMap<String, Vec<String>>,
+}
+
+impl NameParts {
+    /// Adds entries which have ids of family or given names, others are ignored.
+    pub fn add_entries<'a, I: IntoIterator<Item = &'a DictEntry>>(&mut self, entries: I, ids: &NameIds) {
+        for entry in entries {
+            let names = if entry.left_id == ids.family {
+                &mut self.family
+            } else if entry.left_id == ids.given {
+                &mut self.given
+            } else {
+                continue;
+            };
+            let readings = names.entry(entry.surface.clone()).or_default();
+            if !readings.contains(&entry.reading) {
+                readings.push(entry.reading.clone());
+            }
+        }
+    }
+
+    /// Reads family and given names of Mozc OSS dictionary.
+    pub fn read_mozc(ids: &NameIds) -> Result<NameParts> {
+        let mut parts = NameParts::default();
+        for entry in open_mozc_entries()? {
+            parts.add_entries(std::iter::once(&entry?), ids);
+        }
+        Ok(parts)
+    }
+
+    fn contains(names: &HashMap<String, Vec<String>>, reading: &str, surface: &str) -> bool {
+        names.get(surface).is_some_and(|readings| readings.iter().any(|r| r == reading))
+    }
+
+    /// Returns true if the family name is known.
+    pub fn is_family(&self, reading: &str, surface: &str) -> bool {
+        NameParts::contains(&self.family, reading, surface)
+    }
+
+    /// Returns true if the given name is known.
+    pub fn is_given(&self, reading: &str, surface: &str) -> bool {
+        NameParts::contains(&self.given, reading, surface)
+    }
+
+    /// Splits the full name at a known family name or a known given name.
+    /// Splits where both are known are preferred, then known family names,
+    /// then longer known parts, such as 中居 rather than 中 of 中居正広.
+    /// Returns None if neither part is known at any split.
+    pub fn split<'a>(&self, reading: &'a str, surface: &'a str) -> Option<FullName<'a>> {
+        let mut best: Option<((u8, usize), FullName)> = None;
+        for (i, _) in surface.char_indices().skip(1) {
+            let (family_surface, given_surface) = surface.split_at(i);
+            for (j, _) in reading.char_indices().skip(1) {
+                let (family_reading, given_reading) = reading.split_at(j);
+                let family_known = self.is_family(family_reading, family_surface);
+                let given_known = self.is_given(given_reading, given_surface);
+                let known_len = if family_known { family_surface.chars().count() } else { 0 } +
+                    if given_known { given_surface.chars().count() } else { 0 };
+                let score = (u8::from(family_known) * 2 + u8::from(given_known), known_len);
+                if score.0 > 0 && best.as_ref().map_or(true, |(best, _)| score > *best) {
+                    best = Some((score, FullName {
+                        family: (family_reading, family_surface),
+                        given: (given_reading, given_surface),
+                        family_known,
+                        given_known,
+                    }));
+                }
+            }
+        }
+        best.map(|(_, name)| name)
+    }
+}
+
+/// Returns true if the unknown part of a split looks like a family or given
+/// name: one to three kanji or kana read with as many kana or more.
+fn is_name_part((reading, surface): (&str, &str)) -> bool {
+    let len = surface.chars().count();
+    (1..=3).contains(&len) && (len..=len * 4).contains(&reading.chars().count()) &&
+        surface.chars().all(|c| ('\u{4E00}'..='\u{9FFF}').contains(&c) || ('ぁ'..='ゖ').contains(&c) ||
+                                ('ァ'..='ヺ').contains(&c) || c == '々' || c == 'ー')
+}
+
+/// Splits entries which have `id` into family and given names, so that Mozc
+/// combines them in conversion. A name is split when one part is known and
+/// the other looks like a name, and the unknown part is added with its id
+/// and the cost of the full name. The full name is kept only when both parts
+/// are known. Names which cannot be split, such as stage names and group
+/// names, are kept as they are with their original ids.
+pub fn split_full_names(entries: Vec<DictEntry>, id: u16, parts: &NameParts, ids: &NameIds) -> Vec<DictEntry> {
+    let mut result = Vec::with_capacity(entries.len());
+    for entry in entries {
+        // 一方が既知で、もう一方が名前らしい場合だけ分ける
+        // 明石家さんま は 明石 が姓でも 家さんま が名らしくないので分けない
+        let name = if entry.left_id == id { parts.split(&entry.reading, &entry.surface) } else { None };
+        let name = name.filter(|name| (name.family_known || is_name_part(name.family)) &&
+                                      (name.given_known || is_name_part(name.given)));
+        let name = match name {
+            Some(name) => name,
+            None => {
+                result.push(entry);
+                continue;
+            }
+        };
+
+        // 未知の姓または名のエントリを作る
+        for ((reading, surface), known, id) in [(name.family, name.family_known, ids.family),
+                                                (name.given, name.given_known, ids.given)] {
+            if !known {
+                let mut part = entry.clone();
+                part.reading = String::from(reading);
+                part.surface = String::from(surface);
+                part.left_id = id;
+                part.right_id = id;
+                result.push(part);
+            }
+        }
+
+        // 姓と名の両方が既知の場合はフルネームも残す
+        if name.family_known && name.given_known {
+            result.push(entry);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDS: NameIds = NameIds { full: 1, family: 2, given: 3 };
+    const OTHER: u16 = 4;
+
+    fn entry(reading: &str, surface: &str, id: u16) -> DictEntry {
+        DictEntry::new(String::from(reading), id, 6000, String::from(surface), "test")
+    }
+
+    fn parts(names: &[(&str, &str, u16)]) -> NameParts {
+        let entries: Vec<DictEntry> = names.iter().map(|(reading, surface, id)| entry(reading, surface, *id)).collect();
+        let mut parts = NameParts::default();
+        parts.add_entries(&entries, &IDS);
+        parts
+    }
+
+    fn words(entries: &[DictEntry]) -> Vec<(&str, &str, u16)> {
+        entries.iter().map(|e| (e.reading.as_str(), e.surface.as_str(), e.left_id)).collect()
+    }
+
+    #[test]
+    fn split_prefers_longer_known_part() {
+        let parts = parts(&[("なか", "中", IDS.family), ("なかい", "中居", IDS.family)]);
+        let name = parts.split("なかいまさひろ", "中居正広").unwrap();
+        assert_eq!(name.family, ("なかい", "中居"));
+        assert_eq!(name.given, ("まさひろ", "正広"));
+        assert!(name.family_known && !name.given_known);
+    }
+
+    #[test]
+    fn split_prefers_both_known() {
+        let parts = parts(&[
+            ("なかい", "中居", IDS.family),
+            ("なか", "中", IDS.family),
+            ("いまさひろ", "居正広", IDS.given),
+        ]);
+        let name = parts.split("なかいまさひろ", "中居正広").unwrap();
+        assert_eq!(name.family, ("なか", "中"));
+        assert!(name.family_known && name.given_known);
+
+        assert!(parts.split("やまだたろう", "山田太郎").is_none());
+    }
+
+    #[test]
+    fn split_full_names_adds_unknown_parts() {
+        let parts = parts(&[
+            ("なかい", "中居", IDS.family),
+            ("きむら", "木村", IDS.family),
+            ("たくや", "拓哉", IDS.given),
+            ("あかし", "明石", IDS.family),
+        ]);
+        let entries = vec![
+            entry("なかいまさひろ", "中居正広", IDS.full),
+            entry("きむらたくや", "木村拓哉", IDS.full),
+            entry("あかしやさんま", "明石家さんま", IDS.full),
+            entry("あすか", "Asu", IDS.full),
+            entry("なかいまさひろ", "中居正広", OTHER),
+        ];
+        let result = split_full_names(entries, IDS.full, &parts, &IDS);
+        assert_eq!(words(&result), [
+            // 未知の名だけを加え、分け方が確かでないフルネームは残さない
+            ("まさひろ", "正広", IDS.given),
+            // 姓と名が既知のフルネームは残す
+            ("きむらたくや", "木村拓哉", IDS.full),
+            // 家さんま は名らしくないので分けない
+            ("あかしやさんま", "明石家さんま", IDS.full),
+            ("あすか", "Asu", IDS.full),
+            ("なかいまさひろ", "中居正広", OTHER),
+        ]);
+    }
+}
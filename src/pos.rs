@@ -0,0 +1,210 @@
+// Parts of speech of Mozc defined in id.def file.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use super::error::{Error, Result, WithPath};
+
+
+/// File which defines ids of parts of speech, extracted from the source of Mozc.
+pub const ID_DEF_NAME: &str = "id.def";
+
+/// Number of near matches listed when a part of speech is not found.
+const MAX_NEAR_MATCHES: usize = 5;
+
+/// Part of speech of Mozc, which is used as left and right ids of entries.
+///
+/// In id.def, it is written as `id 品詞,細分類1,細分類2,細分類3,活用型,活用形,語彙`
+/// and unused fields are `*`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Pos {
+    pub id: u16,
+    /// 品詞
+    pub pos: String,
+    /// 細分類1
+    pub subcategory1: String,
+    /// 細分類2
+    pub subcategory2: String,
+    /// 細分類3
+    pub subcategory3: String,
+    /// 活用型
+    pub conjugation_type: String,
+    /// 活用形
+    pub conjugation_form: String,
+    /// 語彙
+    pub lexeme: String,
+}
+
+impl Pos {
+    /// Parses a line of id.def, returns None if the line is malformed.
+    pub fn parse_line(line: &str) -> Option<Pos> {
+        let (id, fields) = line.trim_end().split_once(' ')?;
+        let fields = fields.split(',').collect::<Vec<&str>>();
+        if let [pos, subcategory1, subcategory2, subcategory3, conjugation_type, conjugation_form, lexeme] = fields[..] {
+            Some(Pos {
+                id: id.parse().ok()?,
+                pos: String::from(pos),
+                subcategory1: String::from(subcategory1),
+                subcategory2: String::from(subcategory2),
+                subcategory3: String::from(subcategory3),
+                conjugation_type: String::from(conjugation_type),
+                conjugation_form: String::from(conjugation_form),
+                lexeme: String::from(lexeme),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns fields in the order of id.def.
+    pub fn fields(&self) -> [&str; 7] {
+        [&self.pos, &self.subcategory1, &self.subcategory2, &self.subcategory3,
+         &self.conjugation_type, &self.conjugation_form, &self.lexeme]
+    }
+
+    /// Returns true if leading fields are the specified ones and the rest are `*`.
+    pub fn is(&self, fields: &[&str]) -> bool {
+        fields.len() <= 7 && self.fields().iter().enumerate().all(|(i, field)| *field == fields.get(i).copied().unwrap_or("*"))
+    }
+}
+
+impl fmt::Display for Pos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.fields().join(","))
+    }
+}
+
+/// Parts of speech read from id.def, looked up by id or by fields.
+#[derive(Clone, Debug, Default)]
+pub struct PosTable {
+    entries: Vec<Pos>,
+}
+
+impl PosTable {
+    /// Reads id.def from the reader.
+    pub fn read_from<R: BufRead>(reader: R, file: &str) -> Result<PosTable> {
+        let mut entries = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            entries.push(Pos::parse_line(&line).ok_or_else(|| Error::Parse {
+                source: "mozc",
+                file: String::from(file),
+                line: index + 1,
+                record: line.clone(),
+                reason: String::from("not id and 7 fields of part of speech"),
+            })?);
+        }
+        entries.sort_by_key(|pos| pos.id);
+        Ok(PosTable { entries })
+    }
+
+    /// Reads id.def file.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<PosTable> {
+        let path = path.as_ref();
+        PosTable::read_from(BufReader::new(File::open(path).with_path(path)?), &path.to_string_lossy()).with_path(path)
+    }
+
+    /// Returns all parts of speech in the order of ids.
+    pub fn iter(&self) -> impl Iterator<Item = &Pos> {
+        self.entries.iter()
+    }
+
+    /// Returns part of speech of the id.
+    pub fn get(&self, id: u16) -> Option<&Pos> {
+        self.entries.binary_search_by_key(&id, |pos| pos.id).ok().map(|index| &self.entries[index])
+    }
+
+    /// Returns parts of speech which satisfy the condition, such as
+    /// `table.find(|pos| pos.pos == "名詞" && pos.subcategory1 == "固有名詞")`.
+    pub fn find<'a, F: Fn(&Pos) -> bool + 'a>(&'a self, condition: F) -> impl Iterator<Item = &'a Pos> + 'a {
+        self.entries.iter().filter(move |pos| condition(pos))
+    }
+
+    /// Returns id of the part of speech written as comma separated fields
+    /// such as `名詞,固有名詞,一般`, omitted trailing fields are `*`.
+    /// The error lists near matches if it is not found.
+    pub fn id(&self, fields: &str) -> Result<u16> {
+        let fields = fields.split(',').collect::<Vec<&str>>();
+        if let Some(pos) = self.entries.iter().find(|pos| pos.is(&fields)) {
+            return Ok(pos.id);
+        }
+
+        // 先頭から一致するフィールドが多い順に候補を挙げる
+        let common = |pos: &Pos| pos.fields().iter().zip(&fields).take_while(|(a, b)| a == b).count();
+        let mut near = self.entries.iter().map(|pos| (common(pos), pos)).filter(|(count, _)| *count > 0).collect::<Vec<_>>();
+        near.sort_by_key(|(count, pos)| (std::cmp::Reverse(*count), pos.id));
+        let near = near.iter().take(MAX_NEAR_MATCHES).map(|(_, pos)| format!("{} {}", pos.id, pos)).collect::<Vec<String>>();
+        Err(Error::other(if near.is_empty() {
+            format!("{} is not found in {}", fields.join(","), ID_DEF_NAME)
+        } else {
+            format!("{} is not found in {}, near matches: {}", fields.join(","), ID_DEF_NAME, near.join(", "))
+        }))
+    }
+}
+
+/// Table loaded by `pos_table` with the modified time of id.def.
+static LOADED: Mutex<Option<(Option<SystemTime>, Arc<PosTable>)>> = Mutex::new(None);
+
+/// Returns table of id.def in the working directory shared by converters.
+/// The file is read once, and read again only if it is modified.
+pub fn pos_table() -> Result<Arc<PosTable>> {
+    let modified = std::fs::metadata(ID_DEF_NAME).with_path(ID_DEF_NAME)?.modified().ok();
+    let mut loaded = LOADED.lock().unwrap();
+    if let Some((time, table)) = loaded.as_ref() {
+        if modified.is_some() && *time == modified {
+            return Ok(Arc::clone(table));
+        }
+    }
+    let table = Arc::new(PosTable::read(ID_DEF_NAME)?);
+    *loaded = Some((modified, Arc::clone(&table)));
+    Ok(table)
+}
+
+/// Mapping from parts of speech of a source dictionary to Mozc ids.
+///
+/// Rules are pairs of comma separated fields of the source and of id.def,
+/// such as `("名詞,普通名詞,サ変可能", "名詞,サ変接続")`. The rule which
+/// matches the most leading fields of the source is used, and parts of
+/// speech without rules get the default id.
+#[derive(Clone, Debug)]
+pub struct PosMap {
+    rules: Vec<(Vec<String>, u16)>,
+    default: u16,
+}
+
+impl PosMap {
+    /// Resolves ids of the rules and the default part of speech in the table.
+    pub fn new(table: &PosTable, rules: &[(&str, &str)], default: &str) -> Result<PosMap> {
+        let mut resolved = Vec::with_capacity(rules.len());
+        for (source, mozc) in rules {
+            resolved.push((source.split(',').map(String::from).collect(), table.id(mozc)?));
+        }
+        Ok(PosMap {
+            rules: resolved,
+            default: table.id(default)?,
+        })
+    }
+
+    /// Creates mapping which tags all entries with the id.
+    pub fn with_id(id: u16) -> PosMap {
+        PosMap {
+            rules: Vec::new(),
+            default: id,
+        }
+    }
+
+    /// Returns id for fields of the part of speech in the source.
+    pub fn id(&self, fields: &[&str]) -> u16 {
+        self.rules.iter()
+            .filter(|(rule, _)| rule.len() <= fields.len() && rule.iter().zip(fields).all(|(a, b)| a == b))
+            .max_by_key(|(rule, _)| rule.len())
+            .map_or(self.default, |(_, id)| *id)
+    }
+}
@@ -1,26 +1,33 @@
 
 // Ported from convert_skkdic_to_mozcdic.rb file.
 
-use std::io::{BufWriter, Write};
+use std::io::Read;
 use std::fs::File;
+use std::path::PathBuf;
 
 use encoding_rs::*;
-use rayon::prelude::*;
 
-use super::mozc::get_id;
+use super::archive::*;
+use super::entry::{sort_dedup, DictEntry};
+use super::error::{Result, SkippedLines, WithPath};
+use super::license::License;
+use super::pos::pos_table;
+use super::fetch::Fetcher;
+use super::source::Source;
+use super::stats::SourceStats;
 use super::util::*;
 
 
-fn convert_skkdic_to_mozcdic(filename: &str, dicname: &str) -> std::io::Result<()> {
-    // Mozcの品詞IDを取得
-    // 「名詞,固有名詞,人名,一般,*,*」は優先度が低いので使わない。
-    // 「名詞,固有名詞,一般,*,*,*」は後でフィルタリングする。
-    let id = get_id(r"(\d*) 名詞,一般,\*,\*,\*,\*,\*")?;
+const NAME: &str = "skkdic";
 
-    let buf = read_file_vec(filename)?;
+/// Parses EUC-JP encoded SKK dictionary read from the reader.
+/// Entries are tagged with the specified Mozc id, skipped records are counted in `skipped`.
+pub fn parse_skkdic<R: Read>(mut reader: R, id: u16, skipped: &mut SkippedLines) -> Result<Vec<DictEntry>> {
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
     let mut l2 = Vec::new();
     let (cow, _encoding_used, _had_errors) = EUC_JP.decode(&buf);
-    for line in cow.lines() {
+    for (line_no, line) in cow.lines().enumerate() {
         // わりふr /割り振/割振/
         // いずみ /泉/和泉;地名,大阪/出水;地名,鹿児島/
         if let Some((yomi, hyoukis)) = line.split_once(" /") {
@@ -28,6 +35,7 @@ fn convert_skkdic_to_mozcdic(filename: &str, dicname: &str) -> std::io::Result<(
 
             // 読みが英数字を含む場合はスキップ
             if yomi.len() != yomi.chars().count() * 3 {
+                skipped.rule("reading contains alphanumerics", line_no + 1, line);
                 continue;
             }
 
@@ -35,7 +43,7 @@ fn convert_skkdic_to_mozcdic(filename: &str, dicname: &str) -> std::io::Result<(
             let mut hyouki: Vec<String> = hyoukis.split_terminator('/').map(String::from).collect();
             let count = hyouki.len();
             for i in 0..count {
-                if hyouki[i].len() == 0 {
+                if hyouki[i].is_empty() {
                     continue;
                 }
 
@@ -44,7 +52,7 @@ fn convert_skkdic_to_mozcdic(filename: &str, dicname: &str) -> std::io::Result<(
                 }
 
                 // 表記に優先度をつける
-                let cost = 7000 + (10 * i);
+                let cost = 7000 + (10 * i as i32);
 
                 // 2個目以降の表記が前のものと重複している場合はスキップ
                 // ＩＣカード/ICカード/
@@ -52,10 +60,12 @@ fn convert_skkdic_to_mozcdic(filename: &str, dicname: &str) -> std::io::Result<(
                 if let Some(last) = &last_normalized {
                     if let Some(current) = &current_normalized {
                         if last == current {
+                            skipped.rule("duplicated surface", line_no + 1, line);
                             continue;
                         }
                     } else {
                         if last == &hyouki[i] {
+                            skipped.rule("duplicated surface", line_no + 1, line);
                             continue;
                         }
                     }
@@ -63,10 +73,12 @@ fn convert_skkdic_to_mozcdic(filename: &str, dicname: &str) -> std::io::Result<(
                     if i > 0 {
                         if let Some(current) = &current_normalized {
                             if &hyouki[i - 1] == current {
+                                skipped.rule("duplicated surface", line_no + 1, line);
                                 continue;
                             }
                         } else {
-                            if &hyouki[i - 1] == &hyouki[i] {
+                            if hyouki[i - 1] == hyouki[i] {
+                                skipped.rule("duplicated surface", line_no + 1, line);
                                 continue;
                             }
                         }
@@ -74,34 +86,58 @@ fn convert_skkdic_to_mozcdic(filename: &str, dicname: &str) -> std::io::Result<(
                 }
                 last_normalized = current_normalized;
 
-                l2.push(format!("{}\t{}\t{}\t{}\t{}\n", yomi, id, id, cost, hyouki[i]));
+                l2.push(DictEntry::new(yomi.clone(), id, cost, hyouki[i].clone(), NAME).with_record(line_no + 1));
             }
         } else {
+            skipped.rule("comment or no candidates", line_no + 1, line);
             continue;
         }
     }
 
-    let mut lines = l2;
+    Ok(l2)
+}
 
-    // 重複行を削除
-    lines.par_sort_unstable();
-    lines.dedup();
+/// Converts SKK-JISYO.L dictionary.
+#[derive(Debug, Default)]
+pub struct SkkDic;
 
-    let f = File::create(dicname)?;
-    let mut writer = BufWriter::new(f);
-    for line in lines {
-        writer.write(line.as_bytes())?;
+impl Source for SkkDic {
+    fn name(&self) -> &'static str {
+        NAME
     }
 
-    Ok(())
-}
+    fn license(&self) -> License {
+        License::Gpl2
+    }
+
+    fn fetch(&mut self, fetcher: &Fetcher) -> Result<()> {
+        let gz_path = fetcher.fetch("http://openlab.jp/skk/dic/SKK-JISYO.L.gz", "SKK-JISYO.L.gz", false)?;
+        gunzip(&gz_path, "SKK-JISYO.L")?;
+
+        Ok(())
+    }
 
-pub fn run_convert_skkdic_to_mozcdic() -> std::io::Result<()> {
-    command_wait("wget", vec!["-N", "-q", "http://openlab.jp/skk/dic/SKK-JISYO.L.gz"])?;
-    command_wait("rm", vec!["-f", "SKK-JISYO.L"])?;
-    command_wait("gzip", vec!["-dk", "SKK-JISYO.L.gz"])?;
+    fn inputs(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from("SKK-JISYO.L")]
+    }
 
-    convert_skkdic_to_mozcdic("SKK-JISYO.L", "mozcdic-ut-skkdic.txt")?;
+    fn parse(&self, stats: &mut SourceStats) -> Result<Vec<DictEntry>> {
+        // Mozcの品詞IDを取得
+        // 「名詞,固有名詞,人名,一般,*,*」は優先度が低いので使わない。
+        // 「名詞,固有名詞,一般,*,*,*」は後でフィルタリングする。
+        let id = pos_table()?.id("名詞,一般")?;
+
+        let mut skipped = stats.skipped_lines(NAME, "SKK-JISYO.L");
+        let entries = parse_skkdic(File::open("SKK-JISYO.L").with_path("SKK-JISYO.L")?, id, &mut skipped).with_path("SKK-JISYO.L")?;
+        skipped.report();
+        stats.add_skipped(&skipped);
+        Ok(entries)
+    }
 
-    Ok(())
+    fn convert(&self, mut entries: Vec<DictEntry>) -> Result<Vec<DictEntry>> {
+        // 重複行を削除
+        sort_dedup(&mut entries);
+
+        Ok(entries)
+    }
 }
@@ -0,0 +1,109 @@
+// Common interface implemented by every dictionary converter.
+
+use std::path::PathBuf;
+
+use super::alt_cannadic::AltCannadic;
+use super::cache::{cache_key, BuildCache};
+use super::chimei::Chimei;
+use super::edict2::Edict2;
+use super::entry::DictEntry;
+use super::error::Result;
+use super::fetch::Fetcher;
+use super::jawiki_article::JawikiArticles;
+use super::jinmei_ut::JinmeiUt;
+use super::license::License;
+use super::neologd::Neologd;
+use super::skkdic::SkkDic;
+use super::stats::SourceStats;
+use super::sudachidict::SudachiDict;
+
+
+/// Source of dictionary entries.
+pub trait Source {
+    /// Returns name of the source, which is used to tag entries and
+    /// to name the output file as mozcdic-ut-NAME.txt.
+    fn name(&self) -> &'static str;
+
+    /// Returns license of entries made by the source.
+    fn license(&self) -> License;
+
+    /// Downloads or looks up files with the fetcher, and extracts them.
+    fn fetch(&mut self, fetcher: &Fetcher) -> Result<()>;
+
+    /// Returns files read by `parse`, their contents are a part of the cache key.
+    fn inputs(&self) -> Vec<PathBuf>;
+
+    /// Returns revision of the converter, which must be incremented when
+    /// `parse` or `convert` makes different entries from the same files.
+    fn version(&self) -> u32 {
+        1
+    }
+
+    /// Reads fetched files and returns entries found in them.
+    /// Malformed lines and records skipped by rules are counted in `stats`.
+    fn parse(&self, stats: &mut SourceStats) -> Result<Vec<DictEntry>>;
+
+    /// Converts parsed entries into the dictionary entries,
+    /// such as adjusting costs and removing duplicates.
+    fn convert(&self, entries: Vec<DictEntry>) -> Result<Vec<DictEntry>>;
+
+    /// Fetches, parses and converts, entries get the license of the source.
+    /// Numbers of parsed and converted entries are counted in `stats`,
+    /// and entries matching to its query are explained.
+    /// Errors are tagged with the name of the source.
+    fn run(&mut self, fetcher: &Fetcher, stats: &mut SourceStats) -> Result<Vec<DictEntry>> {
+        self.fetch(fetcher).map_err(|e| e.in_source(self.name()))?;
+        self.build(stats)
+    }
+
+    /// Runs like `run`, but entries are reused from the cache if the inputs,
+    /// the converter version and `settings` are the same as the cached run.
+    /// The cache is not used when entries are explained.
+    fn run_cached(&mut self, fetcher: &Fetcher, cache: &BuildCache, settings: &str, stats: &mut SourceStats) -> Result<Vec<DictEntry>> {
+        let name = self.name();
+        self.fetch(fetcher).map_err(|e| e.in_source(name))?;
+        if stats.query.is_some() {
+            return self.build(stats);
+        }
+
+        let key = cache_key(name, self.version(), &self.inputs(), settings).map_err(|e| e.in_source(name))?;
+        if let Some(entries) = cache.load(name, &key, stats)? {
+            println!("{}: {} entries are read from the cache.", name, entries.len());
+            return Ok(entries);
+        }
+        let entries = self.build(stats)?;
+        cache.store(name, &key, &entries, stats)?;
+        Ok(entries)
+    }
+
+    /// Parses fetched files and converts entries.
+    fn build(&self, stats: &mut SourceStats) -> Result<Vec<DictEntry>> {
+        let name = self.name();
+        let entries = self.parse(stats).map_err(|e| e.in_source(name))?;
+        stats.parsed += entries.len();
+        entries.iter().for_each(|entry| stats.explain(entry, "parsed"));
+        let mut entries = self.convert(entries).map_err(|e| e.in_source(name))?;
+        stats.converted += entries.len();
+        entries.iter().for_each(|entry| stats.explain(entry, "converted"));
+        let license = self.license();
+        for entry in &mut entries {
+            entry.license = Some(license);
+        }
+        Ok(entries)
+    }
+}
+
+/// Returns source of dictionary entries specified by command name.
+pub fn source_from_command(command: &str) -> Option<Box<dyn Source>> {
+    Some(match command {
+        "altcannadic" => Box::new(AltCannadic),
+        "chimei" => Box::new(Chimei),
+        "edict2" => Box::new(Edict2),
+        "jawikiarticles" => Box::new(JawikiArticles::default()),
+        "jinmeiut" => Box::new(JinmeiUt),
+        "neologd" => Box::new(Neologd::default()),
+        "skkdic" => Box::new(SkkDic),
+        "sudachidict" | "sudachidic" => Box::new(SudachiDict::default()),
+        _ => return None,
+    })
+}
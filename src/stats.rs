@@ -0,0 +1,263 @@
+// Statistics of entries counted through the build.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use serde::Serialize;
+
+use super::entry::DictEntry;
+use super::error::{Error, Result, SkippedLines, WithPath};
+
+
+/// Width of cost ranges in histograms.
+pub const COST_BUCKET_WIDTH: i32 = 500;
+
+/// Reason why an entry is removed after its source converted it.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropReason {
+    /// Surface is listed in the unsuitable entries filter.
+    Unsuitable,
+    /// Rejected by rules of `remove_unnecessary_entries`, such as a short reading.
+    Unnecessary,
+    /// Mozc OSS dictionary has the same reading, surface and id.
+    MozcDuplicate,
+    /// Another UT entry has the same reading and surface.
+    UtDuplicate,
+    /// Surface consists of ASCII only and does not hit any jawiki titles.
+    NoTitle,
+}
+
+impl DropReason {
+    /// All reasons in the order of the build.
+    pub const ALL: [DropReason; 5] = [
+        DropReason::Unsuitable, DropReason::Unnecessary, DropReason::MozcDuplicate,
+        DropReason::UtDuplicate, DropReason::NoTitle,
+    ];
+
+    /// Returns name of the reason.
+    pub fn name(self) -> &'static str {
+        match self {
+            DropReason::Unsuitable => "unsuitable",
+            DropReason::Unnecessary => "unnecessary",
+            DropReason::MozcDuplicate => "mozc_duplicate",
+            DropReason::UtDuplicate => "ut_duplicate",
+            DropReason::NoTitle => "no_title",
+        }
+    }
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Reading and surface of entries whose decisions are printed by explain mode.
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+    /// Reading in hiragana, any reading if None.
+    pub reading: Option<String>,
+    /// Surface form, any surface if None.
+    pub surface: Option<String>,
+}
+
+impl Query {
+    /// Returns true if the entry has the reading and the surface.
+    pub fn matches(&self, entry: &DictEntry) -> bool {
+        self.reading.as_ref().map_or(true, |reading| reading == &entry.reading) &&
+            self.surface.as_ref().map_or(true, |surface| surface == &entry.surface)
+    }
+
+    /// Returns true if the record read by a converter contains the surface,
+    /// or the reading if the surface is not specified.
+    pub fn matches_record(&self, record: &str) -> bool {
+        match (&self.surface, &self.reading) {
+            (Some(surface), _) => record.contains(surface.as_str()),
+            (None, Some(reading)) => record.contains(reading.as_str()),
+            (None, None) => false,
+        }
+    }
+}
+
+/// Prints the decision made for the entry in explain mode.
+pub fn explain(entry: &DictEntry, message: &str) {
+    println!("explain: {}\t{}\t{}\t{}\t{}: {}", entry.reading, entry.left_id, entry.right_id, entry.cost, entry.surface, message);
+    let origins = entry.origins().map(|origin| origin.to_string()).collect::<Vec<String>>();
+    println!("explain:     from {}", origins.join(" "));
+}
+
+/// Counts of entries of a source at each stage of the build.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SourceStats {
+    /// Entries returned by the parser.
+    pub parsed: usize,
+    /// Malformed lines skipped by the parser.
+    pub malformed: usize,
+    /// Records skipped by each rule of the converter.
+    pub skipped: BTreeMap<&'static str, usize>,
+    /// Entries left after the converter, such as removing duplicates in the source.
+    pub converted: usize,
+    /// Entries removed by each reason after converted.
+    pub dropped: BTreeMap<DropReason, usize>,
+    /// Entries written into the dictionary.
+    pub output: usize,
+    /// Number of written entries for each cost range, keyed by its lower bound.
+    pub costs: BTreeMap<i32, usize>,
+    /// Entries to explain, nothing is explained if None.
+    #[serde(skip)]
+    pub query: Option<Query>,
+}
+
+impl SourceStats {
+    /// Creates counter of malformed lines and skipped records which explains
+    /// records matching to the query.
+    pub fn skipped_lines(&self, source: &'static str, file: &str) -> SkippedLines {
+        SkippedLines::new(source, file).with_query(self.query.clone())
+    }
+
+    /// Prints the decision if the entry matches to the query.
+    pub fn explain(&self, entry: &DictEntry, message: &str) {
+        if self.query.as_ref().is_some_and(|query| query.matches(entry)) {
+            explain(entry, message);
+        }
+    }
+
+    /// Adds malformed lines and records skipped by rules.
+    pub fn add_skipped(&mut self, skipped: &SkippedLines) {
+        self.malformed += skipped.count();
+        for (rule, count) in skipped.rules() {
+            *self.skipped.entry(rule).or_insert(0) += count;
+        }
+    }
+
+    fn add(&mut self, other: &SourceStats) {
+        self.parsed += other.parsed;
+        self.malformed += other.malformed;
+        for (rule, count) in &other.skipped {
+            *self.skipped.entry(rule).or_insert(0) += count;
+        }
+        self.converted += other.converted;
+        for (reason, count) in &other.dropped {
+            *self.dropped.entry(*reason).or_insert(0) += count;
+        }
+        self.output += other.output;
+        for (cost, count) in &other.costs {
+            *self.costs.entry(*cost).or_insert(0) += count;
+        }
+    }
+}
+
+/// Statistics of the build for each source.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct BuildStats {
+    /// Statistics keyed by source names which tag entries.
+    pub sources: BTreeMap<&'static str, SourceStats>,
+    /// Sum of all sources.
+    pub total: SourceStats,
+    /// Entries to explain, nothing is explained if None.
+    #[serde(skip)]
+    pub query: Option<Query>,
+}
+
+impl BuildStats {
+    /// Creates statistics which explain entries matching to the query.
+    pub fn with_query(query: Query) -> BuildStats {
+        BuildStats {
+            query: Some(query),
+            ..BuildStats::default()
+        }
+    }
+
+    /// Returns statistics of the source.
+    pub fn source(&mut self, name: &'static str) -> &mut SourceStats {
+        let query = &self.query;
+        self.sources.entry(name).or_insert_with(|| SourceStats {
+            query: query.clone(),
+            ..SourceStats::default()
+        })
+    }
+
+    /// Returns true if the entry matches to the query.
+    pub fn explains(&self, entry: &DictEntry) -> bool {
+        self.query.as_ref().is_some_and(|query| query.matches(entry))
+    }
+
+    /// Prints the decision if the entry matches to the query.
+    pub fn explain(&self, entry: &DictEntry, message: &str) {
+        if self.explains(entry) {
+            explain(entry, message);
+        }
+    }
+
+    /// Counts the entry removed by the reason, `rule` tells details of the reason.
+    pub fn drop(&mut self, entry: &DictEntry, reason: DropReason, rule: &str) {
+        *self.source(entry.source).dropped.entry(reason).or_insert(0) += 1;
+        if self.explains(entry) {
+            explain(entry, &format!("dropped as {}: {}", reason, rule));
+        }
+    }
+
+    /// Counts the entry written into the dictionary.
+    pub fn add_output(&mut self, entry: &DictEntry) {
+        let stats = self.source(entry.source);
+        stats.output += 1;
+        let bucket = entry.cost.div_euclid(COST_BUCKET_WIDTH) * COST_BUCKET_WIDTH;
+        *stats.costs.entry(bucket).or_insert(0) += 1;
+    }
+
+    /// Sums up all sources after entries are written.
+    pub fn finish(&mut self) {
+        let mut total = SourceStats::default();
+        for stats in self.sources.values() {
+            total.add(stats);
+        }
+        self.total = total;
+    }
+
+    /// Writes statistics in JSON into the file.
+    pub fn write_json(&self, filename: &str) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(filename).with_path(filename)?);
+        serde_json::to_writer_pretty(&mut writer, self).map_err(|e| Error::other(format!("{}: {}", filename, e)))?;
+        writer.write_all(b"\n").with_path(filename)?;
+        writer.flush().with_path(filename)
+    }
+}
+
+impl fmt::Display for BuildStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows = self.sources.iter().map(|(name, stats)| (*name, stats))
+            .chain(std::iter::once(("total", &self.total)));
+        write!(f, "{:<14} {:>9} {:>9} {:>9} {:>9}", "source", "parsed", "malformed", "skipped", "converted")?;
+        for reason in DropReason::ALL.iter() {
+            write!(f, " {:>14}", reason.name())?;
+        }
+        writeln!(f, " {:>9}", "output")?;
+        for (name, stats) in rows.clone() {
+            write!(f, "{:<14} {:>9} {:>9} {:>9} {:>9}", name, stats.parsed, stats.malformed,
+                stats.skipped.values().sum::<usize>(), stats.converted)?;
+            for reason in DropReason::ALL.iter() {
+                write!(f, " {:>14}", stats.dropped.get(reason).copied().unwrap_or(0))?;
+            }
+            writeln!(f, " {:>9}", stats.output)?;
+        }
+
+        for (name, stats) in rows {
+            if name != "total" && !stats.skipped.is_empty() {
+                writeln!(f, "\nskipped in {}:", name)?;
+                for (rule, count) in &stats.skipped {
+                    writeln!(f, "  {:<40} {:>9}", rule, count)?;
+                }
+            }
+            if !stats.costs.is_empty() {
+                writeln!(f, "\ncosts of {}:", name)?;
+                for (cost, count) in &stats.costs {
+                    writeln!(f, "  {:>5}-{:<5} {:>9}", cost, cost + COST_BUCKET_WIDTH - 1, count)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
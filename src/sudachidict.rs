@@ -1,32 +1,90 @@
 
 // Ported from convert_sudachidict_to_mozcdic.rb file.
 
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{BufRead, BufReader};
 use std::fs::File;
-
-use rayon::prelude::*;
-
-use super::mozc::get_id;
+use std::path::PathBuf;
+
+use super::conjugation::{adjective_conjugation, Inflector};
+use super::entry::*;
+use super::error::{Error, Result, SkippedLines, WithPath};
+use super::license::License;
+use super::mozc::MOZCDIC_NAME;
+use super::person_name::{split_full_names, NameIds, NameParts};
+use super::pos::{pos_table, PosMap, PosTable};
+use super::fetch::Fetcher;
+use super::source::Source;
+use super::stats::SourceStats;
 use super::util::*;
 
 
-#[derive(Clone, Debug)]
-struct Entry {
-    key: String,
-    yomi: String,
-    hyouki: String,
-    cost: String,
+const NAME: &str = "sudachidict";
+
+/// Parts of speech of SudachiDict, which follows UniDic, and their Mozc
+/// parts of speech. Others are 名詞,固有名詞,一般.
+const POS_RULES: &[(&str, &str)] = &[
+    ("名詞,固有名詞,一般", "名詞,固有名詞,一般"),
+    ("名詞,固有名詞,人名,一般", "名詞,固有名詞,人名,一般"),
+    ("名詞,固有名詞,人名,姓", "名詞,固有名詞,人名,姓"),
+    ("名詞,固有名詞,人名,名", "名詞,固有名詞,人名,名"),
+    ("名詞,普通名詞,一般", "名詞,一般"),
+    ("名詞,普通名詞,サ変可能", "名詞,サ変接続"),
+    ("名詞,普通名詞,サ変形状詞可能", "名詞,サ変接続"),
+    ("名詞,普通名詞,形状詞可能", "名詞,形容動詞語幹"),
+    ("名詞,普通名詞,副詞可能", "名詞,副詞可能"),
+    ("名詞,普通名詞,助数詞可能", "名詞,一般"),
+    ("名詞,数詞", "名詞,数"),
+];
+
+/// Returns mapping from parts of speech of SudachiDict to ids in the table.
+pub fn pos_map(table: &PosTable) -> Result<PosMap> {
+    PosMap::new(table, POS_RULES, "名詞,固有名詞,一般")
+}
+
+/// Conjugation types of SudachiDict and their IPADIC types. Types of
+/// SudachiDict are matched without the row after `-` if not listed.
+const CONJUGATION_RULES: &[(&str, &str)] = &[
+    ("五段-カ行", "五段・カ行イ音便"),
+    ("五段-ガ行", "五段・ガ行"),
+    ("五段-サ行", "五段・サ行"),
+    ("五段-タ行", "五段・タ行"),
+    ("五段-ナ行", "五段・ナ行"),
+    ("五段-バ行", "五段・バ行"),
+    ("五段-マ行", "五段・マ行"),
+    ("五段-ラ行", "五段・ラ行"),
+    ("五段-ワア行", "五段・ワ行促音便"),
+    ("上一段", "一段"),
+    ("下一段", "一段"),
+];
+
+/// Returns IPADIC conjugation type of the SudachiDict type and the reading.
+fn ipadic_conjugation(conjugation: &str, yomi: &str) -> Option<&'static str> {
+    if conjugation == "形容詞" {
+        return Some(adjective_conjugation(yomi));
+    }
+    // 「行く」はIPADICでは促音便
+    if conjugation == "五段-カ行" && (yomi.ends_with("いく") || yomi.ends_with("ゆく")) {
+        return Some("五段・カ行促音便");
+    }
+    let group = conjugation.split('-').next().unwrap_or_default();
+    CONJUGATION_RULES.iter()
+        .find(|(sudachi, _)| *sudachi == conjugation || *sudachi == group)
+        .map(|(_, ipadic)| *ipadic)
 }
 
-fn convert_sudachidict_to_mozcdic(filename: &str, dicname: &str) -> std::io::Result<()> {
+/// Parses lexicon CSV of SudachiDict read from the reader.
+/// Entries are tagged with Mozc ids mapped from their parts of speech,
+/// Verbs and adjectives are expanded into conjugated forms by `inflector`.
+/// Malformed lines and skipped records are counted in `skipped`.
+pub fn parse_sudachidict<R: BufRead>(mut reader: R, pos: &PosMap, inflector: &Inflector, skipped: &mut SkippedLines) -> Result<Vec<DictEntry>> {
     // sudachidict のエントリから読みと表記を取得
-    
+
     let mut l2 = Vec::with_capacity(1024 * 1024);
 
-    let f = File::open(filename)?;
-    let mut reader = BufReader::new(f);
     let mut line = String::new();
-    while let Ok(1..) = reader.read_line(&mut line) {
+    let mut line_no = 0;
+    while reader.read_line(&mut line)? > 0 {
+        line_no += 1;
         // https://github.com/WorksApplications/Sudachi/blob/develop/docs/user_dict.md
         // 見出し (TRIE 用),左連接ID,右連接ID,コスト,見出し (解析結果表示用),\
         // 品詞1,品詞2,品詞3,品詞4,品詞 (活用型),品詞 (活用形),\
@@ -41,25 +99,30 @@ fn convert_sudachidict_to_mozcdic(filename: &str, dicname: &str) -> std::io::Res
         // アイアンマイケル,5144,4788,9652,アイアンマイケル,名詞,固有名詞,人名,一般,*,*,\
         // アイアンマイケル,アイアン・マイケル,*,C,*,*,*,*
 
-        // midashi, cost, hyouki, kind1, kind3, kind4, yomi
-        // 0,       3,    4,      5,     7,     8,     11
-        let mut ss = line.split(',');
-        let mut midashi = String::from(ss.next().unwrap()); // 0
-        ss.next(); // 1
-        ss.next(); // 2
-        let cost = String::from(ss.next().unwrap()); // 3
+        // midashi, cost, hyouki, kind1, kind3, katsuyougata, katsuyoukei, yomi
+        // 0,       3,    4,      5,     7,     9,            10,          11
+        let ss = line.split(',').collect::<Vec<&str>>();
+        if ss.len() < 12 {
+            skipped.skip(line_no, &line, "too few fields");
+            line.clear();
+            continue;
+        }
+        let mut midashi = String::from(ss[0]);
+        let cost = if let Ok(cost) = ss[3].parse::<i32>() {
+            cost
+        } else {
+            skipped.skip(line_no, &line, "invalid cost");
+            line.clear();
+            continue;
+        };
         // 「見出し (解析結果表示用)」を表記にする
-        let hyouki = String::from(ss.next().unwrap()); // 4
-        let kind1 = ss.next().unwrap(); // 5
-        ss.next(); // 6
-        let kind3 = ss.next().unwrap(); // 7
-        let kind4 = ss.next().unwrap(); // 8
-        ss.next(); // 9
-        ss.next(); // 10
+        let hyouki = String::from(ss[4]);
+        let kind1 = ss[5];
+        let kind3 = ss[7];
         // 「読み」を取得
-        let mut yomi = String::from(ss.next().unwrap()); // 11
+        let mut yomi = String::from(ss[11]);
         if yomi.find('＝').is_some() || yomi.find('・').is_some() {
-            yomi = yomi.replace('＝', "").replace('・', "");
+            yomi = yomi.replace(['＝', '・'], "");
         }
 
         // 読みのカタカナをひらがなに変換
@@ -68,125 +131,156 @@ fn convert_sudachidict_to_mozcdic(filename: &str, dicname: &str) -> std::io::Res
 
         // 読みがひらがな以外を含む場合はスキップ
         if yomi.chars()
-               .any(|c| !(('ぁ' <= c && c <= 'ゔ') || c == 'ー')) {
+               .any(|c| !(('ぁ'..='ゔ').contains(&c) || c == 'ー')) {
+            skipped.rule("reading is not hiragana", line_no, &line);
             line.clear();
             continue;
         }
 
         // 表記が英数字のみで、表記と「見出し (TRIE 用)」の downcase が同じ場合は表記に揃える
-        if hyouki.is_ascii() &&
-           hyouki.to_ascii_lowercase() == midashi.to_ascii_lowercase() {
+        if hyouki.is_ascii() && hyouki.eq_ignore_ascii_case(&midashi) {
             midashi.clear();
             midashi.push_str(&hyouki);
         }
 
+        // 動詞と形容詞は終止形から活用形を作る
+        if hyouki == midashi && (kind1 == "動詞" || kind1 == "形容詞") {
+            let conjugation = ipadic_conjugation(ss[9], &yomi);
+            let base = DictEntry::new(yomi, 0, cost, hyouki, NAME).with_record(line_no);
+            match conjugation.and_then(|conjugation| inflector.inflect(conjugation, &base)) {
+                Some(mut entries) if ss[10] == "終止形-一般" => l2.append(&mut entries),
+                _ => skipped.rule("conjugation is not supported", line_no, &line),
+            }
+            line.clear();
+            continue;
+        }
+
         // 表記が「見出し (TRIE 用)」と異なる場合はスキップ
         if hyouki != midashi ||
            // 名詞以外の場合はスキップ
            kind1 != "名詞" ||
            // 「地名」をスキップ。地名は郵便番号ファイルから生成する
-           kind3 == "地名" ||
-           // 「名」をスキップ
-           kind4 == "名" {
+           kind3 == "地名" {
+            skipped.rule("surface differs from headword or part of speech is not used", line_no, &line);
             line.clear();
             continue;
         }
 
+        // 品詞1から品詞4までをMozcの品詞IDに変換
+        let id = pos.id(&ss[5..9]);
+
         // [読み, 表記, コスト] の順に並べる
-        l2.push(Entry {
-            key: format!("{}\t{}\t{}", yomi, hyouki, cost),
-            yomi,
-            hyouki,
-            cost,
-        });
+        l2.push(DictEntry::new(yomi, id, cost, hyouki, NAME).with_record(line_no));
         line.clear();
     }
 
-    let mut lines = l2;
-    lines.par_sort_unstable_by(|a, b| a.key.cmp(&b.key));
-
-    // Mozcの品詞IDを取得
-    // 「名詞,固有名詞,人名,一般,*,*」は優先度が低いので使わない。
-    // 「名詞,固有名詞,一般,*,*,*」は後でフィルタリングする。
-    let id = get_id(r"(\d*) 名詞,固有名詞,一般,\*,\*,\*,\*")?;
-
-    // Mozc形式で書き出す
-    let f = File::create(dicname)?;
-    let mut writer = BufWriter::new(f);
+    Ok(l2)
+}
 
-    let count = lines.len();
+/// Removes duplicated entries and adjusts costs of parsed SudachiDict entries.
+pub fn convert_sudachidict_to_mozcdic(mut entries: Vec<DictEntry>) -> Vec<DictEntry> {
+    // [読み..表記] が重複する場合はスキップ
+    sort_dedup_by_reading_surface(&mut entries);
 
-    for i in 0..count {
-        let s1 = &lines[i];
+    for entry in entries.iter_mut() {
+        let mut cost = entry.cost;
 
-        if i > 0 {
-            let s2 = &lines[i - 1];
-            // [読み..表記] が重複する場合はスキップ
-            if s1.yomi == s2.yomi && s1.hyouki == s2.hyouki {
-                continue;
-            }
+        // コストがマイナスの場合は8000にする
+        if cost < 0 {
+            cost = 8000;
         }
 
-        let cost = {
-            let mut cost = i32::from_str_radix(&s1.cost, 10).unwrap();
+        // コストが10000を超える場合は10000にする
+        if cost > 10000 {
+            cost = 10000;
+        }
 
-            // コストがマイナスの場合は8000にする
-            if cost < 0 {
-                cost = 8000;
-            }
+        // コストを 6000 < cost < 7000 に調整する
+        entry.cost = 6000 + (cost / 10);
+    }
 
-            // コストが10000を超える場合は10000にする
-            if cost > 10000 {
-                cost = 10000;
-            }
+    entries
+}
 
-            // コストを 6000 < cost < 7000 に調整する
-            6000 + (cost / 10)
-        };
+/// Converts core and notcore lexicons of SudachiDict.
+#[derive(Debug, Default)]
+pub struct SudachiDict {
+    file_names: Vec<String>,
+}
 
-        // [読み,id,id,コスト,表記] の順に並べる
-        let t = format!("{}\t{}\t{}\t{}\t{}\n", &s1.yomi, id, id, cost, &s1.hyouki);
-        writer.write(t.as_bytes())?;
+impl Source for SudachiDict {
+    fn name(&self) -> &'static str {
+        NAME
     }
 
-    Ok(())
-}
+    fn license(&self) -> License {
+        License::Apache2
+    }
 
-pub fn run_convert_sudachidict_to_mozcdic() -> std::io::Result<()> {
-    const TARGET: &str = "/WorksApplications/SudachiDict/commit/";
+    fn fetch(&mut self, fetcher: &Fetcher) -> Result<()> {
+        const TARGET: &str = "/WorksApplications/SudachiDict/commit/";
+        const URL: &str = "https://github.com/WorksApplications/SudachiDict/raw/develop/src/main/text/";
 
-    // sudachidict ページからコミット情報を取得
-    let dictver = {
-        command_wait("wget", vec!["-q", "https://github.com/WorksApplications/SudachiDict/commits/develop/src/main/text/core_lex.csv", "-O", "sudachidict.html"])?;
-        let s = read_file("sudachidict.html")?;
-        if let Some((_prefix, suffix)) = s.split_once(TARGET) {
-            String::from(suffix.get(..7).unwrap())
+        // オフラインの場合はバージョンなしのファイル名を使う
+        // core_lex.csv, notcore_lex.csv
+        let dictver = if fetcher.is_offline() {
+            String::new()
         } else {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "sudachidic version not found"));
+            // sudachidict ページからコミット情報を取得
+            let s = fetcher.fetch_text("https://github.com/WorksApplications/SudachiDict/commits/develop/src/main/text/core_lex.csv")?;
+            if let Some((_prefix, suffix)) = s.split_once(TARGET) {
+                format!(".{}", suffix.get(..7).ok_or_else(|| Error::other("sudachidic version is broken"))?)
+            } else {
+                return Err(Error::other("sudachidic version not found"));
+            }
+        };
+
+        // ファイルをダウンロード
+        self.file_names.clear();
+        for (name, pattern) in [("core_lex", r"core_lex\..*"), ("notcore_lex", r"notcore_lex\..*")] {
+            let file_name = format!("{}{}.csv", name, dictver);
+            if !fetcher.is_offline() && File::open(&file_name).is_err() {
+                remove_matched(".", pattern)?;
+            }
+            let path = fetcher.fetch(&format!("{}{}.csv", URL, name), &file_name, true)?;
+            self.file_names.push(path.to_string_lossy().into_owned());
         }
-    };
-    command_wait("rm", vec!["-f", "sudachidict.html"])?;
-
-    // ファイルをダウンロード
-    let corelex = format!("core_lex.{}.csv", dictver);
-    let notcorelex = format!("notcore_lex.{}.csv", dictver);
-
-    if !File::open(&corelex).is_ok() {
-        remove_matched(".", r"core_lex\..*")?;
-        command_wait("wget", vec!["-nc", "-q", "https://github.com/WorksApplications/SudachiDict/raw/develop/src/main/text/core_lex.csv", "-O", &corelex])?;
-    } else {
-        println!("{} already exists.", &corelex);
+
+        Ok(())
     }
 
-    if !File::open(&notcorelex).is_ok() {
-        remove_matched(".", r"notcore_lex\..*")?;
-        command_wait("wget", vec!["-nc", "-q", "https://github.com/WorksApplications/SudachiDict/raw/develop/src/main/text/notcore_lex.csv", "-O", &notcorelex])?;
-    } else {
-        println!("{} already exists.", &notcorelex);
+    fn inputs(&self) -> Vec<PathBuf> {
+        self.file_names.iter().map(PathBuf::from).chain(std::iter::once(PathBuf::from(MOZCDIC_NAME))).collect()
     }
 
-    convert_sudachidict_to_mozcdic(&corelex, "mozcdic-ut-sudachidict-core.txt")?;
-    convert_sudachidict_to_mozcdic(&notcorelex, "mozcdic-ut-sudachidict-notcore.txt")?;
+    fn version(&self) -> u32 {
+        7
+    }
 
-    Ok(())
+    fn parse(&self, stats: &mut SourceStats) -> Result<Vec<DictEntry>> {
+        // Mozcの品詞IDを取得
+        // 固有名詞は後でフィルタリングする。
+        let table = pos_table()?;
+        let pos = pos_map(&table)?;
+        let inflector = Inflector::new(&table);
+
+        let mut entries = Vec::new();
+        for file_name in &self.file_names {
+            let mut skipped = stats.skipped_lines(NAME, file_name);
+            entries.append(&mut parse_sudachidict(BufReader::new(File::open(file_name).with_path(file_name)?), &pos, &inflector, &mut skipped)
+                .with_path(file_name)?);
+            skipped.report();
+            stats.add_skipped(&skipped);
+        }
+
+        // フルネームを姓と名に分ける
+        let ids = NameIds::new(&table)?;
+        let mut parts = NameParts::read_mozc(&ids)?;
+        parts.add_entries(&entries, &ids);
+        Ok(split_full_names(entries, ids.full, &parts, &ids))
+    }
+
+    fn convert(&self, entries: Vec<DictEntry>) -> Result<Vec<DictEntry>> {
+        Ok(convert_sudachidict_to_mozcdic(entries))
+    }
 }
@@ -1,12 +1,14 @@
 
 
-use std::process::Command;
 use std::io::{BufReader, Read};
 use std::fs::File;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use regex::*;
 
+use super::error::{Error, Result};
+
 
 /// Returns files matched to regex pattern in the specified directory.
 #[derive(Debug)]
@@ -31,16 +33,17 @@ impl Iterator for MatchedFiles {
     type Item = String;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(Ok(entry)) = self.read_dir.next() {
+        // 一致しないファイルは飛ばして次を探す
+        for entry in self.read_dir.by_ref().flatten() {
             let path = entry.path();
             if path.is_file() {
-                if let Some(name) = path.to_str() {
+                if let Some(name) = entry.file_name().to_str() {
                     if self.pattern.is_match(name) {
                         let mut path = PathBuf::from(&self.dirpath);
                         path.push(name);
                         return path.as_path()
                                    .to_str()
-                                   .map_or(None, |s| Some(String::from(s)));
+                                   .map(String::from);
                     }
                 }
             }
@@ -49,6 +52,22 @@ impl Iterator for MatchedFiles {
     }
 }
 
+/// Names interned by `intern`, they live until the process exits.
+static INTERNED: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+/// Returns the name which lives until the process exits, such as a source
+/// name read from a file. Each name is allocated only once.
+pub fn intern(name: &str) -> &'static str {
+    let mut names = INTERNED.lock().unwrap();
+    if let Some(interned) = names.iter().find(|interned| **interned == name) {
+        interned
+    } else {
+        let interned: &'static str = Box::leak(String::from(name).into_boxed_str());
+        names.push(interned);
+        interned
+    }
+}
+
 /// Removes files which match to regex pattern from specified directory.
 pub fn remove_matched(dirpath: &str, pattern: &str) -> std::io::Result<()> {
     for path in MatchedFiles::new(dirpath, pattern)? {
@@ -75,28 +94,51 @@ pub fn read_file_vec(filename: &str) -> std::io::Result<Vec<u8>> {
     Ok(buf)
 }
 
-/// Execute command with arguments and wait until finished.
-pub fn command_wait(cmd: &str, args: Vec<&str>) -> std::io::Result<()> {
-    if let Ok(mut child) = Command::new(cmd).args(args).spawn() {
-        child.wait()?;
+/// Removes the file, it is not an error if the file does not exist.
+pub fn remove_file_if_exists<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Removes the directory with its contents, it is not an error if the directory does not exist.
+pub fn remove_dir_if_exists<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
+    match std::fs::remove_dir_all(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
     }
-    Ok(())
 }
 
-/// Execute command with arguments and wait until finished, output is returned.
-pub fn command_wait_output(cmd: &str, args: Vec<&str>) -> std::io::Result<String> {
-    let output = Command::new(cmd).args(args).output()?;
-    Ok(unsafe { String::from_utf8_unchecked(output.stdout) })
+/// Returns today's date in UTC as YYYY-MM-DD.
+pub fn today() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    // 1970-01-01 からの日数を年月日に変換する
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
 }
 
 /// Returns number of cores in the CPU.
-pub fn get_core_count() -> std::io::Result<usize> {
+pub fn get_core_count() -> Result<usize> {
     let re = Regex::new(r"^cpu cores\s*: (\d*)").unwrap();
-    let info = command_wait_output("grep", vec!["cpu.cores", "/proc/cpuinfo"])?;
-    if let Some(captures) = re.captures(&info) {
-        Ok(usize::from_str_radix(captures.get(1).unwrap().as_str(), 10).unwrap() - 1)
+    let info = read_file("/proc/cpuinfo")?;
+    if let Some(cores) = info.lines()
+                             .find_map(|line| re.captures(line))
+                             .and_then(|captures| captures.get(1)?.as_str().parse::<usize>().ok()) {
+        Ok(cores.saturating_sub(1))
     } else {
-        Err(std::io::Error::new(std::io::ErrorKind::Other, "failed to obtain core count from cpuinfo"))
+        Err(Error::other("failed to obtain core count from cpuinfo"))
     }
 }
 
@@ -139,7 +181,7 @@ pub fn ascii_fullwidth_to_halfwidth(c: char) -> Option<char> {
 /// Converts from fullwidth ASCII to halfwidth ASCII character.
 pub fn ascii_to_halfwidth(s: &str) -> Option<String> {
     // No need to convert in most cases, so just check and convert if required.
-    if s.chars().any(|c| '！' <= c && c <= '～') {
+    if s.chars().any(|c| ('！'..='～').contains(&c)) {
         let mut ns = String::with_capacity(s.len());
         s.chars().for_each(|c| ns.push(ascii_fullwidth_to_halfwidth(c).unwrap_or(c)));
         Some(ns)
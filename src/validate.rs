@@ -0,0 +1,174 @@
+// Validation of the dictionary file before it is archived.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use regex::Regex;
+
+use super::error::{Error, Result, WithPath};
+
+
+/// Largest cost which Mozc accepts, costs are stored as 16 bit integers.
+pub const MAX_COST: i32 = i16::MAX as i32;
+
+/// Number of violations reported one by one for each rule.
+const MAX_REPORTED_LINES: usize = 10;
+
+/// Returns true if the character can be used in readings.
+/// 「ー」と踊り字はMozc辞書の読みでも使われる
+fn is_reading_char(c: char) -> bool {
+    ('ぁ'..='ゖ').contains(&c) || c == 'ゝ' || c == 'ゞ' || c == 'ー'
+}
+
+/// Counts of lines which violate each rule.
+#[derive(Clone, Debug, Default)]
+pub struct Violations {
+    /// Number of checked lines.
+    pub lines: usize,
+    /// Number of lines which violate each rule.
+    pub rules: BTreeMap<&'static str, usize>,
+}
+
+impl Violations {
+    /// Returns number of violations of all rules.
+    pub fn count(&self) -> usize {
+        self.rules.values().sum()
+    }
+
+    /// Counts the violation, the first few violations of each rule are reported.
+    fn add(&mut self, file: &str, line: usize, rule: &'static str, record: &str) {
+        let count = self.rules.entry(rule).or_insert(0);
+        if *count < MAX_REPORTED_LINES {
+            eprintln!("error: {}", Error::Parse {
+                source: "validate",
+                file: String::from(file),
+                line,
+                record: String::from(record),
+                reason: String::from(rule),
+            });
+        }
+        *count += 1;
+    }
+}
+
+impl fmt::Display for Violations {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} lines are checked, {} violations are found", self.lines, self.count())?;
+        for (rule, count) in &self.rules {
+            writeln!(f, "  {:<50} {:>9}", rule, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks lines of the dictionary written in Mozc format.
+#[derive(Debug)]
+pub struct Validator {
+    ids: HashSet<u16>,
+    entity: Regex,
+    seen: HashSet<String>,
+}
+
+impl Validator {
+    /// Creates validator which accepts ids defined in id.def.
+    pub fn new(ids: HashSet<u16>) -> Validator {
+        Validator {
+            ids,
+            // jawiki_article では &amp; と &quot; しかデコードしない
+            entity: Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[A-Za-z][A-Za-z0-9]*);").unwrap(),
+            seen: HashSet::new(),
+        }
+    }
+
+    fn valid_id(&self, id: &str) -> bool {
+        id.parse().is_ok_and(|id| self.ids.contains(&id))
+    }
+
+    /// Returns rules which the line violates.
+    pub fn check_line(&mut self, line: &str) -> Vec<&'static str> {
+        let fields = line.split('\t').collect::<Vec<&str>>();
+        let (reading, left_id, right_id, cost, surface) = if let [reading, left_id, right_id, cost, surface] = fields[..] {
+            (reading, left_id, right_id, cost, surface)
+        } else {
+            return vec!["line does not have five tab separated fields"];
+        };
+
+        let mut rules = Vec::new();
+        if reading.is_empty() || !reading.chars().all(is_reading_char) {
+            rules.push("reading is empty or not hiragana");
+        }
+        if !self.valid_id(left_id) || !self.valid_id(right_id) {
+            rules.push("id is not defined in id.def");
+        }
+        if !cost.parse::<i32>().is_ok_and(|cost| (0..=MAX_COST).contains(&cost)) {
+            rules.push("cost is out of range");
+        }
+        if surface.is_empty() || surface.chars().any(char::is_control) {
+            rules.push("surface is empty or contains control characters");
+        }
+        if self.entity.is_match(surface) {
+            rules.push("surface contains HTML entity");
+        }
+        if !self.seen.insert(String::from(line)) {
+            rules.push("duplicated line");
+        }
+        rules
+    }
+}
+
+/// Checks every line of the dictionary read from the reader.
+pub fn validate_from<R: BufRead>(reader: R, file: &str, ids: HashSet<u16>) -> Result<Violations> {
+    let mut validator = Validator::new(ids);
+    let mut violations = Violations::default();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        violations.lines += 1;
+        for rule in validator.check_line(&line) {
+            violations.add(file, index + 1, rule, &line);
+        }
+    }
+    Ok(violations)
+}
+
+/// Checks every line of the dictionary file.
+pub fn validate(filename: &str, ids: HashSet<u16>) -> Result<Violations> {
+    validate_from(BufReader::new(File::open(filename).with_path(filename)?), filename, ids).with_path(filename)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validator() -> Validator {
+        Validator::new(vec![1847, 1848].into_iter().collect())
+    }
+
+    #[test]
+    fn check_lines() {
+        let mut validator = validator();
+        assert!(validator.check_line("なかいまさひろ\t1847\t1847\t6477\t中居正広").is_empty());
+        assert!(validator.check_line("あーゞ\t1847\t1848\t0\tあーゞ").is_empty());
+
+        assert_eq!(validator.check_line("なかい\t1847\t9999\t6477\t中居"), ["id is not defined in id.def"]);
+        assert_eq!(validator.check_line("なかい\t1847\tx\t6477\t中居"), ["id is not defined in id.def"]);
+        assert_eq!(validator.check_line("なかい\t1847\t1847\t32768\t中居"), ["cost is out of range"]);
+        assert_eq!(validator.check_line("なかい\t1847\t1847\t-1\t中居"), ["cost is out of range"]);
+        assert_eq!(validator.check_line("えいてぃあんどてぃ\t1847\t1847\t6000\tAT&amp;T"), ["surface contains HTML entity"]);
+        assert_eq!(validator.check_line("ちるだ\t1847\t1847\t6000\t&#x7e;"), ["surface contains HTML entity"]);
+        assert_eq!(validator.check_line("ナカイ\t1847\t1847\t6000\t中居"), ["reading is empty or not hiragana"]);
+        assert_eq!(validator.check_line("なかい\t1847\t1847\t6000"), ["line does not have five tab separated fields"]);
+    }
+
+    #[test]
+    fn count_duplicated_lines() {
+        let text = "なかいまさひろ\t1847\t1847\t6477\t中居正広\n\
+                    なかいまさひろ\t1847\t1847\t6000\t中居正広\n\
+                    なかいまさひろ\t1847\t1847\t6477\t中居正広\n";
+        let violations = validate_from(text.as_bytes(), "test.txt", vec![1847].into_iter().collect()).unwrap();
+        assert_eq!(violations.lines, 3);
+        assert_eq!(violations.count(), 1);
+        assert_eq!(violations.rules.get("duplicated line"), Some(&1));
+    }
+}